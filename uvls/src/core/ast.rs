@@ -28,6 +28,15 @@ mod visitor;
 pub use def::*;
 pub use visitor::*;
 
+/// path components of a directory
+pub fn dir_to_path(dir: &std::path::Path) -> Vec<Ustr> {
+    dir.components()
+        .filter_map(|c| match c {
+            Component::Normal(os) => os.to_str().map(|s| s.into()),
+            _ => None,
+        })
+        .collect()
+}
 pub fn uri_to_path(uri: &Url) -> Option<Vec<Ustr>> {
     let mut p = uri.to_file_path().ok()?;
     p.set_extension("");
@@ -163,7 +172,7 @@ impl Ast {
             Symbol::Group(i) => Some(self.groups[i].span.clone()),
             Symbol::Constraint(i) => Some(self.constraints[i].span.clone()),
             Symbol::LangLvl(i) => Some(self.includes[i].span.clone()),
-            Symbol::Keyword(i) => Some(self.keywords[i].span.clone()),
+            Symbol::Keyword(i) => self.keywords.get(i).map(|k| k.span.clone()),
             _ => None,
         }
     }
@@ -228,30 +237,6 @@ impl Ast {
             .chain(self.all_references())
             .find(|s| self.span(*s).unwrap().contains(&offset))
     }
-    fn find_all_of(&self, current: Symbol, name: Ustr) -> Vec<Symbol> {
-        let mut relatives: Vec<Symbol> = vec![];
-        if let Symbol::Feature(x) = current {
-            if let Some(feature) = self.get_feature(x) {
-                if feature.name.name == name {
-                    relatives.push(Symbol::Feature(x));
-                    return relatives;
-                }
-            }
-        }
-        // Go through Children
-        match self.structure.children.get(&current) {
-            Some(list) => {
-                for sym in list {
-                    relatives.append(self.find_all_of(sym.to_owned(), name).as_mut())
-                }
-            }
-            None => {
-                // info!("Relative Symbol {:?} has no children", from);
-            }
-        }
-
-        return relatives;
-    }
 }
 /// Combines the AST with metadata, this is also a public interface to the AST.
 #[derive(Clone, Debug)]
@@ -261,6 +246,9 @@ pub struct AstDocument {
     pub tree: Tree,
     pub timestamp: Instant,
     pub errors: Vec<ErrorInfo>,
+    pub suppressions: Vec<Suppression>,
+    //errors of the transform without the name checks, reused by incremental updates
+    visit_errors: Vec<ErrorInfo>,
     pub path: Vec<Ustr>,
     pub uri: Url,
     pub id: FileID,
@@ -285,6 +273,27 @@ impl AstDocument {
         }
         Symbol::Root
     }
+    /// bounds of the feature cardinality if sym is a feature with instances
+    pub fn cardinality(&self, sym: Symbol) -> Option<(usize, Option<usize>)> {
+        match sym {
+            Symbol::Feature(i) => self.ast.features[i].cardinality.as_ref()?.bounds(),
+            _ => None,
+        }
+    }
+    /// Features with a cardinality between root (exclusive) and sym (inclusive),
+    /// outermost first. None if sym is not contained in root.
+    pub fn cardinality_path(&self, root: Symbol, sym: Symbol) -> Option<Vec<Symbol>> {
+        let mut path = Vec::new();
+        let mut cur = sym;
+        while cur != root {
+            if self.cardinality(cur).is_some() {
+                path.push(cur);
+            }
+            cur = self.parent(cur, false)?;
+        }
+        path.reverse();
+        Some(path)
+    }
     pub fn name(&self, sym: Symbol) -> Option<Ustr> {
         self.ast.name(sym)
     }
@@ -370,6 +379,22 @@ impl AstDocument {
     pub fn get_reference(&self, index: usize) -> Option<&Reference> {
         self.ast.references.get(index)
     }
+    /// Name resolution scope of a reference, this is the owning feature for references in
+    /// feature local constraints and Root otherwise
+    pub fn reference_scope(&self, sym: Symbol) -> Symbol {
+        match sym {
+            Symbol::Reference(i) => self.ast.references[i].scope,
+            _ => Symbol::Root,
+        }
+    }
+    /// All references used inside a constraint
+    pub fn constraint_references(&self, sym: Symbol) -> Vec<Symbol> {
+        let mut out = Vec::new();
+        if let Some(decl) = self.constraint(sym) {
+            decl.references(&mut out);
+        }
+        out
+    }
     pub fn lsp_range(&self, sym: Symbol) -> Option<tower_lsp::lsp_types::Range> {
         self.ast.lsp_range(sym, &self.source)
     }
@@ -380,6 +405,32 @@ impl AstDocument {
     pub fn namespace(&self) -> Option<&Path> {
         self.ast.namespace.as_ref()
     }
+    /// Hash of everything other files can observe when importing this document, two versions
+    /// with equal exports link the same way from the outside
+    pub fn exports(&self) -> u64 {
+        use std::hash::Hasher;
+        let mut state = std::collections::hash_map::DefaultHasher::new();
+        self.path.hash(&mut state);
+        self.namespace().map(|ns| &ns.names).hash(&mut state);
+        for i in self.ast.import.iter() {
+            i.path.names.hash(&mut state);
+            i.alias.as_ref().map(|a| a.name).hash(&mut state);
+        }
+        for (i, f) in self.ast.features.iter().enumerate() {
+            let sym = Symbol::Feature(i);
+            (sym, self.parent(sym, false), f.name.name, f.ty as u8).hash(&mut state);
+            f.cardinality
+                .as_ref()
+                .and_then(|c| c.bounds())
+                .hash(&mut state);
+        }
+        for (i, a) in self.ast.attributes.iter().enumerate() {
+            let sym = Symbol::Attribute(i);
+            (sym, self.parent(sym, false), a.name.name).hash(&mut state);
+            std::mem::discriminant(&a.value.value).hash(&mut state);
+        }
+        state.finish()
+    }
     pub fn path(&self, sym: Symbol) -> &[Ustr] {
         match sym {
             Symbol::Import(i) => &self.ast.import[i].path.names,
@@ -464,27 +515,6 @@ impl AstDocument {
             })
         })
     }
-    /// returns all Symbols with same name, used for cardinality resolving
-    pub fn get_all_entities(&self, path: &[Ustr]) -> Vec<Symbol> {
-        let ustr_path = Ustr::from(path.iter().map(|s| s.to_string()).join(".").as_str());
-        let mut res = vec![];
-        for i in 0..self.ast.features.len() {
-            if ustr_path == self.get_feature(i).unwrap().name.name {
-                res.push(Symbol::Feature(i));
-            }
-        }
-        let mut path: Vec<Ustr> = path.iter().cloned().collect();
-        if let Some(name) = path.pop() {
-            let features = self.get_all_entities(&path);
-            for i in features {
-                if let Some(sym) = self.ast.index.get(&(i, name, SymbolKind::Attribute)) {
-                    res.push(*sym);
-                }
-            }
-        }
-        res
-    }
-
     pub fn get_symbols(&self, path: Ustr) -> Vec<Symbol> {
         let mut res = vec![];
         for i in 0..self.ast.features.len() {
@@ -623,9 +653,6 @@ impl AstDocument {
     ) {
         self.visit_children_depth(root, merge_root_features, |sym, _| f(sym));
     }
-    pub fn find_all_of(&self, name: Ustr) -> Vec<Symbol> {
-        self.ast.find_all_of(Symbol::Root, name)
-    }
     /// Iterate all named symbole under root
     pub fn visit_children_depth<F: FnMut(Symbol, u32) -> bool>(
         &self,
@@ -678,6 +705,17 @@ impl AstDocument {
         });
     }
     pub fn new(source: Rope, tree: Tree, uri: Url, timestamp: Instant) -> Self {
-        transform::visit_root(source, tree, uri, timestamp)
+        transform::visit_root(source, tree, uri, timestamp, None)
+    }
+    /// Create a new version of old, unchanged features are copied instead of transformed again
+    pub fn update(
+        source: Rope,
+        tree: Tree,
+        uri: Url,
+        timestamp: Instant,
+        old: &AstDocument,
+        edits: &EditLog,
+    ) -> Self {
+        transform::visit_root(source, tree, uri, timestamp, Some((old, edits)))
     }
 }
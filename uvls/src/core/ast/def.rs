@@ -74,8 +74,20 @@ pub enum GroupMode {
 #[derive(Clone, Debug)]
 pub enum Cardinality {
     Range(usize, usize),
+    /// [n..*]
+    AtLeast(usize),
     Fixed,
 }
+impl Cardinality {
+    /// Lower and upper bound, None if the cardinality is not set
+    pub fn bounds(&self) -> Option<(usize, Option<usize>)> {
+        match self {
+            Self::Range(min, max) => Some((*min, Some(*max))),
+            Self::AtLeast(min) => Some((*min, None)),
+            Self::Fixed => None,
+        }
+    }
+}
 #[derive(Clone, Debug)]
 pub enum LanguageLevelMajor {
     Boolean,
@@ -116,8 +128,6 @@ pub struct Feature {
     pub name: SymbolSpan,
     pub cardinality: Option<Cardinality>,
     pub ty: Type,
-    pub duplicate: bool,
-    pub first_cardinality_child: bool, // used to fix same name problem
 }
 #[derive(Clone, Debug)]
 pub struct Import {
@@ -136,13 +146,14 @@ pub struct Group {
 #[derive(Clone, Debug)]
 pub struct Reference {
     pub path: Path,
+    /// Feature owning the constraint this reference is part of, Root for global references
+    pub scope: Symbol,
 }
 #[derive(Clone, Debug)]
 pub struct Attribute {
     pub name: SymbolSpan,
     pub value: ValueDecl,
     pub depth: u32,
-    pub duplicate: bool,
 }
 #[derive(Clone, Debug)]
 pub struct Keyword {
@@ -245,6 +256,24 @@ pub struct ConstraintDecl {
     pub content: Constraint,
     pub span: Span,
 }
+impl ConstraintDecl {
+    /// collect all references used in this constraint
+    pub fn references(&self, out: &mut Vec<Symbol>) {
+        match &self.content {
+            Constraint::Constant(..) => {}
+            Constraint::Ref(sym) => out.push(*sym),
+            Constraint::Not(lhs) => lhs.references(out),
+            Constraint::Logic { lhs, rhs, .. } => {
+                lhs.references(out);
+                rhs.references(out);
+            }
+            Constraint::Equation { lhs, rhs, .. } => {
+                lhs.references(out);
+                rhs.references(out);
+            }
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum Expr {
@@ -272,6 +301,22 @@ pub struct ExprDecl {
     pub content: Expr,
     pub span: Span,
 }
+impl ExprDecl {
+    /// collect all references used in this expression
+    pub fn references(&self, out: &mut Vec<Symbol>) {
+        match &self.content {
+            Expr::Number(..) | Expr::String(..) => {}
+            Expr::Ref(sym) => out.push(*sym),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.references(out);
+                rhs.references(out);
+            }
+            Expr::Aggregate { context, .. } => out.extend(context.iter().cloned()),
+            Expr::Integer { n, .. } => n.references(out),
+            Expr::Len(lhs) => lhs.references(out),
+        }
+    }
+}
 /// A symbol represents an entity in some uvl document
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, enum_kinds::EnumKind)]
 #[enum_kind(SymbolKind, derive(Hash))]
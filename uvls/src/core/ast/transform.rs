@@ -1,11 +1,15 @@
 //! Transform a tree-sitter tree into the Ast ECS via recursive decent
 //! While parsing we keep a mutable state to store entities and errors
+//!
+//! After an edit only the changed part of the tree is visited, feature subtrees outside the
+//! changed bytes are copied from the previous version of the document.
 
 use crate::core::*;
 use ast::visitor::Visitor;
 use ast::visitor::*;
 use ast::Ast;
 use check::ErrorInfo;
+use hashbrown::HashMap;
 use log::info;
 use parse::*;
 use ropey::Rope;
@@ -17,12 +21,29 @@ use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
 use tree_sitter::{Node, Tree, TreeCursor};
 use util::node_range;
 
+/// Previous version of a document and the bytes changed since
+#[derive(Clone)]
+struct Reuse<'a> {
+    old: &'a AstDocument,
+    //changed bytes in the new source, everything else is the same as in old
+    dirty: Span,
+    //last line touched by the change in the new source
+    dirty_line: usize,
+    //shift of bytes and lines behind the change
+    delta: isize,
+    line_delta: i64,
+    //old features by the start of their name
+    features: HashMap<usize, Symbol>,
+}
 #[derive(Clone)]
 struct VisitorState<'a> {
     errors: Vec<ErrorInfo>,
     cursor: TreeCursor<'a>,
     ast: Ast,
     source: &'a Rope,
+    reuse: Option<Reuse<'a>>,
+    //number of features copied from the previous version
+    reused: usize,
 }
 impl<'a> Visitor<'a> for VisitorState<'a> {
     fn cursor(&self) -> &TreeCursor<'a> {
@@ -40,19 +61,36 @@ impl<'a> Visitor<'a> for VisitorState<'a> {
 }
 impl<'a> VisitorState<'a> {
     fn add_constraint(&mut self, constraint: ConstraintDecl, scope: Symbol) -> Symbol {
+        //feature local constraints resolve their references relative to the owning feature
+        let mut owner = scope;
+        while let (Symbol::Attribute(..), Some(p)) = (owner, self.ast.structure.parent.get(&owner))
+        {
+            owner = *p;
+        }
+        let mut refs = Vec::new();
+        constraint.references(&mut refs);
+        for i in refs {
+            self.ast.references[i.offset()].scope = owner;
+        }
         self.ast.constraints.push(constraint);
         let sym = Symbol::Constraint(self.ast.constraints.len() - 1);
         self.push_child(scope, sym);
         sym
     }
     fn add_ref(&mut self, path: Path, scope: Symbol) -> Symbol {
-        self.ast.references.push(Reference { path });
+        self.ast.references.push(Reference {
+            path,
+            scope: Symbol::Root,
+        });
         let sym = Symbol::Reference(self.ast.references.len() - 1);
         self.push_child(scope, sym);
         sym
     }
     fn add_ref_direct(&mut self, path: Path) -> Symbol {
-        self.ast.references.push(Reference { path });
+        self.ast.references.push(Reference {
+            path,
+            scope: Symbol::Root,
+        });
 
         Symbol::Reference(self.ast.references.len() - 1)
     }
@@ -94,6 +132,7 @@ impl<'a> VisitorState<'a> {
                         self.ast.lsp_range(old, self.source).unwrap().start.line
                     ),
                     error_type: ErrorType::Any,
+                    rule: Rule::DuplicateName,
                 });
             }
         }
@@ -102,29 +141,28 @@ impl<'a> VisitorState<'a> {
         while let Some((node, scope, depth)) = stack.pop() {
             let new_scope = if let Some(name) = self.ast.name(node) {
                 match node {
-                    Symbol::Feature(i) => {
-                        // removes duplicate feature error for cardinality
-                        if !self.ast.get_feature(i).unwrap().duplicate {
-                            if let Some(old) = self
-                                .ast
-                                .index
-                                .insert((Symbol::Root, name, SymbolKind::Feature), node)
-                            {
-                                self.errors.push(ErrorInfo {
-                                    location: self.ast.lsp_range(node, self.source).unwrap(),
-                                    severity: DiagnosticSeverity::ERROR,
-                                    weight: 20,
-                                    msg: "duplicate feature".to_string(),
-                                    error_type: ErrorType::Any,
-                                });
-                                self.errors.push(ErrorInfo {
-                                    location: self.ast.lsp_range(old, self.source).unwrap(),
-                                    severity: DiagnosticSeverity::ERROR,
-                                    weight: 20,
-                                    msg: "duplicate feature".to_string(),
-                                    error_type: ErrorType::Any,
-                                })
-                            }
+                    Symbol::Feature(..) => {
+                        if let Some(old) = self
+                            .ast
+                            .index
+                            .insert((Symbol::Root, name, SymbolKind::Feature), node)
+                        {
+                            self.errors.push(ErrorInfo {
+                                location: self.ast.lsp_range(node, self.source).unwrap(),
+                                severity: DiagnosticSeverity::ERROR,
+                                weight: 20,
+                                msg: "duplicate feature".to_string(),
+                                error_type: ErrorType::Any,
+                                rule: Rule::DuplicateName,
+                            });
+                            self.errors.push(ErrorInfo {
+                                location: self.ast.lsp_range(old, self.source).unwrap(),
+                                severity: DiagnosticSeverity::ERROR,
+                                weight: 20,
+                                msg: "duplicate feature".to_string(),
+                                error_type: ErrorType::Any,
+                                rule: Rule::DuplicateName,
+                            })
                         }
                         node
                     }
@@ -140,6 +178,7 @@ impl<'a> VisitorState<'a> {
                                 weight: 20,
                                 msg: "duplicate attribute".to_string(),
                                 error_type: ErrorType::Any,
+                                rule: Rule::DuplicateName,
                             });
                             self.errors.push(ErrorInfo {
                                 location: self.ast.lsp_range(old, self.source).unwrap(),
@@ -147,6 +186,7 @@ impl<'a> VisitorState<'a> {
                                 weight: 20,
                                 msg: "duplicate attribute".to_string(),
                                 error_type: ErrorType::Any,
+                                rule: Rule::DuplicateName,
                             });
                         }
                         self.ast.attributes[i].depth = depth + 1;
@@ -181,6 +221,7 @@ impl<'a> VisitorState<'a> {
                         weight: 20,
                         msg: "name already defined as import directory".to_string(),
                         error_type: ErrorType::Any,
+                        rule: Rule::DuplicateName,
                     });
                 }
                 if self
@@ -195,6 +236,7 @@ impl<'a> VisitorState<'a> {
                         weight: 20,
                         msg: "name already defined as import".to_string(),
                         error_type: ErrorType::Any,
+                        rule: Rule::DuplicateName,
                     });
                 }
             }
@@ -216,6 +258,7 @@ impl<'a> VisitorState<'a> {
             weight: w,
             msg: error.into(),
             error_type: ErrorType::Any,
+            rule: Rule::Syntax,
         });
     }
     //Push an error with location of the current block header
@@ -231,6 +274,7 @@ impl<'a> VisitorState<'a> {
             weight: w,
             msg: error.into(),
             error_type,
+            rule: error_type.rule(Rule::Syntax),
         });
     }
 }
@@ -526,15 +570,21 @@ fn opt_cardinality(node: Node, state: &mut VisitorState) -> Option<Cardinality>
     let begin = node.child_by_field_name("begin");
     let end = node.child_by_field_name("end");
     match (begin, end.map(|n| n.kind())) {
-        (Some(begin), Some("int")) => Some(Cardinality::Range(
-            opt_int(begin, state)?,
-            opt_int(end.unwrap(), state)?,
-        )),
+        (Some(begin), Some("int")) => {
+            let min = opt_int(begin, state)?;
+            let max = opt_int(end.unwrap(), state)?;
+            if min > max {
+                state.push_error_node(node, 30, "lower bound exceeds upper bound");
+                return None;
+            }
+            Some(Cardinality::Range(min, max))
+        }
         (None, Some("int")) => Some(Cardinality::Range(
             opt_int(end.unwrap(), state)?,
             opt_int(end.unwrap(), state)?,
         )),
-        (_, _) => Some(Cardinality::Range(0, 1)),
+        (Some(begin), _) => Some(Cardinality::AtLeast(opt_int(begin, state)?)),
+        (None, _) => Some(Cardinality::AtLeast(0)),
     }
 }
 
@@ -941,7 +991,7 @@ fn opt_constraint(state: &mut VisitorState) -> Option<ConstraintDecl> {
     }
     .map(|content| ConstraintDecl { span, content })
 }
-fn visit_constraint(state: &mut VisitorState, parent: Symbol, _duplicate: &bool) {
+fn visit_constraint(state: &mut VisitorState, parent: Symbol) {
     if let Some(cons) = opt_constraint(state) {
         state.add_constraint(cons, parent);
     }
@@ -998,7 +1048,7 @@ fn opt_value(state: &mut VisitorState) -> Value {
     }
 }
 
-fn visit_attribute_value(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
+fn visit_attribute_value(state: &mut VisitorState, parent: Symbol) {
     state.goto_field("name");
     let name = opt_name(state).unwrap();
     let sym = Symbol::Attribute(state.ast.attributes.len());
@@ -1013,39 +1063,40 @@ fn visit_attribute_value(state: &mut VisitorState, parent: Symbol, duplicate: &b
             span: state.node().byte_range(),
         },
         depth: 0,
-        duplicate: duplicate.clone(),
     });
     if has_children {
-        visit_children_arg(state, sym, &duplicate, visit_attributes);
+        visit_children_arg(state, sym, visit_attributes);
     }
 }
-fn visit_constraint_list(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
+fn visit_constraint_list(state: &mut VisitorState, parent: Symbol) {
     loop {
         if state.kind() == "constraint" {
-            visit_children_arg(state, parent, duplicate, visit_constraint);
+            visit_children_arg(state, parent, visit_constraint);
         }
         if !state.goto_next_sibling() {
             break;
         }
     }
 }
-fn visit_attributes(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
+fn visit_attributes(state: &mut VisitorState, parent: Symbol) {
     loop {
         match state.kind() {
             "attribute_constraints" => {
                 if state.child_by_name("tail").is_some() {
                     state.push_error(10, "tailing comma unsupported");
                 }
-                visit_children_arg(state, parent, duplicate, visit_constraint_list);
+                visit_children_arg(state, parent, visit_constraint_list);
             }
             "attribute_constraint" => {
                 visit_children(state, |state| {
-                    debug_assert!(state.goto_kind("constraint"));
-                    visit_children_arg(state, parent, duplicate, visit_constraint);
+                    //the keyword has the same kind as the constraint node
+                    if state.goto_named() && state.kind() == "constraint" {
+                        visit_children_arg(state, parent, visit_constraint);
+                    }
                 });
             }
             "attribute_value" => {
-                visit_children_arg(state, parent, duplicate, visit_attribute_value);
+                visit_children_arg(state, parent, visit_attribute_value);
             }
             _ => {}
         }
@@ -1055,86 +1106,44 @@ fn visit_attributes(state: &mut VisitorState, parent: Symbol, duplicate: &bool)
     }
 }
 
-fn visit_feature(
-    state: &mut VisitorState,
-    parent: Symbol,
-    name: SymbolSpan,
-    ty: Type,
-    duplicate: bool,
-) {
+fn visit_feature(state: &mut VisitorState, parent: Symbol, name: SymbolSpan, ty: Type) {
     match parent {
         Symbol::Feature(..) => {
             state.push_error(40, "features have to be separated by groups");
         }
         _ => {}
     }
-    let feature = Feature {
+    let sym = Symbol::Feature(state.ast.features.len());
+    // A feature with a feature cardinality is stored once and acts as template for its
+    // instances, those are created by the module of a configuration
+    let cardinality = state
+        .node()
+        .parent()
+        .unwrap()
+        .child_by_field_name("cardinality")
+        .and_then(|n| {
+            check_langlvls(
+                state,
+                LanguageLevel::Arithmetic(vec![LanguageLevelArithmetic::FeatureCardinality]),
+            );
+            opt_cardinality(n, state)
+        })
+        .or(Some(Cardinality::Fixed));
+    state.ast.features.push(Feature {
         name,
         ty,
-        cardinality: state
-            .node()
-            .parent()
-            .unwrap()
-            .child_by_field_name("cardinality")
-            .and_then(|n| {
-                check_langlvls(
-                    state,
-                    LanguageLevel::Arithmetic(vec![LanguageLevelArithmetic::FeatureCardinality]),
-                );
-                opt_cardinality(n, state)
-            })
-            .or_else(|| Some(Cardinality::Fixed)),
-        duplicate: duplicate.clone(),
-        first_cardinality_child: true,
-    };
-
-    // remaps feature to different entities of the cardinality
-    let mut sym = vec![];
-
-    match feature.clone().cardinality.unwrap() {
-        Cardinality::Fixed => {
-            sym.push(Symbol::Feature(state.ast.features.len()));
-            state.ast.features.push(feature.clone());
-            state.push_child(parent, sym.get(0).unwrap().clone());
-        }
-        Cardinality::Range(_, max) => {
-            for i in 0..max {
-                let mut dup_feature = feature.clone();
-                sym.push(Symbol::Feature(state.ast.features.len()));
-                if i != 0 {
-                    dup_feature.duplicate = true;
-                    dup_feature.first_cardinality_child = false;
-                }
-                state.ast.features.push(dup_feature);
-                state.push_child(parent, sym.get(i).unwrap().clone());
-            }
-        }
-    }
+        cardinality,
+    });
+    state.push_child(parent, sym);
     loop {
-        for index in 0..sym.len() {
-            let mut duplicate_par = false;
-            if let Symbol::Feature(id) = sym.get(index).unwrap() {
-                duplicate_par = state.ast.get_feature(*id).unwrap().duplicate;
+        match state.kind() {
+            "attributes" => {
+                visit_children_arg(state, sym, visit_attributes);
             }
-            match state.kind() {
-                "attributes" => {
-                    visit_children_arg(
-                        state,
-                        sym.get(index).unwrap().clone(),
-                        &duplicate_par,
-                        visit_attributes,
-                    );
-                }
-                "blk" => {
-                    visit_children_arg(
-                        state,
-                        sym.get(index).unwrap().clone(),
-                        &&duplicate_par,
-                        visit_blk_decl,
-                    );
-                }
-                _ => {}
+            "blk" => {
+                visit_children_arg(state, sym, visit_blk_decl);
             }
+            _ => {}
         }
         if !state.goto_next_sibling() {
             break;
@@ -1157,7 +1166,7 @@ fn visit_ref(state: &mut VisitorState, parent: Symbol, path: Path) {
         }
     }
 }
-fn visit_group(state: &mut VisitorState, parent: Symbol, mode: GroupMode, duplicate: &bool) {
+fn visit_group(state: &mut VisitorState, parent: Symbol, mode: GroupMode) {
     match parent {
         Symbol::Group(..) => {
             state.push_error(40, "groups have to be separated by features");
@@ -1176,19 +1185,233 @@ fn visit_group(state: &mut VisitorState, parent: Symbol, mode: GroupMode, duplic
     loop {
         check_no_extra_blk(state, "group");
         if state.kind() == "blk" {
-            visit_children_arg(state, sym, duplicate, visit_blk_decl);
+            visit_children_arg(state, sym, visit_blk_decl);
         }
         if !state.goto_next_sibling() {
             break;
         }
     }
 }
-fn visit_blk_decl(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
+fn overlaps(a: &Span, b: &Span) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+fn shift_span(span: &Span, shift: isize) -> Span {
+    (span.start as isize + shift) as usize..(span.end as isize + shift) as usize
+}
+fn shift_path(path: &Path, shift: isize) -> Path {
+    Path {
+        names: path.names.clone(),
+        spans: path.spans.iter().map(|s| shift_span(s, shift)).collect(),
+    }
+}
+/// Copy the reference old into the new ast
+fn copy_reference(
+    state: &mut VisitorState,
+    old: &AstDocument,
+    sym: Symbol,
+    shift: isize,
+    map: &HashMap<Symbol, Symbol>,
+) -> Symbol {
+    let reference = &old.ast.references[sym.offset()];
+    state.ast.references.push(Reference {
+        path: shift_path(&reference.path, shift),
+        scope: map.get(&reference.scope).cloned().unwrap_or(Symbol::Root),
+    });
+    Symbol::Reference(state.ast.references.len() - 1)
+}
+fn copy_expr(
+    state: &mut VisitorState,
+    old: &AstDocument,
+    expr: &ExprDecl,
+    shift: isize,
+    map: &HashMap<Symbol, Symbol>,
+) -> ExprDecl {
+    let copy =
+        |state: &mut VisitorState, e: &ExprDecl| Box::new(copy_expr(state, old, e, shift, map));
+    ExprDecl {
+        span: shift_span(&expr.span, shift),
+        content: match &expr.content {
+            Expr::Number(n) => Expr::Number(*n),
+            Expr::String(s) => Expr::String(s.clone()),
+            Expr::Ref(sym) => Expr::Ref(copy_reference(state, old, *sym, shift, map)),
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = copy(state, lhs);
+                Expr::Binary {
+                    op: op.clone(),
+                    lhs,
+                    rhs: copy(state, rhs),
+                }
+            }
+            Expr::Aggregate { op, context, query } => Expr::Aggregate {
+                op: op.clone(),
+                context: context.map(|sym| copy_reference(state, old, sym, shift, map)),
+                query: shift_path(query, shift),
+            },
+            Expr::Integer { op, n } => Expr::Integer {
+                op: op.clone(),
+                n: copy(state, n),
+            },
+            Expr::Len(n) => Expr::Len(copy(state, n)),
+        },
+    }
+}
+fn copy_constraint(
+    state: &mut VisitorState,
+    old: &AstDocument,
+    decl: &ConstraintDecl,
+    shift: isize,
+    map: &HashMap<Symbol, Symbol>,
+) -> ConstraintDecl {
+    let copy = |state: &mut VisitorState, c: &ConstraintDecl| {
+        Box::new(copy_constraint(state, old, c, shift, map))
+    };
+    ConstraintDecl {
+        span: shift_span(&decl.span, shift),
+        content: match &decl.content {
+            Constraint::Constant(b) => Constraint::Constant(*b),
+            Constraint::Ref(sym) => Constraint::Ref(copy_reference(state, old, *sym, shift, map)),
+            Constraint::Not(lhs) => Constraint::Not(copy(state, lhs)),
+            Constraint::Logic { op, lhs, rhs } => {
+                let lhs = copy(state, lhs);
+                Constraint::Logic {
+                    op: op.clone(),
+                    lhs,
+                    rhs: copy(state, rhs),
+                }
+            }
+            Constraint::Equation { op, lhs, rhs } => {
+                let lhs = Box::new(copy_expr(state, old, lhs, shift, map));
+                Constraint::Equation {
+                    op: op.clone(),
+                    lhs,
+                    rhs: Box::new(copy_expr(state, old, rhs, shift, map)),
+                }
+            }
+        },
+    }
+}
+/// Copy sym with all its children from old under parent, symbols are renumbered in the same
+/// order a visit would create them
+fn copy_subtree(
+    state: &mut VisitorState,
+    old: &AstDocument,
+    sym: Symbol,
+    parent: Symbol,
+    shift: isize,
+    map: &mut HashMap<Symbol, Symbol>,
+) {
+    let new = match sym {
+        Symbol::Feature(i) => {
+            let mut feature = old.ast.features[i].clone();
+            feature.name.span = shift_span(&feature.name.span, shift);
+            state.ast.features.push(feature);
+            state.reused += 1;
+            Symbol::Feature(state.ast.features.len() - 1)
+        }
+        Symbol::Attribute(i) => {
+            let mut attribute = old.ast.attributes[i].clone();
+            attribute.name.span = shift_span(&attribute.name.span, shift);
+            attribute.value.span = shift_span(&attribute.value.span, shift);
+            state.ast.attributes.push(attribute);
+            Symbol::Attribute(state.ast.attributes.len() - 1)
+        }
+        Symbol::Group(i) => {
+            let mut group = old.ast.groups[i].clone();
+            group.span = shift_span(&group.span, shift);
+            state.ast.groups.push(group);
+            Symbol::Group(state.ast.groups.len() - 1)
+        }
+        Symbol::Reference(..) => copy_reference(state, old, sym, shift, map),
+        Symbol::Constraint(i) => {
+            let constraint = copy_constraint(state, old, &old.ast.constraints[i], shift, map);
+            state.ast.constraints.push(constraint);
+            Symbol::Constraint(state.ast.constraints.len() - 1)
+        }
+        _ => return,
+    };
+    map.insert(sym, new);
+    state.push_child(parent, new);
+    for child in old.ast.children(sym) {
+        copy_subtree(state, old, child, new, shift, map);
+    }
+}
+/// Copy the feature declared by the current block from the previous version of the document
+/// if neither its source nor anything it depends on changed
+fn reuse_feature(state: &mut VisitorState, parent: Symbol) -> bool {
+    let Some(reuse) = state.reuse.take() else {
+        return false;
+    };
+    let ok = try_reuse_feature(state, &reuse, parent).is_some();
+    state.reuse = Some(reuse);
+    ok
+}
+fn try_reuse_feature(state: &mut VisitorState, reuse: &Reuse, parent: Symbol) -> Option<()> {
+    let blk = state.node().parent()?;
+    let span = blk.byte_range();
+    let (shift, line_shift) = if span.end < reuse.dirty.start {
+        (0, 0)
+    } else if span.start > reuse.dirty.end
+        && state.source.byte_to_line(span.start) > reuse.dirty_line
+    {
+        (reuse.delta, reuse.line_delta)
+    } else {
+        return None;
+    };
+    //language level errors depend on the includes
+    if state
+        .ast
+        .includes
+        .iter()
+        .any(|i| overlaps(&i.span, &reuse.dirty))
+    {
+        return None;
+    }
+    let old = reuse.old;
+    let header = state.node();
+    let name = header.child_by_field_name("name").unwrap_or(header);
+    let old_name = shift_span(&name.byte_range(), -shift);
+    let sym = *reuse.features.get(&old_name.start)?;
+    //errors for misplaced features depend on the parent
+    let old_parent = old.parent(sym, false).unwrap_or(Symbol::Root);
+    if SymbolKind::from(old_parent) != SymbolKind::from(parent) {
+        return None;
+    }
+    //the old block has to cover the same bytes
+    let mut old_blk = old
+        .tree
+        .root_node()
+        .descendant_for_byte_range(old_name.start, old_name.end)?;
+    while old_blk.kind() != "blk" {
+        old_blk = old_blk.parent()?;
+    }
+    let old_span = shift_span(&span, -shift);
+    if old_blk.byte_range() != old_span || old_blk.child_count() != blk.child_count() {
+        return None;
+    }
+    let old_range = lsp_range(old_span, &old.source)?;
+    state.errors.extend(
+        old.visit_errors
+            .iter()
+            .filter(|e| old_range.start <= e.location.start && e.location.end <= old_range.end)
+            .map(|e| {
+                let mut e = e.clone();
+                e.location.start.line = (e.location.start.line as i64 + line_shift) as u32;
+                e.location.end.line = (e.location.end.line as i64 + line_shift) as u32;
+                e
+            }),
+    );
+    copy_subtree(state, old, sym, parent, shift, &mut HashMap::new());
+    Some(())
+}
+fn visit_blk_decl(state: &mut VisitorState, parent: Symbol) {
     state.goto_field("header");
+    if matches!(state.kind(), "name" | "typed_feature") && reuse_feature(state, parent) {
+        return;
+    }
     match state.kind() {
         "name" => {
             let name = opt_name(state).unwrap();
-            visit_feature(state, parent, name, Type::Bool, *duplicate);
+            visit_feature(state, parent, name, Type::Bool);
         }
         "typed_feature" => {
             check_langlvls(state, LanguageLevel::Type(vec![]));
@@ -1207,7 +1430,7 @@ fn visit_blk_decl(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
                 Some((opt_name(state).unwrap(), ty))
             })
             .unwrap();
-            visit_feature(state, parent, name, ty, *duplicate);
+            visit_feature(state, parent, name, ty);
         }
         "ref" => {
             let path = visit_children(state, |state| {
@@ -1229,7 +1452,7 @@ fn visit_blk_decl(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
                 "alternative" => GroupMode::Alternative,
                 _ => GroupMode::Mandatory,
             };
-            visit_group(state, parent, mode, duplicate);
+            visit_group(state, parent, mode);
         }
         "cardinality" => {
             check_langlvls(
@@ -1237,7 +1460,7 @@ fn visit_blk_decl(state: &mut VisitorState, parent: Symbol, duplicate: &bool) {
                 LanguageLevel::Boolean(vec![LanguageLevelBoolean::GroupCardinality]),
             );
             let card = opt_cardinality(state.node(), state).unwrap_or(Cardinality::Fixed);
-            visit_group(state, parent, GroupMode::Cardinality(card), duplicate);
+            visit_group(state, parent, GroupMode::Cardinality(card));
         }
         _ => {
             if state.kind() == "constraint" && state.name(state.cursor().node()).contains("-") {
@@ -1262,7 +1485,7 @@ fn visit_features(state: &mut VisitorState) {
     loop {
         check_no_extra_blk(state, "features");
         if state.kind() == "blk" {
-            visit_children_arg(state, Symbol::Root, &false, visit_blk_decl);
+            visit_children_arg(state, Symbol::Root, visit_blk_decl);
         }
         if !state.goto_next_sibling() {
             break;
@@ -1273,10 +1496,8 @@ fn visit_constraint_decl(state: &mut VisitorState) {
     loop {
         check_simple_blk(state, "constraints");
         match state.kind() {
-            "constraint" | "ref" => {
-                visit_children_arg(state, Symbol::Root, &false, visit_constraint)
-            }
-            "name" => visit_constraint(state, Symbol::Root, &false),
+            "constraint" | "ref" => visit_children_arg(state, Symbol::Root, visit_constraint),
+            "name" => visit_constraint(state, Symbol::Root),
             _ => {}
         }
         if state.kind() == "ref" {
@@ -1370,19 +1591,70 @@ fn visit_top_lvl(state: &mut VisitorState) {
     }
 }
 /// visits all valid children of a tree-sitter (green tree) recursively to translate them into the
-/// AST(red tree)
-pub fn visit_root(source: Rope, tree: Tree, uri: Url, timestamp: Instant) -> AstDocument {
-    let (ast, errors) = {
+/// AST(red tree), unchanged features are copied from old if present
+pub fn visit_root(
+    source: Rope,
+    tree: Tree,
+    uri: Url,
+    timestamp: Instant,
+    old: Option<(&AstDocument, &EditLog)>,
+) -> AstDocument {
+    let time = std::time::Instant::now();
+    let reuse = old.map(|(old, edits)| {
+        let dirty = changed_span(
+            &old.tree,
+            edits.since(old.timestamp),
+            &tree,
+            source.len_bytes(),
+        );
+        let old_dirty = shift_span(
+            &dirty,
+            old.source.len_bytes() as isize - source.len_bytes() as isize,
+        );
+        Reuse {
+            dirty_line: source.byte_to_line(dirty.end),
+            delta: source.len_bytes() as isize - old.source.len_bytes() as isize,
+            line_delta: source.len_lines() as i64 - old.source.len_lines() as i64,
+            //language levels of all features depend on removed includes
+            features: if old
+                .ast
+                .includes
+                .iter()
+                .any(|i| overlaps(&i.span, &old_dirty))
+            {
+                HashMap::new()
+            } else {
+                old.all_features()
+                    .map(|f| (old.ast.features[f.offset()].name.span.start, f))
+                    .collect()
+            },
+            dirty,
+            old,
+        }
+    });
+    let (ast, errors, visit_errors, reused) = {
         let mut state = VisitorState {
             errors: Vec::new(),
             cursor: tree.walk(),
             ast: Default::default(),
             source: &source,
+            reuse,
+            reused: 0,
         };
         visit_children(&mut state, visit_top_lvl);
+        let visit_errors = state.errors.clone();
         state.connect();
-        (state.ast, state.errors)
+        (state.ast, state.errors, visit_errors, state.reused)
     };
+    if old.is_some() {
+        info!(
+            "transformed {uri} incrementally in {:?}, reused {reused} of {} features",
+            time.elapsed(),
+            ast.features.len()
+        );
+    } else {
+        info!("transformed {uri} in {:?}", time.elapsed());
+    }
     let mut path = uri_to_path(&uri).unwrap();
     if let Some(ns) = ast.namespace.as_ref() {
         let len = path.len().saturating_sub(ns.names.len());
@@ -1390,6 +1662,7 @@ pub fn visit_root(source: Rope, tree: Tree, uri: Url, timestamp: Instant) -> Ast
         path.extend_from_slice(&ns.names);
     }
     AstDocument {
+        suppressions: suppressions(&source),
         id: FileID::from_uri(&uri),
         path,
         uri,
@@ -1398,5 +1671,165 @@ pub fn visit_root(source: Rope, tree: Tree, uri: Url, timestamp: Instant) -> Ast
         tree,
         timestamp,
         errors,
+        visit_errors,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{
+        DidChangeTextDocumentParams, TextDocumentContentChangeEvent,
+        VersionedTextDocumentIdentifier,
+    };
+    use tree_sitter::InputEdit;
+    use ustr::Ustr;
+    fn document(source: &str) -> AstDocument {
+        let source = Rope::from_str(source);
+        let tree = parse::parse(&source, None);
+        let uri = Url::parse("file:///test.uvl").unwrap();
+        visit_root(source, tree, uri, Instant::now(), None)
+    }
+    /// Replace each (from, to) in old like an editor would and transform incrementally
+    fn edit(old: &AstDocument, changes: &[(&str, &str)], edits: &mut EditLog) -> AstDocument {
+        let mut source = old.source.clone();
+        let mut tree = old.tree.clone();
+        let mut applied = Vec::new();
+        for (from, to) in changes {
+            let start = source.to_string().find(from).unwrap();
+            let params = DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: old.uri.clone(),
+                    version: 0,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: lsp_range(start..start + from.len(), &source),
+                    range_length: None,
+                    text: to.to_string(),
+                }],
+            };
+            applied.extend(update_text(&mut source, Some(&mut tree), params).unwrap());
+        }
+        let timestamp = Instant::now();
+        edits.record(timestamp, Some(applied));
+        let tree = parse::parse(&source, Some(&tree));
+        visit_root(source, tree, old.uri.clone(), timestamp, Some((old, edits)))
+    }
+    fn summary(doc: &AstDocument) -> Vec<(Ustr, Option<Symbol>, Option<Span>)> {
+        doc.all_features()
+            .chain(doc.all_attributes())
+            .chain(doc.all_references())
+            .map(|sym| {
+                (
+                    doc.name(sym).unwrap_or_default(),
+                    doc.parent(sym, false),
+                    doc.span(sym),
+                )
+            })
+            .collect()
+    }
+    #[test]
+    fn test_incremental_transform() {
+        let old = document(
+            "features\n    A\n        optional\n            B {x 1}\n            C\n            D {y 'a'}\n                mandatory\n                    E\nconstraints\n    B => E\n",
+        );
+        let mut edits = EditLog::new(old.timestamp);
+        let new = edit(
+            &old,
+            &[("C\n", "Cc\n                or\n                    F\n")],
+            &mut edits,
+        );
+        let full = document(&new.source.to_string());
+        assert_eq!(
+            new.source.to_string(),
+            "features\n    A\n        optional\n            B {x 1}\n            Cc\n                or\n                    F\n            D {y 'a'}\n                mandatory\n                    E\nconstraints\n    B => E\n"
+        );
+        assert_eq!(summary(&new), summary(&full));
+        let ranges = |doc: &AstDocument| {
+            doc.errors
+                .iter()
+                .map(|e| (e.location, e.msg.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(&new), ranges(&full));
+        //edits of several versions are combined
+        let newer = edit(
+            &new,
+            &[("B {x 1}", "B {x 2}"), ("E\n", "E\n    F\n")],
+            &mut edits,
+        );
+        assert_eq!(
+            summary(&newer),
+            summary(&document(&newer.source.to_string()))
+        );
+    }
+    #[test]
+    fn test_changed_span() {
+        let old = document("features\n    A\n        optional\n            B\n            C\n");
+        let mut edits = EditLog::new(old.timestamp);
+        let mid = edit(&old, &[("B", "Bb")], &mut edits);
+        let new = edit(&mid, &[("C", "Cc")], &mut edits);
+        let len = new.source.len_bytes();
+        let span = |since: Instant| changed_span(&old.tree, edits.since(since), &new.tree, len);
+        let source = new.source.to_string();
+        let (b, c) = (source.find("Bb").unwrap(), source.find("Cc").unwrap());
+        //both edits are dirty when starting from the first version
+        let dirty = span(old.timestamp);
+        assert!(dirty.start <= b && dirty.end >= c + 2, "{dirty:?}");
+        //the first edit is already known to the second version
+        let dirty = changed_span(&mid.tree, edits.since(mid.timestamp), &new.tree, len);
+        assert!(dirty.start > b + 2 && dirty.end >= c + 2, "{dirty:?}");
+        //unknown edits mark the whole document
+        assert_eq!(
+            changed_span(
+                &old.tree,
+                None::<std::iter::Empty<&InputEdit>>,
+                &new.tree,
+                len
+            ),
+            0..len
+        );
+        //a whole file replacement drops the log
+        edits.record(Instant::now(), None);
+        assert!(edits.since(new.timestamp).is_none());
+    }
+    #[test]
+    fn test_attribute_constraint_scope() {
+        let doc = document(
+            "features\n    A {constraint B, meta {constraints [B | !C]}}\n        optional\n            B\n    C\nconstraints\n    C\n",
+        );
+        let a = doc
+            .all_features()
+            .find(|f| doc.name(*f) == Some("A".into()))
+            .unwrap();
+        let scopes: Vec<_> = doc
+            .all_constraints()
+            .map(|c| {
+                let mut refs = Vec::new();
+                doc.constraint(c).unwrap().references(&mut refs);
+                let ref_scopes: Vec<_> = refs
+                    .iter()
+                    .map(|r| doc.get_reference(r.offset()).unwrap().scope)
+                    .collect();
+                (doc.scope(c), ref_scopes)
+            })
+            .collect();
+        //feature local constraints resolve relative to their feature, even below an attribute
+        assert_eq!(
+            scopes,
+            vec![
+                (a, vec![a]),
+                (a, vec![a, a]),
+                (Symbol::Root, vec![Symbol::Root])
+            ]
+        );
+    }
+    #[test]
+    fn test_invalid_cardinality() {
+        let doc = document("features\n    A cardinality [3..1]\n");
+        assert!(doc
+            .errors
+            .iter()
+            .any(|e| e.msg == "lower bound exceeds upper bound"));
+        assert_eq!(doc.cardinality(Symbol::Feature(0)), None);
     }
 }
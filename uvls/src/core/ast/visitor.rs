@@ -106,6 +106,7 @@ pub trait Visitor<'a> {
             weight: w,
             msg: error.into(),
             error_type: ErrorType::Any,
+            rule: Rule::Syntax,
         });
     }
     fn push_error_with_type<T: Into<String>>(&mut self, w: u32, error: T, error_type: ErrorType) {
@@ -115,6 +116,7 @@ pub trait Visitor<'a> {
             weight: w,
             msg: error.into(),
             error_type,
+            rule: error_type.rule(Rule::Syntax),
         });
     }
     fn push_error_node<T: Into<String>>(&mut self, node: Node, w: u32, error: T) {
@@ -124,6 +126,7 @@ pub trait Visitor<'a> {
             weight: w,
             msg: error.into(),
             error_type: ErrorType::Any,
+            rule: Rule::Syntax,
         });
     }
 }
@@ -148,14 +151,14 @@ where
     }
 }
 
-pub fn visit_children_arg<'a, A, F, T, V>(state: &mut V, arg: A, duplicate: &bool, mut f: F) -> T
+pub fn visit_children_arg<'a, A, F, T, V>(state: &mut V, arg: A, mut f: F) -> T
 where
     V: Visitor<'a>,
-    F: FnMut(&mut V, A, &bool) -> T,
+    F: FnMut(&mut V, A) -> T,
     T: Default,
 {
     if state.goto_first_child() {
-        let out = stacker::maybe_grow(32 * 1024, 1024 * 1024, || f(state, arg, duplicate));
+        let out = stacker::maybe_grow(32 * 1024, 1024 * 1024, || f(state, arg));
         state.goto_parent();
         out
     } else {
@@ -8,6 +8,7 @@ use log::info;
 use module::{ConfigModule, Module};
 use petgraph::prelude::*;
 use resolve::*;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use ustr::Ustr;
 #[derive(Debug, Clone, PartialEq)]
@@ -27,11 +28,27 @@ impl FSNode {
         matches!(self, Self::Dir)
     }
 }
+/// Directories imports are resolved against besides the directory of the importing file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportRoots {
+    /// workspace folders, files import relative to the folder containing them
+    pub folders: Vec<PathBuf>,
+    /// read only library directories, searched after local paths
+    pub libraries: Vec<PathBuf>,
+}
 /// Simple virtual filesystem for fast completions, resolve and namespaces
 #[derive(Debug, Clone, Default)]
 pub struct FileSystem {
     graph: DiGraph<FSNode, FSEdge>,
     file2node: HashMap<FileID, NodeIndex>,
+    //workspace folder of each file, imports fall back to it
+    file2root: HashMap<FileID, NodeIndex>,
+    //path of each workspace folder node
+    folders: HashMap<NodeIndex, PathBuf>,
+    //logical path of each workspace folder and library node
+    root_paths: HashMap<NodeIndex, Vec<Ustr>>,
+    libraries: Vec<NodeIndex>,
+    library_files: HashSet<FileID>,
 }
 impl FileSystem {
     fn goto_dir(
@@ -80,45 +97,143 @@ impl FileSystem {
             })
         })
     }
-    fn new(files: &AstFiles, errors: &mut ErrorsAcc) -> Self {
+    /// create missing directories under path
+    fn make_dir(
+        graph: &mut DiGraph<FSNode, FSEdge>,
+        mut dir: NodeIndex,
+        path: &[Ustr],
+    ) -> NodeIndex {
+        for i in path.iter() {
+            if let Some(old) = graph.edges(dir).find(|e| match e.weight() {
+                FSEdge::Path(name) => name == i && graph[e.target()].is_dir(),
+                _ => false,
+            }) {
+                dir = old.target();
+            } else {
+                let new = graph.add_node(FSNode::Dir);
+                graph.add_edge(dir, new, FSEdge::Path(*i));
+                dir = new;
+            }
+        }
+        dir
+    }
+    fn new(files: &AstFiles, import_roots: &ImportRoots, errors: &mut ErrorsAcc) -> Self {
         let mut graph = DiGraph::new();
         let mut file2node = HashMap::new();
+        let mut file2root = HashMap::new();
+        let mut library_files = HashSet::new();
         let root = graph.add_node(FSNode::Dir);
+        let mut make_roots = |dirs: &[PathBuf]| -> Vec<_> {
+            dirs.iter()
+                .map(|dir| {
+                    let path = dir_to_path(dir);
+                    let node = Self::make_dir(&mut graph, root, &path);
+                    (path, node)
+                })
+                .collect()
+        };
+        let libraries = make_roots(&import_roots.libraries);
+        let mut roots = make_roots(&import_roots.folders);
+        let folders = roots
+            .iter()
+            .zip(import_roots.folders.iter())
+            .map(|((_, node), path)| (*node, path.clone()))
+            .collect();
+        roots.extend(libraries.iter().cloned());
+        let root_paths = roots
+            .iter()
+            .map(|(path, node)| (*node, path.clone()))
+            .collect();
         //create file system
         for (&n, f) in files.iter() {
-            let mut dir = root;
-            for i in f.path[0..f.path.len() - 1].iter() {
-                if let Some(old) = graph.edges(dir).find(|e| match e.weight() {
-                    FSEdge::Path(name) => name == i && graph[e.target()].is_dir(),
-                    _ => false,
-                }) {
-                    dir = old.target();
-                } else {
-                    let new = graph.add_node(FSNode::Dir);
-                    graph.add_edge(dir, new, FSEdge::Path(*i));
-                    dir = new;
-                }
-            }
+            let dir = Self::make_dir(&mut graph, root, &f.path[0..f.path.len() - 1]);
             let id = graph.add_node(FSNode::File(n));
             graph.add_edge(dir, id, FSEdge::Path(*f.path.last().unwrap()));
             file2node.insert(n, id);
+            let Some(path) = uri_to_path(&f.uri) else {
+                continue;
+            };
+            //the innermost folder wins for nested workspace folders
+            if let Some((_, dir)) = roots
+                .iter()
+                .filter(|(root, _)| path.starts_with(root))
+                .max_by_key(|(root, _)| root.len())
+            {
+                file2root.insert(n, *dir);
+            }
+            if libraries.iter().any(|(lib, _)| path.starts_with(lib)) {
+                library_files.insert(n);
+            }
         }
+        let mut fs = Self {
+            graph,
+            file2node,
+            file2root,
+            folders,
+            root_paths,
+            libraries: libraries.into_iter().map(|(_, node)| node).collect(),
+            library_files,
+        };
         //resolve imports
         for (&n, f) in files.iter() {
             for i in f.all_imports().rev() {
-                if let Some(node) = Self::goto_file(&graph, file2node[&n], f.path(i)) {
-                    if graph.contains_edge(node, file2node[&n]) {
+                let candidates = fs.candidates(n, f.path(i));
+                if candidates.len() > 1 {
+                    let targets: Vec<_> = candidates
+                        .iter()
+                        .filter_map(|node| match &fs.graph[*node] {
+                            FSNode::File(id) => Some(id.url().to_string()),
+                            _ => None,
+                        })
+                        .collect();
+                    errors.sym_rule(
+                        i,
+                        n,
+                        10,
+                        Rule::AmbiguousImport,
+                        format!(
+                            "ambiguous import, using {} over {}",
+                            targets[0],
+                            targets[1..].join(", ")
+                        ),
+                    );
+                }
+                if let Some(node) = candidates.first().cloned() {
+                    if fs.graph.contains_edge(node, fs.file2node[&n]) {
                         errors.sym(i, n, 50, "cyclic import not allowed");
                     } else {
-                        graph.add_edge(file2node[&n], node, FSEdge::Import(i));
+                        fs.graph.add_edge(fs.file2node[&n], node, FSEdge::Import(i));
                     }
                 } else {
-                    errors.sym(i, n, 50, "unresolved import");
+                    errors.sym_rule(i, n, 50, Rule::UnresolvedImport, "unresolved import");
                     info!("Cant find {:?} ", f.path(i));
                 }
             }
         }
-        Self { graph, file2node }
+        fs
+    }
+    /// all file nodes under path by priority: relative to the directory of origin, its
+    /// workspace folder and the libraries
+    fn candidates(&self, origin: FileID, path: &[Ustr]) -> Vec<NodeIndex> {
+        let mut out = Vec::new();
+        let local = Self::goto_file(&self.graph, self.file2node[&origin], path);
+        let roots = self
+            .file2root
+            .get(&origin)
+            .into_iter()
+            .chain(self.libraries.iter());
+        for node in local
+            .into_iter()
+            .chain(roots.filter_map(|root| Self::goto_file(&self.graph, *root, path)))
+        {
+            if !out.contains(&node) {
+                out.push(node);
+            }
+        }
+        out
+    }
+    fn lookup(&self, origin: FileID, path: &[Ustr]) -> Option<NodeIndex> {
+        self.candidates(origin, path).first().cloned()
     }
     /// Check an import between a and b
     pub fn imports_connecting(&self, a: FileID, b: FileID) -> impl Iterator<Item = Symbol> + '_ {
@@ -184,27 +299,11 @@ impl FileSystem {
     }
     /// find a file under path from origin
     pub fn resolve(&self, origin: FileID, path: &[Ustr]) -> Option<FileID> {
-        let mut dir = self
-            .graph
-            .neighbors_directed(self.file2node[&origin], Incoming)
-            .find(|n| matches!(self.graph[*n], FSNode::Dir))
-            .unwrap();
-        for i in path[0..path.len() - 1].iter() {
-            if let Some(e) = self.graph.edges(dir).find(|e| match e.weight() {
-                FSEdge::Path(name) => name == i && self.graph[e.target()].is_dir(),
-                _ => false,
-            }) {
-                dir = e.target();
-            } else {
-                return None;
-            }
-        }
-        Self::goto_file(&self.graph, self.file2node[&origin], path).and_then(|node| {
-            match &self.graph[node] {
+        self.lookup(origin, path)
+            .and_then(|node| match &self.graph[node] {
                 FSNode::File(id) => Some(*id),
                 _ => None,
-            }
-        })
+            })
     }
     pub fn dir_files<'a>(
         &'a self,
@@ -253,6 +352,28 @@ impl FileSystem {
             .find(|n| matches!(self.graph[*n], FSNode::Dir))
             .unwrap()
     }
+    /// workspace folder containing file
+    pub fn root_of(&self, file: FileID) -> Option<NodeIndex> {
+        self.file2root.get(&file).cloned()
+    }
+    /// path of the workspace folder containing file, None for libraries and other files
+    pub fn folder_of(&self, file: FileID) -> Option<&Path> {
+        self.root_of(file)
+            .and_then(|root| self.folders.get(&root))
+            .map(|path| path.as_path())
+    }
+    /// logical paths of the workspace folders and of the libraries in lookup order
+    pub fn import_dirs(&self) -> (Vec<&[Ustr]>, Vec<&[Ustr]>) {
+        let path = |node: &NodeIndex| self.root_paths.get(node).map(|p| p.as_slice());
+        (
+            self.folders.keys().filter_map(path).collect(),
+            self.libraries.iter().filter_map(path).collect(),
+        )
+    }
+    /// library files are read only
+    pub fn is_library(&self, file: FileID) -> bool {
+        self.library_files.contains(&file)
+    }
     /// all subfiles from origin under path, returns (prefix,filename,filenode)
     pub fn sub_files<'a>(
         &'a self,
@@ -260,10 +381,23 @@ impl FileSystem {
         path: &[Ustr],
     ) -> impl Iterator<Item = (compact_str::CompactString, Ustr, FSNode)> + 'a {
         let dir = self.dir_of(origin);
+        //files under the workspace folder and the libraries are importable as well
+        let under_roots: Vec<_> = self
+            .root_of(origin)
+            .into_iter()
+            .chain(self.libraries.iter().cloned())
+            .filter(|root| *root != dir)
+            .map(|root| self.dir_files(root, path))
+            .collect();
+        let mut seen = HashSet::new();
         self.dir_files(dir, path)
-            .filter(move |(_, _, node)| match node {
-                FSNode::File(tgt) => tgt != &origin,
-                _ => true,
+            .chain(under_roots.into_iter().flatten())
+            .filter(move |(prefix, _, node)| {
+                seen.insert(prefix.clone())
+                    && match node {
+                        FSNode::File(tgt) => tgt != &origin,
+                        _ => true,
+                    }
             })
     }
 }
@@ -303,18 +437,30 @@ impl Cache {
         configs: &ConfigFiles,
         dirty: &HashSet<FileID>,
         revision: u64,
+        import_roots: &ImportRoots,
         errors: &mut ErrorsAcc,
     ) -> Cache {
+        let time = std::time::Instant::now();
         let mut trans_dirty = dirty.clone();
-        let fs = FileSystem::new(files, errors);
+        //files which have to be linked again, importers are only relinked when the exports of
+        //a dependency changed
+        let mut relink = dirty.clone();
+        let fs = FileSystem::new(files, import_roots, errors);
         for i in dirty.iter() {
             if !i.is_config() {
+                let exports_changed = old
+                    .ast
+                    .get(i)
+                    .map_or(true, |old| old.content.exports() != files[i].exports());
                 for k in fs.recursive_imported(*i) {
                     trans_dirty.insert(k);
+                    if exports_changed {
+                        relink.insert(k);
+                    }
                 }
             }
         }
-        for i in trans_dirty.iter() {
+        for i in relink.iter() {
             if !errors.errors.contains_key(i) {
                 errors.errors.insert(*i, Vec::new()); //Remove old errors when dependencies change
                                                       //but not the file
@@ -327,9 +473,9 @@ impl Cache {
             .map(|(k, v)| (k.clone(), v.clone()))
             .collect();
 
-        info!("updating cache dirty {:?}", trans_dirty);
+        info!("updating cache dirty {:?} relink {:?}", trans_dirty, relink);
         //Link ASTs
-        for i in trans_dirty.iter() {
+        for i in relink.iter() {
             if !i.is_config() {
                 linked_ast.insert(
                     *i,
@@ -400,7 +546,12 @@ impl Cache {
                 }
             }
         }
-
+        info!(
+            "linked {} of {} files in {:?}",
+            relink.len(),
+            files.len(),
+            time.elapsed()
+        );
         Cache {
             fs,
             config_modules,
@@ -409,3 +560,67 @@ impl Cache {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+    use tokio::time::Instant;
+    use tower_lsp::lsp_types::Url;
+    fn files(sources: &[(&str, &str)]) -> AstFiles {
+        sources
+            .iter()
+            .map(|(uri, source)| {
+                let rope = Rope::from_str(source);
+                let tree = parse::parse(&rope, None);
+                let ast = AstDocument::new(rope, tree, Url::parse(uri).unwrap(), Instant::now());
+                (ast.id, Arc::new(ast))
+            })
+            .collect()
+    }
+    #[test]
+    fn test_library_imports() {
+        let files = files(&[
+            (
+                "file:///ws/main.uvl",
+                "imports\n    Hardware\nfeatures\n    Main\n",
+            ),
+            ("file:///lib/Hardware.uvl", "features\n    Hardware\n"),
+            ("file:///other/Hardware.uvl", "features\n    Hardware\n"),
+        ]);
+        let main = FileID::new("file:///ws/main.uvl");
+        let lib = FileID::new("file:///lib/Hardware.uvl");
+        let configs = ConfigFiles::new();
+        let resolve = |libraries: &[&str]| {
+            let mut err = ErrorsAcc {
+                errors: HashMap::new(),
+                files: &files,
+                configs: &configs,
+            };
+            let roots = ImportRoots {
+                folders: vec!["/ws".into()],
+                libraries: libraries.iter().map(PathBuf::from).collect(),
+            };
+            let fs = FileSystem::new(&files, &roots, &mut err);
+            let rules: Vec<_> = err.errors.values().flatten().map(|e| e.rule).collect();
+            (fs, rules)
+        };
+        let (fs, rules) = resolve(&[]);
+        assert_eq!(fs.imports(main).count(), 0);
+        assert_eq!(rules, vec![Rule::UnresolvedImport]);
+        let (fs, rules) = resolve(&["/lib"]);
+        assert_eq!(
+            fs.imports(main).map(|(_, f)| f).collect::<Vec<_>>(),
+            vec![lib]
+        );
+        assert!(fs.is_library(lib) && !fs.is_library(main));
+        assert!(rules.is_empty());
+        assert_eq!(fs.folder_of(main), Some(Path::new("/ws")));
+        assert_eq!(fs.folder_of(lib), None);
+        let (fs, rules) = resolve(&["/lib", "/other"]);
+        assert_eq!(
+            fs.imports(main).map(|(_, f)| f).collect::<Vec<_>>(),
+            vec![lib]
+        );
+        assert_eq!(rules, vec![Rule::AmbiguousImport]);
+    }
+}
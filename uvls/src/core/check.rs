@@ -1,18 +1,20 @@
 use crate::core::*;
 use ast::insert_multi;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use log::info;
 use regex::Regex;
 use ropey::Rope;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
 use tokio::time::Instant;
+use tokio::{select, spawn};
 use tower_lsp::lsp_types::*;
 use tower_lsp::Client;
 use tree_sitter::{Node, QueryCursor, Tree};
 use unicode_segmentation::UnicodeSegmentation;
 
 /// This type is used to provide quickactions for a error
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ErrorType {
     Any = 0,
     FeatureNameContainsDashes,
@@ -20,11 +22,17 @@ pub enum ErrorType {
     AddIndentation,
     StartsWithNumber,
     WrongLanguageLevel,
+    UnusedImport,
+    SingleChildGroup,
+    EmptyGroup,
 }
 
 impl ErrorType {
     pub fn from_u32(value: u32) -> ErrorType {
         match value {
+            8 => ErrorType::EmptyGroup,
+            7 => ErrorType::SingleChildGroup,
+            6 => ErrorType::UnusedImport,
             5 => ErrorType::WrongLanguageLevel,
             4 => ErrorType::StartsWithNumber,
             3 => ErrorType::AddIndentation,
@@ -33,6 +41,20 @@ impl ErrorType {
             _ => ErrorType::Any,
         }
     }
+    /// Rule of errors with a quickfix, everything else falls back to the producer's rule
+    pub fn rule(&self, fallback: Rule) -> Rule {
+        match self {
+            ErrorType::Any => fallback,
+            ErrorType::FeatureNameContainsDashes => Rule::DashInName,
+            ErrorType::ReferenceToString => Rule::Type,
+            ErrorType::AddIndentation => Rule::Syntax,
+            ErrorType::StartsWithNumber => Rule::StartsWithNumber,
+            ErrorType::WrongLanguageLevel => Rule::LanguageLevel,
+            ErrorType::UnusedImport => Rule::UnusedImport,
+            ErrorType::SingleChildGroup => Rule::SingleChildGroup,
+            ErrorType::EmptyGroup => Rule::EmptyGroup,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -42,6 +64,7 @@ pub struct ErrorInfo {
     pub weight: u32,
     pub msg: String,
     pub error_type: ErrorType,
+    pub rule: Rule,
 }
 
 #[derive(Clone, Debug)]
@@ -51,43 +74,52 @@ pub struct FileErrorInfo {
 }
 
 impl ErrorInfo {
-    fn diagnostic(self) -> Diagnostic {
-        Diagnostic {
+    fn diagnostic(self, options: &LintOptions) -> Option<Diagnostic> {
+        Some(Diagnostic {
             range: self.location,
-            severity: Some(self.severity),
+            severity: Some(options.severity(self.rule, self.severity)?),
+            code: Some(self.rule.code()),
+            code_description: self.rule.code_description(),
+            tags: self.rule.tags(),
+            source: Some("uvls".into()),
             message: self.msg,
             data: Some(serde_json::value::Value::Number(
-                serde_json::value::Number::from(self.error_type.clone() as i32),
+                serde_json::value::Number::from(self.error_type as i32),
             )),
             ..Default::default()
-        }
+        })
     }
 }
-/// Publishes all collected errors and infos to the IDE
-pub async fn publish(client: &Client, uri: &Url, err: &[ErrorInfo]) {
-    // reduces cardinality error to one error
+/// Diagnostics as shown to the user, only errors of the highest weight are kept.
+/// If provider is set only diagnostics of that provider are returned
+fn diagnostics(
+    err: &[ErrorInfo],
+    options: &LintOptions,
+    provider: Option<DiagnosticProvider>,
+) -> Vec<Diagnostic> {
+    // drop identical and disabled errors
     let mut reduced_err = vec![];
     err.iter().for_each(|ele| {
-        if !reduced_err.contains(ele) {
+        if !reduced_err.contains(ele) && options.severity(ele.rule, ele.severity).is_some() {
             reduced_err.push(ele.clone())
         }
     });
-    if let Some(max) = reduced_err.clone().into_iter().max_by_key(|e| e.weight) {
-        client
-            .publish_diagnostics(
-                uri.clone(),
-                reduced_err[..]
-                    .iter()
-                    .rev()
-                    .filter(|e| e.weight == max.weight)
-                    .map(|i| i.clone().diagnostic())
-                    .collect(),
-                None,
-            )
-            .await;
-    } else {
-        client.publish_diagnostics(uri.clone(), vec![], None).await;
-    }
+    let Some(max) = reduced_err.iter().map(|e| e.weight).max() else {
+        return Vec::new();
+    };
+    reduced_err
+        .into_iter()
+        .rev()
+        .filter(|e| e.weight == max)
+        .filter(|e| provider.map(|p| e.rule.provider() == p).unwrap_or(true))
+        .filter_map(|i| i.diagnostic(options))
+        .collect()
+}
+/// Publishes all collected errors and infos to the IDE
+pub async fn publish(client: &Client, uri: &Url, err: &[ErrorInfo], options: &LintOptions) {
+    client
+        .publish_diagnostics(uri.clone(), diagnostics(err, options, None), None)
+        .await;
 }
 //W/ alk the syntax tree and only go "down" if F is true
 fn ts_filterd_visit<F: FnMut(Node) -> bool>(root: Node, mut f: F) {
@@ -157,6 +189,7 @@ pub fn check_sanity(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
                             severity: DiagnosticSeverity::ERROR,
                             msg: "line breaks are only allowed inside parenthesis".to_string(),
                             error_type: ErrorType::Any,
+                            rule: Rule::Syntax,
                         });
                     }
                 }
@@ -170,6 +203,7 @@ pub fn check_sanity(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
                     severity: DiagnosticSeverity::ERROR,
                     msg: "line breaks are only allowed inside parenthesis".to_string(),
                     error_type: ErrorType::Any,
+                    rule: Rule::Syntax,
                 });
             }
             if lines.insert(node.start_position().row, node).is_some() {
@@ -203,6 +237,7 @@ pub fn check_sanity(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
                         severity: DiagnosticSeverity::ERROR,
                         msg: "features are not allowed to start with a number".to_string(),
                         error_type: ErrorType::StartsWithNumber,
+                        rule: Rule::StartsWithNumber,
                     });
                 } else {
                     error.push(ErrorInfo {
@@ -211,6 +246,7 @@ pub fn check_sanity(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
                         severity: DiagnosticSeverity::ERROR,
                         msg: "features have to be in different lines".to_string(),
                         error_type: ErrorType::Any,
+                        rule: Rule::Syntax,
                     });
                 }
             }
@@ -223,6 +259,7 @@ pub fn check_sanity(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
                     severity: DiagnosticSeverity::ERROR,
                     msg: "multiline strings are not supported".to_string(),
                     error_type: ErrorType::Any,
+                    rule: Rule::Syntax,
                 });
             }
         }
@@ -253,6 +290,7 @@ pub fn classify_error(root: Node, source: &Rope) -> ErrorInfo {
                 weight: 80,
                 msg: "missing lhs or rhs expression".into(),
                 error_type: ErrorType::Any,
+                rule: Rule::Syntax,
             };
         }
     }
@@ -291,6 +329,7 @@ pub fn classify_error(root: Node, source: &Rope) -> ErrorInfo {
             weight: 100,
             msg: "features are not allowed to start with a number here".into(),
             error_type: ErrorType::StartsWithNumber,
+            rule: Rule::StartsWithNumber,
         };
     }
     ErrorInfo {
@@ -299,6 +338,7 @@ pub fn classify_error(root: Node, source: &Rope) -> ErrorInfo {
         weight: 80,
         msg: "unknown syntax error".into(),
         error_type: ErrorType::Any,
+        rule: Rule::Syntax,
     }
 }
 pub fn check_errors(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
@@ -311,6 +351,7 @@ pub fn check_errors(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
                 weight: 80,
                 msg: format!("missing {}", i.kind()),
                 error_type: ErrorType::Any,
+                rule: Rule::Syntax,
             });
             false
         } else if i.is_error() {
@@ -322,53 +363,235 @@ pub fn check_errors(tree: &Tree, source: &Rope) -> Vec<ErrorInfo> {
     });
     err
 }
+/// Stage which reported a diagnostic update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Producer {
+    Link,
+    Smt,
+}
 #[derive(Debug, Clone)]
 pub struct DiagnosticUpdate {
     pub error_state: HashMap<FileID, Vec<ErrorInfo>>,
     pub timestamp: u64,
+    pub producer: Producer,
+    /// Files the producer analysed completely, suppressions of smt rules
+    /// are only judged for these files
+    pub complete: HashSet<FileID>,
 }
 
+/// Latest errors of a file for each producer together with the revision they belong to
+#[derive(Default)]
 struct DiagnosticState {
-    timestamp: u64,
-    error: Vec<ErrorInfo>,
+    link: (u64, Vec<ErrorInfo>),
+    smt: (u64, Vec<ErrorInfo>),
+    //smt results of the current revision are complete
+    smt_complete: bool,
+    //suppressions reported as unused the last time
+    unused: Vec<Suppression>,
 }
-async fn maybe_publish(
-    client: &Client,
-    source_map: &mut HashMap<FileID, DiagnosticState>,
-    uri: FileID,
-    mut err: Vec<ErrorInfo>,
-    timestamp: u64,
-) {
-    if let Some(old) = source_map.get_mut(&uri) {
-        if old.timestamp < timestamp {
-            publish(client, &uri.url(), &err).await;
-            old.timestamp = timestamp;
-            old.error = err;
-        } else if old.timestamp == timestamp {
-            old.timestamp = timestamp;
-            old.error.append(&mut err);
-            publish(client, &uri.url(), &old.error).await;
+impl DiagnosticState {
+    fn judged(&self, provider: DiagnosticProvider) -> bool {
+        provider != DiagnosticProvider::Smt || self.smt_complete
+    }
+    fn errors(&self) -> Vec<ErrorInfo> {
+        self.link
+            .1
+            .iter()
+            .chain(self.smt.1.iter())
+            .cloned()
+            .collect()
+    }
+}
+#[derive(Default)]
+struct StoreState {
+    files: HashMap<FileID, DiagnosticState>,
+    //last report of each file and provider for resultIds
+    reports: HashMap<(FileID, Option<DiagnosticProvider>), (String, Vec<Diagnostic>)>,
+    next_result_id: u64,
+    pull: bool,
+    refresh: bool,
+}
+/// All diagnostics known to the server, they are either pushed to the client
+/// or pulled by the client when it supports pull diagnostics
+#[derive(Clone, Default)]
+pub struct DiagnosticStore {
+    state: Arc<parking_lot::Mutex<StoreState>>,
+}
+impl DiagnosticStore {
+    /// Switch to pull diagnostics, refresh if the client can be asked to pull again
+    pub fn set_pull(&self, pull: bool, refresh: bool) {
+        let mut state = self.state.lock();
+        state.pull = pull;
+        state.refresh = refresh;
+    }
+    pub fn is_pull(&self) -> bool {
+        self.state.lock().pull
+    }
+    /// Merge an update and return the files whose errors changed.
+    /// Results of the linker reset smt results of older revisions,
+    /// smt results older than the latest link are dropped
+    fn update(&self, update: DiagnosticUpdate) -> Vec<FileID> {
+        let mut state = self.state.lock();
+        let mut changed = Vec::new();
+        let timestamp = update.timestamp;
+        let mut error_state = update.error_state;
+        for id in update.complete.iter() {
+            error_state.entry(*id).or_default();
         }
-    } else {
-        publish(client, &uri.url(), &err).await;
-        source_map.insert(
-            uri,
-            DiagnosticState {
-                timestamp,
-                error: err,
-            },
+        for (id, mut err) in error_state {
+            if id.is_virtual() {
+                continue;
+            }
+            let file = state.files.entry(id).or_default();
+            let complete = update.complete.contains(&id);
+            match update.producer {
+                Producer::Link if file.link.0 <= timestamp => {
+                    file.link = (timestamp, err);
+                    if file.smt.0 < timestamp {
+                        file.smt = (timestamp, Vec::new());
+                        file.smt_complete = false;
+                    }
+                }
+                Producer::Smt if file.link.0 <= timestamp && file.smt.0 < timestamp => {
+                    file.smt = (timestamp, err);
+                    file.smt_complete = complete;
+                }
+                Producer::Smt if file.link.0 <= timestamp && file.smt.0 == timestamp => {
+                    file.smt.1.append(&mut err);
+                    file.smt_complete |= complete;
+                }
+                _ => continue,
+            }
+            changed.push(id);
+        }
+        changed
+    }
+    pub fn files(&self) -> Vec<FileID> {
+        self.state.lock().files.keys().cloned().collect()
+    }
+    /// Forget files which are not part of root anymore, eg. deleted or unloaded ones,
+    /// and return them
+    fn prune(&self, root: &RootGraph) -> Vec<FileID> {
+        let mut state = self.state.lock();
+        let removed: Vec<FileID> = state
+            .files
+            .keys()
+            .filter(|id| !root.contains_id(**id))
+            .cloned()
+            .collect();
+        for id in removed.iter() {
+            state.files.remove(id);
+        }
+        state.reports.retain(|(id, _), _| root.contains_id(*id));
+        removed
+    }
+    /// Errors of a file without the suppressed ones, unused suppressions are reported as errors
+    fn errors(&self, id: FileID, root: &RootGraph) -> Vec<ErrorInfo> {
+        //library files are read only, nothing to report
+        if root.fs().is_library(id) {
+            return Vec::new();
+        }
+        let mut state = self.state.lock();
+        let Some(file) = root.files.get(&id) else {
+            return state.files.get(&id).map(|f| f.errors()).unwrap_or_default();
+        };
+        let state = state.files.entry(id).or_default();
+        let (err, unused) = apply_suppressions(
+            &state.errors(),
+            &file.suppressions,
+            &file.source,
+            |p| state.judged(p),
+            &state.unused,
         );
+        state.unused = unused;
+        err
+    }
+    /// Latest diagnostics of a file with their resultId,
+    /// the resultId only changes when the diagnostics change
+    pub fn report(
+        &self,
+        id: FileID,
+        provider: Option<DiagnosticProvider>,
+        options: &WorkspaceLints,
+        root: &RootGraph,
+    ) -> (String, Vec<Diagnostic>) {
+        let items = diagnostics(&self.errors(id, root), options.of(id, root.fs()), provider);
+        let mut state = self.state.lock();
+        if let Some((result_id, old)) = state.reports.get(&(id, provider)) {
+            if *old == items {
+                return (result_id.clone(), items);
+            }
+        }
+        state.next_result_id += 1;
+        let result_id = state.next_result_id.to_string();
+        state
+            .reports
+            .insert((id, provider), (result_id.clone(), items.clone()));
+        (result_id, items)
+    }
+    /// Inform the client about changed files
+    async fn notify(
+        &self,
+        client: &Client,
+        files: &[FileID],
+        options: &WorkspaceLints,
+        root: &RootGraph,
+    ) {
+        let (pull, refresh) = {
+            let state = self.state.lock();
+            (state.pull, state.refresh)
+        };
+        if pull {
+            if refresh && !files.is_empty() {
+                //the client answers only after pulling, which must not block the handler
+                let client = client.clone();
+                spawn(async move {
+                    let _ = client.workspace_diagnostic_refresh().await;
+                });
+            }
+            return;
+        }
+        for id in files {
+            let errors = self.errors(*id, root);
+            publish(client, &id.url(), &errors, options.of(*id, root.fs())).await;
+        }
     }
 }
 
-pub async fn diagnostic_handler(mut rx: mpsc::Receiver<DiagnosticUpdate>, client: Client) {
-    let mut source_map: HashMap<FileID, DiagnosticState> = HashMap::new();
-    while let Some(mut update) = rx.recv().await {
-        for (uri, err) in update.error_state.drain() {
-            if uri.is_virtual() {
-                continue;
+pub async fn diagnostic_handler(
+    mut rx: mpsc::Receiver<DiagnosticUpdate>,
+    mut rx_options: watch::Receiver<WorkspaceLints>,
+    rx_root: watch::Receiver<Arc<RootGraph>>,
+    store: DiagnosticStore,
+    client: Client,
+) {
+    loop {
+        select! {
+            update = rx.recv() => {
+                let Some(update) = update else {
+                    break;
+                };
+                let mut changed = store.update(update);
+                let options = rx_options.borrow().clone();
+                //suppression comments are taken from the latest linked documents
+                let root = rx_root.borrow().clone();
+                //removed files are published once more without diagnostics
+                for id in store.prune(&root) {
+                    if !changed.contains(&id) {
+                        changed.push(id);
+                    }
+                }
+                store.notify(&client, &changed, &options, &root).await;
+            }
+            changed = rx_options.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                //severities changed, republish everything we know
+                let options = rx_options.borrow_and_update().clone();
+                let root = rx_root.borrow().clone();
+                store.notify(&client, &store.files(), &options, &root).await;
             }
-            maybe_publish(&client, &mut source_map, uri, err, update.timestamp).await
         }
     }
 }
@@ -405,6 +628,7 @@ impl<'a> ErrorsAcc<'a> {
                 weight,
                 msg: s.into(),
                 error_type: ErrorType::Any,
+                rule: Rule::Error,
             },
         );
     }
@@ -426,6 +650,7 @@ impl<'a> ErrorsAcc<'a> {
                 weight,
                 msg: s.into(),
                 error_type,
+                rule: error_type.rule(Rule::Error),
             },
         );
     }
@@ -440,6 +665,29 @@ impl<'a> ErrorsAcc<'a> {
                 weight,
                 msg: s.into(),
                 error_type: ErrorType::Any,
+                rule: Rule::Error,
+            },
+        );
+    }
+    /// Report a diagnostic of rule with the default severity of the rule
+    pub fn sym_rule<S: Into<String>>(
+        &mut self,
+        sym: Symbol,
+        file: FileID,
+        weight: u32,
+        rule: Rule,
+        s: S,
+    ) {
+        insert_multi(
+            &mut self.errors,
+            file,
+            ErrorInfo {
+                location: self.files[&file].lsp_range(sym).unwrap(),
+                severity: rule.default_severity(),
+                weight,
+                msg: s.into(),
+                error_type: ErrorType::Any,
+                rule,
             },
         );
     }
@@ -459,6 +707,7 @@ impl<'a> ErrorsAcc<'a> {
                 weight,
                 msg: s.into(),
                 error_type: ErrorType::Any,
+                rule: Rule::Error,
             },
         );
     }
@@ -486,10 +735,39 @@ impl<'a> ErrorsAcc<'a> {
                 weight,
                 msg: s.into(),
                 error_type: error_type,
+                rule: error_type.rule(Rule::Error),
             },
         );
     }
 
+    /// Report a diagnostic of rule with the default severity of the rule
+    pub fn span_rule<S: Into<String>>(
+        &mut self,
+        span: Span,
+        file: FileID,
+        weight: u32,
+        rule: Rule,
+        s: S,
+    ) {
+        let source = self
+            .configs
+            .get(&file)
+            .map(|i| &i.source)
+            .or_else(|| self.files.get(&file).map(|i| &i.source))
+            .unwrap();
+        insert_multi(
+            &mut self.errors,
+            file,
+            ErrorInfo {
+                location: lsp_range(span, &source).unwrap(),
+                severity: rule.default_severity(),
+                weight,
+                msg: s.into(),
+                error_type: ErrorType::Any,
+                rule,
+            },
+        );
+    }
     pub fn span_info<S: Into<String>>(&mut self, span: Span, file: FileID, weight: u32, s: S) {
         let source = self
             .configs
@@ -506,7 +784,48 @@ impl<'a> ErrorsAcc<'a> {
                 weight,
                 msg: s.into(),
                 error_type: ErrorType::Any,
+                rule: Rule::Error,
             },
         );
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ide::refactor::tests::root_graph;
+    #[test]
+    fn test_prune_removed_files() {
+        let sources = [
+            ("file:///ws/a.uvl", "features\n    A\n"),
+            ("file:///ws/b.uvl", "features\n    B\n"),
+        ];
+        let root = root_graph(&sources, &ImportRoots::default());
+        let error = ErrorInfo {
+            location: Range::default(),
+            severity: DiagnosticSeverity::ERROR,
+            weight: 10,
+            msg: "error".into(),
+            error_type: ErrorType::Any,
+            rule: Rule::Error,
+        };
+        let store = DiagnosticStore::default();
+        store.update(DiagnosticUpdate {
+            error_state: root
+                .files
+                .keys()
+                .map(|id| (*id, vec![error.clone()]))
+                .collect(),
+            timestamp: 1,
+            producer: Producer::Link,
+            complete: HashSet::new(),
+        });
+        let options = WorkspaceLints::default();
+        let b = FileID::new("file:///ws/b.uvl");
+        assert_eq!(store.report(b, None, &options, &root).1.len(), 1);
+        //b was deleted
+        let root = root_graph(&sources[..1], &ImportRoots::default());
+        assert_eq!(store.prune(&root), vec![b]);
+        assert_eq!(store.files(), vec![FileID::new("file:///ws/a.uvl")]);
+        assert!(store.report(b, None, &options, &root).1.is_empty());
+    }
+}
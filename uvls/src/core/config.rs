@@ -35,8 +35,10 @@ use tower_lsp::lsp_types::*;
 use tree_sitter::{Node, Tree, TreeCursor};
 use ustr::Ustr;
 
-/// This enum is used for storing a cardinality of a feature
+/// This enum is used for storing the instances of a feature with a feature cardinality
 ///
+/// CardinalityLvl holds one list of entries per instance, the number of lists is the number
+/// of instances.
 /// EntitiyLvl is only used to serialize cardinality, to achieve a new sub layer for the multiple children features.
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub enum CardinalityEntry {
@@ -403,6 +405,7 @@ pub fn parse_json(tree: Tree, source: Rope, uri: Url, timestamp: Instant) -> Con
                 severity: DiagnosticSeverity::ERROR,
                 msg: "JSON syntax errors".into(),
                 error_type: ErrorType::Any,
+                rule: Rule::Syntax,
             });
             (None, state.err)
         } else {
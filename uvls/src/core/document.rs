@@ -6,7 +6,8 @@ use tokio::time::Instant;
 use tower_lsp::lsp_types::*;
 use tree_sitter::{InputEdit, Tree};
 
-/// update the document text using text deltas form the editor
+/// update the document text using text deltas form the editor, returns the applied edits or
+/// None if the whole file was replaced
 ///
 /// for some insane reason lsp uses utf16 code points as offsets
 /// so we have to transform utf16->bytes for tree-sitter
@@ -14,8 +15,9 @@ pub fn update_text(
     source: &mut Rope,
     tree: Option<&mut Tree>,
     changes: DidChangeTextDocumentParams,
-) -> bool {
+) -> Option<Vec<InputEdit>> {
     let mut whole_file = false;
+    let mut edits = Vec::new();
     for e in changes.content_changes.iter() {
         if let Some(range) = e.range {
             let start_line = range.start.line as usize;
@@ -46,31 +48,82 @@ pub fn update_text(
             source.insert(start_char, &e.text);
             let new_end_line = source.byte_to_line(start_byte + e.text.len());
             let new_end_col_byte = (start_byte + e.text.len()) - source.line_to_byte(new_end_line);
+            let edit = InputEdit {
+                start_byte,
+                old_end_byte: end_byte,
+                new_end_byte: start_byte + e.text.len(),
+                start_position: tree_sitter::Point {
+                    row: start_line,
+                    column: start_col_byte,
+                },
+                old_end_position: tree_sitter::Point {
+                    row: end_line,
+                    column: end_col_byte,
+                },
+                new_end_position: tree_sitter::Point {
+                    row: new_end_line,
+                    column: new_end_col_byte,
+                },
+            };
             if let Some(&mut ref mut tree) = tree {
-                tree.edit(&InputEdit {
-                    start_byte,
-                    old_end_byte: end_byte,
-                    new_end_byte: start_byte + e.text.len(),
-                    start_position: tree_sitter::Point {
-                        row: start_line,
-                        column: start_col_byte,
-                    },
-                    old_end_position: tree_sitter::Point {
-                        row: end_line,
-                        column: end_col_byte,
-                    },
-                    new_end_position: tree_sitter::Point {
-                        row: new_end_line,
-                        column: new_end_col_byte,
-                    },
-                });
+                tree.edit(&edit);
             }
+            edits.push(edit);
         } else {
             whole_file = true;
             *source = Rope::from_str(&e.text);
         }
     }
-    whole_file
+    if whole_file {
+        None
+    } else {
+        Some(edits)
+    }
+}
+/// Edits kept per draft, older ones are dropped
+const EDIT_LOG_LIMIT: usize = 256;
+/// Edits of a draft with the timestamp of the version they produced, used to find the part
+/// of the document that changed since an older version
+#[derive(Debug, Clone)]
+pub struct EditLog {
+    //oldest version the edits lead from
+    base: Instant,
+    edits: Vec<(Instant, InputEdit)>,
+}
+impl EditLog {
+    pub fn new(timestamp: Instant) -> Self {
+        Self {
+            base: timestamp,
+            edits: Vec::new(),
+        }
+    }
+    /// Record the edits of the version at timestamp, None if the whole file was replaced
+    pub fn record(&mut self, timestamp: Instant, edits: Option<Vec<InputEdit>>) {
+        let Some(edits) = edits else {
+            *self = Self::new(timestamp);
+            return;
+        };
+        self.edits.extend(edits.into_iter().map(|e| (timestamp, e)));
+        if self.edits.len() > EDIT_LOG_LIMIT {
+            let drop = self.edits.len() - EDIT_LOG_LIMIT;
+            //versions are only complete up to the last dropped edit
+            let base = self.edits[drop - 1].0;
+            self.edits.retain(|(t, _)| *t > base);
+            self.base = base;
+        }
+    }
+    /// Edits from the version at timestamp to the latest one, None if they are unknown
+    pub fn since(&self, timestamp: Instant) -> Option<impl Iterator<Item = &InputEdit>> {
+        if timestamp < self.base {
+            return None;
+        }
+        Some(
+            self.edits
+                .iter()
+                .filter(move |(t, _)| *t > timestamp)
+                .map(|(_, e)| e),
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -79,6 +132,7 @@ pub enum Draft {
         source: Rope,
         tree: Tree,
         timestamp: Instant,
+        edits: EditLog,
     },
     JSON {
         source: Rope,
@@ -97,6 +151,18 @@ impl Draft {
             Self::UVL { source, .. } | Self::JSON { source, .. } => source,
         }
     }
+    pub fn tree(&self) -> &Tree {
+        match self {
+            Self::UVL { tree, .. } | Self::JSON { tree, .. } => tree,
+        }
+    }
+    /// Edits leading to this version, only tracked for uvl files
+    pub fn edits(&self) -> Option<&EditLog> {
+        match self {
+            Self::UVL { edits, .. } => Some(edits),
+            Self::JSON { .. } => None,
+        }
+    }
 }
 /// A document can be owned by the operating system or opened in the editor
 #[derive(Clone, PartialEq, Eq, Copy, Debug)]
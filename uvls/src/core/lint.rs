@@ -0,0 +1,645 @@
+//! Registry of named diagnostic rules
+//!
+//! Every diagnostic belongs to a rule with a stable name like `uvl::dead-feature`. The name is
+//! published as the diagnostic code and used to override the severity per workspace, eg.
+//! `{"lints":{"uvl::tautology":"off"}}` in the initialization options.
+//!
+//! Besides the checks of the compiler passes, [lint_file] reports modeling style issues.
+//!
+//! Single diagnostics are suppressed in the source with `// uvls-ignore <rule>` on the line
+//! before the feature or constraint, or for the whole file with `// uvls-ignore-file <rule>`.
+use crate::core::*;
+use hashbrown::{HashMap, HashSet};
+use lazy_static::lazy_static;
+use regex::Regex;
+use ropey::Rope;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::{
+    CodeDescription, DiagnosticSeverity, DiagnosticTag, NumberOrString, Url,
+};
+use ustr::Ustr;
+
+/// Documentation of all rules, each rule links to this section
+const RULE_DOCS: &str = "https://github.com/Universal-Variability-Language/uvl-lsp#lint-rules";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Rule {
+    #[default]
+    Error,
+    Syntax,
+    DuplicateName,
+    DashInName,
+    StartsWithNumber,
+    LanguageLevel,
+    UnresolvedReference,
+    UnresolvedImport,
+    Type,
+    Config,
+    DeadFeature,
+    Tautology,
+    VoidModel,
+    Unsat,
+    UnusedSuppression,
+    UnusedImport,
+    EmptyGroup,
+    SingleChildGroup,
+    FeatureName,
+    AttributeType,
+    MaxDepth,
+    AmbiguousImport,
+    AnalysisTimeout,
+}
+impl Rule {
+    pub const ALL: &'static [Rule] = &[
+        Rule::Error,
+        Rule::Syntax,
+        Rule::DuplicateName,
+        Rule::DashInName,
+        Rule::StartsWithNumber,
+        Rule::LanguageLevel,
+        Rule::UnresolvedReference,
+        Rule::UnresolvedImport,
+        Rule::Type,
+        Rule::Config,
+        Rule::DeadFeature,
+        Rule::Tautology,
+        Rule::VoidModel,
+        Rule::Unsat,
+        Rule::UnusedSuppression,
+        Rule::UnusedImport,
+        Rule::EmptyGroup,
+        Rule::SingleChildGroup,
+        Rule::FeatureName,
+        Rule::AttributeType,
+        Rule::MaxDepth,
+        Rule::AmbiguousImport,
+        Rule::AnalysisTimeout,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Error => "uvl::error",
+            Rule::Syntax => "uvl::syntax",
+            Rule::DuplicateName => "uvl::duplicate-name",
+            Rule::DashInName => "uvl::dash-in-name",
+            Rule::StartsWithNumber => "uvl::starts-with-number",
+            Rule::LanguageLevel => "uvl::language-level",
+            Rule::UnresolvedReference => "uvl::unresolved-reference",
+            Rule::UnresolvedImport => "uvl::unresolved-import",
+            Rule::Type => "uvl::type",
+            Rule::Config => "uvl::config",
+            Rule::DeadFeature => "uvl::dead-feature",
+            Rule::Tautology => "uvl::tautology",
+            Rule::VoidModel => "uvl::void-model",
+            Rule::Unsat => "uvl::unsat",
+            Rule::UnusedSuppression => "uvl::unused-suppression",
+            Rule::UnusedImport => "uvl::unused-import",
+            Rule::EmptyGroup => "uvl::empty-group",
+            Rule::SingleChildGroup => "uvl::single-child-group",
+            Rule::FeatureName => "uvl::feature-name",
+            Rule::AttributeType => "uvl::attribute-type",
+            Rule::MaxDepth => "uvl::max-depth",
+            Rule::AmbiguousImport => "uvl::ambiguous-import",
+            Rule::AnalysisTimeout => "uvl::analysis-timeout",
+        }
+    }
+    pub fn default_severity(self) -> DiagnosticSeverity {
+        match self {
+            Rule::DeadFeature | Rule::Tautology => DiagnosticSeverity::INFORMATION,
+            Rule::UnusedSuppression
+            | Rule::UnusedImport
+            | Rule::EmptyGroup
+            | Rule::FeatureName
+            | Rule::AttributeType
+            | Rule::AmbiguousImport => DiagnosticSeverity::WARNING,
+            Rule::SingleChildGroup | Rule::MaxDepth | Rule::AnalysisTimeout => {
+                DiagnosticSeverity::INFORMATION
+            }
+            _ => DiagnosticSeverity::ERROR,
+        }
+    }
+    /// Rule by name, the `uvl::` prefix is optional
+    pub fn from_name(name: &str) -> Option<Rule> {
+        let name = name.trim();
+        Rule::ALL
+            .iter()
+            .find(|r| r.name() == name || r.name().strip_prefix("uvl::") == Some(name))
+            .copied()
+    }
+    pub fn code(self) -> NumberOrString {
+        NumberOrString::String(self.name().into())
+    }
+    pub fn provider(self) -> DiagnosticProvider {
+        match self {
+            Rule::Syntax | Rule::DuplicateName | Rule::DashInName | Rule::StartsWithNumber => {
+                DiagnosticProvider::Syntax
+            }
+            Rule::Config
+            | Rule::DeadFeature
+            | Rule::Tautology
+            | Rule::VoidModel
+            | Rule::Unsat
+            | Rule::AnalysisTimeout => DiagnosticProvider::Smt,
+            _ => DiagnosticProvider::Semantic,
+        }
+    }
+    /// Unused code is rendered faded out by most clients
+    pub fn tags(self) -> Option<Vec<DiagnosticTag>> {
+        match self {
+            Rule::UnusedSuppression | Rule::UnusedImport => Some(vec![DiagnosticTag::UNNECESSARY]),
+            _ => None,
+        }
+    }
+    pub fn code_description(self) -> Option<CodeDescription> {
+        Url::parse(RULE_DOCS)
+            .ok()
+            .map(|href| CodeDescription { href })
+    }
+}
+
+/// Pull diagnostics are split into independent providers, each reports a subset of the rules
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DiagnosticProvider {
+    Syntax,
+    Semantic,
+    Smt,
+}
+impl DiagnosticProvider {
+    pub const ALL: &'static [DiagnosticProvider] = &[
+        DiagnosticProvider::Syntax,
+        DiagnosticProvider::Semantic,
+        DiagnosticProvider::Smt,
+    ];
+    pub fn identifier(self) -> &'static str {
+        match self {
+            DiagnosticProvider::Syntax => "uvls-syntax",
+            DiagnosticProvider::Semantic => "uvls-semantic",
+            DiagnosticProvider::Smt => "uvls-smt",
+        }
+    }
+    pub fn from_identifier(identifier: &str) -> Option<DiagnosticProvider> {
+        Self::ALL
+            .iter()
+            .find(|p| p.identifier() == identifier)
+            .copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleSeverity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+    Off,
+}
+
+/// Configuration of a single rule, either only a severity or a table with rule settings, eg.
+/// `{"severity":"warning","pattern":"^[A-Z][a-zA-Z0-9]*$"}`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RuleConfig {
+    Severity(RuleSeverity),
+    Table {
+        severity: Option<RuleSeverity>,
+        pattern: Option<String>,
+        max: Option<usize>,
+    },
+}
+
+/// Trees deeper than this are reported by `uvl::max-depth` if not configured otherwise
+const DEFAULT_MAX_DEPTH: usize = 10;
+
+/// Per workspace severity overrides and rule settings, unknown rule names are ignored
+#[derive(Clone, Debug)]
+pub struct LintOptions {
+    severity: HashMap<Rule, RuleSeverity>,
+    /// naming convention of features, not checked by default
+    pub feature_name: Option<Regex>,
+    /// maximal number of nested features
+    pub max_depth: usize,
+}
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            severity: HashMap::new(),
+            feature_name: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+impl<'de> Deserialize<'de> for LintOptions {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = std::collections::HashMap::<String, RuleConfig>::deserialize(deserializer)?;
+        let mut options = LintOptions::default();
+        for (k, v) in raw {
+            let Some(rule) = Rule::from_name(&k) else {
+                continue;
+            };
+            match v {
+                RuleConfig::Severity(severity) => {
+                    options.severity.insert(rule, severity);
+                }
+                RuleConfig::Table {
+                    severity,
+                    pattern,
+                    max,
+                } => {
+                    if let Some(severity) = severity {
+                        options.severity.insert(rule, severity);
+                    }
+                    match rule {
+                        Rule::FeatureName => {
+                            options.feature_name = pattern
+                                .map(|p| Regex::new(&p))
+                                .transpose()
+                                .map_err(serde::de::Error::custom)?;
+                        }
+                        Rule::MaxDepth => {
+                            options.max_depth = max.unwrap_or(DEFAULT_MAX_DEPTH);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+impl LintOptions {
+    /// Severity of a diagnostic reported with severity by the producer, None if disabled
+    pub fn severity(&self, rule: Rule, severity: DiagnosticSeverity) -> Option<DiagnosticSeverity> {
+        match self.severity.get(&rule) {
+            None => Some(severity),
+            Some(RuleSeverity::Error) => Some(DiagnosticSeverity::ERROR),
+            Some(RuleSeverity::Warning) => Some(DiagnosticSeverity::WARNING),
+            Some(RuleSeverity::Info) => Some(DiagnosticSeverity::INFORMATION),
+            Some(RuleSeverity::Hint) => Some(DiagnosticSeverity::HINT),
+            Some(RuleSeverity::Off) => None,
+        }
+    }
+}
+/// Lint options of each workspace folder, files outside of the folders use the default
+#[derive(Clone, Debug, Default)]
+pub struct WorkspaceLints {
+    pub default: LintOptions,
+    pub folders: HashMap<PathBuf, LintOptions>,
+}
+impl WorkspaceLints {
+    /// Options of the workspace folder containing file
+    pub fn of(&self, file: FileID, fs: &FileSystem) -> &LintOptions {
+        fs.folder_of(file)
+            .and_then(|folder| self.folders.get(folder))
+            .unwrap_or(&self.default)
+    }
+}
+
+lazy_static! {
+    static ref SUPPRESSION: Regex = Regex::new(r"^\s*//\s*uvls-ignore(-file)?\s+(.*)$").unwrap();
+    static ref RULE_NAME: Regex = Regex::new(r"[^\s,]+").unwrap();
+}
+
+/// One rule name of a `// uvls-ignore` comment
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suppression {
+    /// None for unknown rule names
+    pub rule: Option<Rule>,
+    /// location of the rule name
+    pub span: Span,
+    /// diagnostics starting on this line are suppressed, None for the whole file
+    pub target: Option<usize>,
+}
+impl Suppression {
+    pub fn suppresses(&self, err: &ErrorInfo) -> bool {
+        self.rule == Some(err.rule)
+            && self
+                .target
+                .map(|line| err.location.start.line as usize == line)
+                .unwrap_or(true)
+    }
+}
+
+/// Collects all suppression comments of a document, stacked comments and blank lines
+/// in between target the next line with content
+pub fn suppressions(source: &Rope) -> Vec<Suppression> {
+    let mut out = Vec::new();
+    let mut pending = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        let text = text.to_string();
+        let text = text.trim_end();
+        if text.is_empty() {
+            continue;
+        }
+        let Some(cap) = SUPPRESSION.captures(text) else {
+            for mut s in pending.drain(..) {
+                if let Suppression {
+                    target: Some(_), ..
+                } = s
+                {
+                    s.target = Some(line);
+                }
+                out.push(s);
+            }
+            continue;
+        };
+        let file_level = cap.get(1).is_some();
+        let names = cap.get(2).unwrap();
+        let offset = source.line_to_byte(line) + names.start();
+        for name in RULE_NAME.find_iter(names.as_str()) {
+            pending.push(Suppression {
+                rule: Rule::from_name(name.as_str()),
+                span: offset + name.start()..offset + name.end(),
+                target: (!file_level).then_some(line),
+            });
+        }
+    }
+    out.extend(pending);
+    out
+}
+
+/// Drops suppressed diagnostics and reports suppressions which did not match any diagnostic.
+/// Suppressions of rules whose provider is not `judged` keep their verdict from `unused`,
+/// returns the remaining diagnostics and the suppressions which are unused now
+pub fn apply_suppressions(
+    err: &[ErrorInfo],
+    suppressions: &[Suppression],
+    source: &Rope,
+    judged: impl Fn(DiagnosticProvider) -> bool,
+    unused: &[Suppression],
+) -> (Vec<ErrorInfo>, Vec<Suppression>) {
+    if suppressions.is_empty() {
+        return (err.to_vec(), Vec::new());
+    }
+    let mut used = vec![false; suppressions.len()];
+    let mut out: Vec<ErrorInfo> = err
+        .iter()
+        .filter(|e| {
+            let mut keep = true;
+            for (s, used) in suppressions.iter().zip(used.iter_mut()) {
+                if s.suppresses(e) {
+                    *used = true;
+                    keep = false;
+                }
+            }
+            keep
+        })
+        .cloned()
+        .collect();
+    let unused: Vec<Suppression> = suppressions
+        .iter()
+        .zip(used)
+        .filter(|(s, used)| match s.rule {
+            Some(rule) if !judged(rule.provider()) => unused
+                .iter()
+                .any(|old| old.rule == s.rule && old.target == s.target),
+            _ => !used,
+        })
+        .map(|(s, _)| s.clone())
+        .collect();
+    for s in unused.iter() {
+        let Some(location) = lsp_range(s.span.clone(), source) else {
+            continue;
+        };
+        let msg = match s.rule {
+            Some(rule) => format!("unused suppression of {}", rule.name()),
+            None => format!("unknown lint rule {}", source.byte_slice(s.span.clone())),
+        };
+        out.push(ErrorInfo {
+            location,
+            severity: Rule::UnusedSuppression.default_severity(),
+            weight: 10,
+            msg,
+            error_type: ErrorType::Any,
+            rule: Rule::UnusedSuppression,
+        });
+    }
+    (out, unused)
+}
+
+fn lint_error(file: &AstDocument, sym: Symbol, rule: Rule, msg: String) -> Option<ErrorInfo> {
+    lint_error_with_type(file, sym, rule, msg, ErrorType::Any)
+}
+fn lint_error_with_type(
+    file: &AstDocument,
+    sym: Symbol,
+    rule: Rule,
+    msg: String,
+    error_type: ErrorType,
+) -> Option<ErrorInfo> {
+    Some(ErrorInfo {
+        location: file.lsp_range(sym)?,
+        severity: rule.default_severity(),
+        weight: 10,
+        msg,
+        error_type,
+        rule,
+    })
+}
+/// Imports without any reference into them
+fn unused_imports(file: &AstDocument, out: &mut Vec<ErrorInfo>) {
+    let used: HashSet<Symbol> = file
+        .all_references()
+        .flat_map(|r| file.lookup_import(file.path(r)).map(|(i, _)| i))
+        .collect();
+    for i in file.all_imports().filter(|i| !used.contains(i)) {
+        out.extend(lint_error_with_type(
+            file,
+            i,
+            Rule::UnusedImport,
+            "unused import".into(),
+            ErrorType::UnusedImport,
+        ));
+    }
+}
+/// Groups without children and alternative/or groups with a single child
+fn group_children(file: &AstDocument, out: &mut Vec<ErrorInfo>) {
+    file.visit_children(Symbol::Root, true, |sym| {
+        if matches!(sym, Symbol::Group(..)) {
+            let children = file
+                .direct_children(sym)
+                .filter(|c| matches!(c, Symbol::Feature(..) | Symbol::Reference(..)))
+                .count();
+            match (children, file.group_mode(sym)) {
+                (0, _) => out.extend(lint_error_with_type(
+                    file,
+                    sym,
+                    Rule::EmptyGroup,
+                    "empty group".into(),
+                    ErrorType::EmptyGroup,
+                )),
+                (1, Some(GroupMode::Alternative | GroupMode::Or)) => {
+                    out.extend(lint_error_with_type(
+                        file,
+                        sym,
+                        Rule::SingleChildGroup,
+                        "group with a single child behaves like mandatory".into(),
+                        ErrorType::SingleChildGroup,
+                    ))
+                }
+                _ => {}
+            }
+        }
+        true
+    });
+}
+fn feature_names(file: &AstDocument, pattern: &Regex, out: &mut Vec<ErrorInfo>) {
+    for f in file.all_features() {
+        let Some(name) = file.name(f) else {
+            continue;
+        };
+        if !pattern.is_match(&name) {
+            out.extend(lint_error(
+                file,
+                f,
+                Rule::FeatureName,
+                format!("{name} does not match the naming convention {pattern}"),
+            ));
+        }
+    }
+}
+/// Attributes with the same key but a different type in other features
+fn attribute_types(file: &AstDocument, out: &mut Vec<ErrorInfo>) {
+    let mut first: HashMap<Vec<Ustr>, (Type, Symbol)> = HashMap::new();
+    let mut conflicts = Vec::new();
+    file.visit_attributes(Symbol::Root, |owner, attrib, prefix| {
+        let Some(ty) = file.type_of(attrib).filter(|ty| *ty != Type::Void) else {
+            return;
+        };
+        match first.get(prefix) {
+            Some((other, other_owner)) if *other != ty => {
+                conflicts.push((attrib, prefix.to_vec(), ty, *other, *other_owner))
+            }
+            Some(_) => {}
+            None => {
+                first.insert(prefix.to_vec(), (ty, owner));
+            }
+        }
+    });
+    for (attrib, key, ty, other, other_owner) in conflicts {
+        let owner = file
+            .name(other_owner)
+            .map(|n| format!(" in {n}"))
+            .unwrap_or_default();
+        out.extend(lint_error(
+            file,
+            attrib,
+            Rule::AttributeType,
+            format!(
+                "attribute {} is {ty:?} here but {other:?}{owner}",
+                key.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(".")
+            ),
+        ));
+    }
+}
+/// First feature of each branch nested deeper than max
+fn tree_depth(file: &AstDocument, max: usize, out: &mut Vec<ErrorInfo>) {
+    let mut stack = vec![(Symbol::Root, 0)];
+    while let Some((sym, depth)) = stack.pop() {
+        for c in file.direct_children(sym) {
+            match c {
+                Symbol::Feature(..) if depth + 1 > max => out.extend(lint_error(
+                    file,
+                    c,
+                    Rule::MaxDepth,
+                    format!("feature is nested deeper than {max} levels"),
+                )),
+                Symbol::Feature(..) => stack.push((c, depth + 1)),
+                Symbol::Group(..) => stack.push((c, depth)),
+                _ => {}
+            }
+        }
+    }
+}
+/// Modeling style checks of a single document
+pub fn lint_file(file: &AstDocument, options: &LintOptions) -> Vec<ErrorInfo> {
+    let mut out = Vec::new();
+    unused_imports(file, &mut out);
+    group_children(file, &mut out);
+    if let Some(pattern) = options.feature_name.as_ref() {
+        feature_names(file, pattern, &mut out);
+    }
+    attribute_types(file, &mut out);
+    tree_depth(file, options.max_depth, &mut out);
+    out
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{Position, Range};
+    fn error(rule: Rule, line: u32) -> ErrorInfo {
+        ErrorInfo {
+            location: Range::new(Position::new(line, 4), Position::new(line, 10)),
+            severity: rule.default_severity(),
+            weight: 10,
+            msg: String::new(),
+            error_type: ErrorType::Any,
+            rule,
+        }
+    }
+    fn messages(err: &[ErrorInfo]) -> Vec<&str> {
+        err.iter()
+            .filter(|e| e.rule == Rule::UnusedSuppression)
+            .map(|e| e.msg.as_str())
+            .collect()
+    }
+    #[test]
+    fn test_suppression_targets() {
+        let source = Rope::from_str(
+            "// uvls-ignore-file uvl::unused-import\nfeatures\n    // uvls-ignore uvl::dead-feature\n\n    // uvls-ignore uvl::tautology, foo\n\n    Engine\n",
+        );
+        let s = suppressions(&source);
+        assert_eq!(s.len(), 4);
+        assert_eq!(s[0].target, None);
+        assert!(s[1..].iter().all(|s| s.target == Some(6)));
+        assert_eq!(s[3].rule, None);
+        assert_eq!(source.byte_slice(s[3].span.clone()).to_string(), "foo");
+    }
+    #[test]
+    fn test_apply_suppressions() {
+        let source = Rope::from_str(
+            "features\n    // uvls-ignore uvl::dead-feature\n    Engine\n    // uvls-ignore uvl::unused-import uvl::foo\n    Wheel\n",
+        );
+        let s = suppressions(&source);
+        let err = vec![error(Rule::DeadFeature, 2), error(Rule::DeadFeature, 4)];
+        let (out, unused) = apply_suppressions(&err, &s, &source, |_| true, &[]);
+        assert_eq!(
+            messages(&out),
+            vec![
+                "unused suppression of uvl::unused-import",
+                "unknown lint rule uvl::foo"
+            ]
+        );
+        assert_eq!(
+            out.iter().filter(|e| e.rule == Rule::DeadFeature).count(),
+            1
+        );
+        assert_eq!(unused.len(), 2);
+    }
+    #[test]
+    fn test_unjudged_suppressions() {
+        let source = Rope::from_str("features\n    // uvls-ignore uvl::dead-feature\n    Engine\n");
+        let s = suppressions(&source);
+        //no smt results yet, the suppression is not reported
+        let (out, unused) =
+            apply_suppressions(&[], &s, &source, |p| p != DiagnosticProvider::Smt, &[]);
+        assert!(messages(&out).is_empty());
+        assert!(unused.is_empty());
+        //the verdict of the last complete results is kept
+        let (out, _) = apply_suppressions(&[], &s, &source, |_| true, &[]);
+        assert_eq!(
+            messages(&out),
+            vec!["unused suppression of uvl::dead-feature"]
+        );
+        let (out, unused) =
+            apply_suppressions(&[], &s, &source, |p| p != DiagnosticProvider::Smt, &s);
+        assert_eq!(
+            messages(&out),
+            vec!["unused suppression of uvl::dead-feature"]
+        );
+        assert_eq!(unused, s);
+    }
+}
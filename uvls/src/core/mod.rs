@@ -5,12 +5,14 @@ pub mod cache;
 pub mod check;
 pub mod config;
 pub mod document;
+pub mod lint;
 pub mod module;
 pub mod parse;
 pub mod pipeline;
 pub mod query;
 pub mod resolve;
 pub mod semantic;
+pub mod settings;
 
 pub mod util;
 pub use ast::*;
@@ -18,9 +20,11 @@ pub use cache::*;
 pub use check::*;
 pub use config::*;
 pub use document::*;
+pub use lint::*;
 pub use log::info;
 pub use module::*;
 pub use parse::*;
 pub use pipeline::*;
 pub use semantic::*;
+pub use settings::*;
 pub use util::*;
@@ -68,6 +68,12 @@ fn iterate_instances<'a>(
     })
 }
 
+/// Number of instances a cardinality feature gets when the configuration does not specify them
+fn default_instances((min, max): (usize, Option<usize>)) -> usize {
+    let n = min.max(1);
+    max.map(|max| n.min(max)).unwrap_or(n)
+}
+
 /// An actual instance of a root file with all subfiles
 ///
 /// A module is basically a depth first iteration of all features and recusive sub file contents
@@ -76,17 +82,29 @@ fn iterate_instances<'a>(
 /// resolution. Since references in diffrent instances have diffrent resolutions, we currently
 /// reresolve references to non local symbols, TODO this can be avoided using a static instance
 /// encoding scheme?.
+///
+/// Features with a feature cardinality are instantiated as well. A cardinality instance shares
+/// the file of its parent instance but only contains the subtree of the feature. Inside its
+/// parent instance the feature subtree acts as template, its features are selected if they are
+/// selected in any instance. Since the number of instances is part of the configuration,
+/// cardinality instances are created on demand and stored after the file instances.
 #[derive(Debug, Clone)]
 pub struct Module {
     instance_files: Vec<FileID>,
-    instances: IndexSet<(InstanceID, Symbol)>,
+    /// (parent, import or cardinality feature, index of the cardinality instance)
+    instances: IndexSet<(InstanceID, Symbol, usize)>,
+    /// Subtree of each instance, Root for file instances
+    instance_roots: Vec<Symbol>,
+    /// Number of active instances for each cardinality feature
+    cardinality: HashMap<ModuleSymbol, usize>,
+    file_instances: usize,
     pub timestamp: Instant,
     pub files: HashMap<FileID, Arc<LinkedAstDocument>>,
     pub ok: bool,
 }
 impl Module {
     pub fn get_instance(&self, instance: InstanceID, sym: Symbol) -> InstanceID {
-        InstanceID(self.instances.get_index_of(&(instance, sym)).unwrap())
+        InstanceID(self.instances.get_index_of(&(instance, sym, 0)).unwrap())
     }
     pub fn new(
         root: FileID,
@@ -96,38 +114,187 @@ impl Module {
         let mut members = fs.recursive_imports(root);
         members.push(root);
         let ok = members.iter().all(|i| files[i].ok);
+        let files = files
+            .iter()
+            .filter(|(k, _)| members.contains(k))
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
         if ok {
             let mut instances = IndexSet::new();
             let mut instance_files = Vec::new();
-            for (origin, _, file, _) in iterate_instances(root, files) {
-                instances.insert((origin.instance, origin.sym));
+            for (origin, _, file, _) in iterate_instances(root, &files) {
+                instances.insert((origin.instance, origin.sym, 0));
                 instance_files.push(file);
             }
-            Module {
-                files: files
-                    .iter()
-                    .filter(|(k, _)| members.contains(k))
-                    .map(|(k, v)| (*k, v.clone()))
-                    .collect(),
+            let mut module = Module {
+                file_instances: instance_files.len(),
+                instance_roots: vec![Symbol::Root; instance_files.len()],
+                cardinality: HashMap::new(),
+                files,
                 instance_files,
                 instances,
                 timestamp: Instant::now(),
                 ok,
-            }
+            };
+            module.instantiate_defaults();
+            module
         } else {
             Module {
-                files: files
-                    .iter()
-                    .filter(|(k, _)| members.contains(k))
-                    .map(|(k, v)| (*k, v.clone()))
-                    .collect(),
+                files,
                 instance_files: [root].into(),
                 instances: IndexSet::new(),
+                instance_roots: vec![Symbol::Root],
+                cardinality: HashMap::new(),
+                file_instances: 1,
                 timestamp: Instant::now(),
                 ok,
             }
         }
     }
+    /// A copy of this module where each cardinality feature has its default instances
+    pub fn template(&self) -> Module {
+        let n = self.file_instances;
+        let mut module = Module {
+            instance_files: self.instance_files[..n].to_vec(),
+            instances: self.instances.iter().take(n).cloned().collect(),
+            instance_roots: self.instance_roots[..n].to_vec(),
+            cardinality: HashMap::new(),
+            file_instances: n,
+            timestamp: self.timestamp,
+            files: self.files.clone(),
+            ok: self.ok,
+        };
+        if module.ok {
+            module.instantiate_defaults();
+        }
+        module
+    }
+    fn instantiate_defaults(&mut self) {
+        for i in 0..self.file_instances {
+            self.instantiate(InstanceID(i));
+        }
+    }
+    /// Create the default instances for the outermost cardinality features in instance
+    fn instantiate(&mut self, instance: InstanceID) {
+        let file = self.file(instance);
+        let mut features = Vec::new();
+        let mut stack: Vec<Symbol> = file
+            .direct_children(self.instance_roots[instance.0])
+            .collect();
+        while let Some(sym) = stack.pop() {
+            if let Some(bounds) = file.cardinality(sym) {
+                features.push((sym, default_instances(bounds)));
+            } else {
+                stack.extend(file.direct_children(sym));
+            }
+        }
+        for (sym, n) in features {
+            self.set_instance_count(instance.sym(sym), n);
+        }
+    }
+    /// Set the number of instances of a cardinality feature, missing instances are created.
+    /// Removed instances are kept inactive so instance ids remain stable.
+    pub fn set_instance_count(&mut self, feature: ModuleSymbol, count: usize) {
+        self.cardinality.insert(feature, count);
+        for k in 0..count {
+            let key = (feature.instance, feature.sym, k);
+            if self.instances.contains(&key) {
+                continue;
+            }
+            let (id, _) = self.instances.insert_full(key);
+            self.instance_files
+                .push(self.instance_files[feature.instance.0]);
+            self.instance_roots.push(feature.sym);
+            self.instantiate(InstanceID(id));
+        }
+    }
+    /// Number of active instances of a cardinality feature
+    pub fn instance_count(&self, feature: ModuleSymbol) -> usize {
+        self.cardinality.get(&feature).cloned().unwrap_or(0)
+    }
+    /// Active instances of a cardinality feature in order
+    pub fn instances_of(&self, feature: ModuleSymbol) -> impl Iterator<Item = InstanceID> + '_ {
+        (0..self.instance_count(feature)).map(move |k| {
+            InstanceID(
+                self.instances
+                    .get_index_of(&(feature.instance, feature.sym, k))
+                    .unwrap(),
+            )
+        })
+    }
+    /// Subtree covered by an instance, Root for file instances
+    pub fn instance_root(&self, instance: InstanceID) -> Symbol {
+        self.instance_roots[instance.0]
+    }
+    /// Cardinality feature in the parent instance and index for cardinality instances
+    pub fn cardinality_origin(&self, instance: InstanceID) -> Option<(ModuleSymbol, usize)> {
+        if instance.0 < self.file_instances {
+            None
+        } else {
+            let (parent, sym, k) = self.instances[instance.0];
+            Some((parent.sym(sym), k))
+        }
+    }
+    fn is_active(&self, instance: InstanceID) -> bool {
+        match self.cardinality_origin(instance) {
+            Some((feature, k)) => {
+                k < self.instance_count(feature) && self.is_active(feature.instance)
+            }
+            None => true,
+        }
+    }
+    /// File instance a cardinality instance belongs to
+    pub fn file_instance(&self, mut instance: InstanceID) -> InstanceID {
+        while let Some((feature, _)) = self.cardinality_origin(instance) {
+            instance = feature.instance;
+        }
+        instance
+    }
+    /// Innermost instance containing sym, starting at instance
+    pub fn scope_instance(&self, mut instance: InstanceID, sym: Symbol) -> InstanceID {
+        let file = self.file(instance);
+        while let Some((feature, _)) = self.cardinality_origin(instance) {
+            if file
+                .cardinality_path(self.instance_root(instance), sym)
+                .is_some()
+            {
+                break;
+            }
+            instance = feature.instance;
+        }
+        instance
+    }
+    /// Cardinality features between the instance root and the symbol, the symbol is part of a
+    /// template when this is not empty
+    pub fn template_path(&self, ms: ModuleSymbol) -> Vec<Symbol> {
+        self.file(ms.instance)
+            .cardinality_path(self.instance_root(ms.instance), ms.sym)
+            .unwrap_or_default()
+    }
+    /// True if sym is part of the instance and not of a template inside the instance
+    pub fn owns(&self, instance: InstanceID, sym: Symbol) -> bool {
+        self.file(instance)
+            .cardinality_path(self.instance_root(instance), sym)
+            .map(|path| path.is_empty())
+            .unwrap_or(false)
+    }
+    /// All features of an instance including templates
+    pub fn features(&self, instance: InstanceID) -> impl Iterator<Item = Symbol> + '_ {
+        let file = self.file(instance);
+        let root = self.instance_root(instance);
+        file.all_features()
+            .filter(move |f| file.cardinality_path(root, *f).is_some())
+    }
+    /// The symbols a template symbol stands for in the active instances
+    pub fn instantiations(&self, ms: ModuleSymbol) -> Vec<ModuleSymbol> {
+        match self.template_path(ms).first() {
+            Some(&feature) => self
+                .instances_of(ms.instance.sym(feature))
+                .flat_map(|i| self.instantiations(i.sym(ms.sym)))
+                .collect(),
+            None => vec![ms],
+        }
+    }
     /// Resolves references inside this module
     pub fn resolve_value(&self, src_sym: ModuleSymbol) -> ModuleSymbol {
         assert!(self.ok);
@@ -139,14 +306,14 @@ impl Module {
                 //Fast path
                 if tgt.file == src_file.content.id {
                     return ModuleSymbol {
-                        instance: src_sym.instance,
+                        instance: self.scope_instance(src_sym.instance, tgt.sym),
                         sym: tgt.sym,
                     };
                 }
                 let tgt_file = &self.files[&tgt.file];
                 let path = src_file.content.path(src_sym.sym);
                 let instance_path = &path[0..path.len() - tgt_file.content.depth(tgt.sym) as usize];
-                let mut stack = vec![(instance_path, src_sym.instance)];
+                let mut stack = vec![(instance_path, self.file_instance(src_sym.instance))];
 
                 while let Some((prefix, instance)) = stack.pop() {
                     let file = self.file(instance);
@@ -168,10 +335,10 @@ impl Module {
             _ => panic!("{src_sym:?} not a value"),
         }
     }
-    /// Bind a recursive configuration doc to a linear set of symbols
-    /// Add layer for cardinality
+    /// Bind a recursive configuration doc to a linear set of symbols, the instances of
+    /// cardinality features are created as listed in the configuration
     pub fn resolve_config<E: FnMut(Span, String)>(
-        &self,
+        &mut self,
         doc: &Vec<ConfigEntry>,
         mut err: E,
     ) -> (
@@ -181,106 +348,95 @@ impl Module {
         assert!(self.ok);
         let mut out = HashMap::new();
         let mut out_span = HashMap::new();
-        // offset is used for mapping to different entities of a cardinality and its children
-        let mut stack = vec![(InstanceID(0), doc.as_slice(), 0 as usize)];
-        while let Some((instance, config, offset)) = stack.pop() {
-            let file = self.file(instance);
+        let mut stack = vec![(InstanceID(0), doc.as_slice())];
+        while let Some((instance, config)) = stack.pop() {
             for c in config.iter() {
                 match c {
-                    ConfigEntry::Value(path, val) => match val {
-                        ConfigValue::Cardinality(cardinality) => match cardinality {
-                            CardinalityEntry::CardinalityLvl(cardinality_lvl) => {
-                                let entries = file.get_all_entities(&path.names.clone());
-                                let entries = entries.iter().nth(offset);
-                                match entries {
-                                    Some(Symbol::Feature(id)) => {
-                                        let feature = file.get_feature(id.clone()).unwrap();
-                                        match feature.cardinality {
-                                            Some(Cardinality::Range(_, max)) => {
-                                                for i in 0..cardinality_lvl.len() {
-                                                    stack.push((
-                                                        instance,
-                                                        cardinality_lvl.iter().nth(i).unwrap(),
-                                                        offset * max + i,
-                                                    ));
-                                                }
-                                            }
-                                            _ => (),
-                                        }
-                                    }
-                                    _ => panic!("unexpected feature"),
+                    ConfigEntry::Value(path, val) => {
+                        let file = self.file(instance);
+                        let Some(sym) = file
+                            .lookup(Symbol::Root, &path.names, |_| true)
+                            .find(|sym| matches!(sym, Symbol::Feature(..) | Symbol::Attribute(..)))
+                        else {
+                            err(path.range(), format!("unresolved value"));
+                            continue;
+                        };
+                        let ms = self.scope_instance(instance, sym).sym(sym);
+                        let template = self.template_path(ms);
+                        let ty = file.type_of(sym).unwrap();
+                        let bounds = file.cardinality(sym);
+                        match val {
+                            ConfigValue::Cardinality(CardinalityEntry::CardinalityLvl(entries)) => {
+                                let Some((_, max)) = bounds else {
+                                    err(
+                                        path.range(),
+                                        format!(
+                                            "{} has no feature cardinality",
+                                            file.name(sym).unwrap()
+                                        ),
+                                    );
+                                    continue;
+                                };
+                                if template.len() > 1 {
+                                    err(
+                                        path.range(),
+                                        format!(
+                                            "{} is part of the instances of {}",
+                                            file.name(sym).unwrap(),
+                                            file.name(template[0]).unwrap()
+                                        ),
+                                    );
+                                    continue;
+                                }
+                                if let Some(max) = max.filter(|max| entries.len() > *max) {
+                                    err(
+                                        path.range(),
+                                        format!(
+                                            "expected at most {} instances got {}",
+                                            max,
+                                            entries.len()
+                                        ),
+                                    );
+                                    continue;
+                                }
+                                self.set_instance_count(ms, entries.len());
+                                out_span.insert(ms, path.range());
+                                for (i, entries) in self.instances_of(ms).zip(entries.iter()) {
+                                    stack.push((i, entries.as_slice()));
                                 }
                             }
-                            CardinalityEntry::EntitiyLvl(_) => {
-                                panic!("unexpected cardinality level")
+                            ConfigValue::Cardinality(CardinalityEntry::EntitiyLvl(_)) => {
+                                err(path.range(), format!("unexpected instance"));
                             }
-                        },
-                        _ => {
-                            if let Some(sym_ref) =
-                                file.get_all_entities(&path.names).iter().nth(offset)
-                            {
-                                let sym = sym_ref.clone();
-                                if file.type_of(sym).unwrap() == val.ty() {
-                                    out.insert(ModuleSymbol { instance, sym }, val.clone());
-                                    out_span.insert(ModuleSymbol { instance, sym }, path.range());
-                                } else {
-                                    match sym {
-                                        Symbol::Feature(i) => {
-                                            let feature = file.get_feature(i).unwrap().clone();
-                                            if let Cardinality::Range(_, _) =
-                                                feature.cardinality.unwrap()
-                                            {
-                                                out.insert(
-                                                    ModuleSymbol { instance, sym },
-                                                    val.clone(),
-                                                );
-                                                out_span.insert(
-                                                    ModuleSymbol { instance, sym },
-                                                    path.range(),
-                                                );
-                                            } else {
-                                                err(
-                                                    path.range(),
-                                                    format!(
-                                                        "expected {} got {}",
-                                                        file.type_of(sym).unwrap(),
-                                                        val.ty()
-                                                    ),
-                                                );
-                                            }
-                                        }
-                                        Symbol::Attribute(_) => {
-                                            out.insert(ModuleSymbol { instance, sym }, val.clone());
-                                            out_span.insert(
-                                                ModuleSymbol { instance, sym },
-                                                path.range(),
-                                            );
-                                        }
-                                        _ => {
-                                            err(
-                                                path.range(),
-                                                format!(
-                                                    "expected {} got {}",
-                                                    file.type_of(sym).unwrap(),
-                                                    val.ty()
-                                                ),
-                                            );
-                                        }
-                                    }
-                                }
-                            } else {
-                                err(path.range(), format!("unresolved value",));
+                            _ if !template.is_empty() => {
+                                err(
+                                    path.range(),
+                                    format!(
+                                        "{} is part of the instances of {}",
+                                        file.name(sym).unwrap(),
+                                        file.name(template[0]).unwrap()
+                                    ),
+                                );
+                            }
+                            _ if ty == val.ty() => {
+                                out.insert(ms, val.clone());
+                                out_span.insert(ms, path.range());
+                            }
+                            _ => {
+                                err(path.range(), format!("expected {} got {}", ty, val.ty()));
                             }
                         }
-                    },
+                    }
                     ConfigEntry::Import(path, val) => {
-                        if let Some(sym) = file
+                        let instance = self.file_instance(instance);
+                        if let Some(sym) = self
+                            .file(instance)
                             .lookup(Symbol::Root, &path.names, |sym| {
                                 matches!(sym, Symbol::Import(..) | Symbol::Dir(..))
                             })
                             .find(|sym| matches!(sym, Symbol::Import(..)))
                         {
-                            stack.push((self.get_instance(instance, sym), &val, offset));
+                            stack.push((self.get_instance(instance, sym), &val));
                         } else {
                             err(path.range(), format!("unresolved import",));
                         }
@@ -302,13 +458,14 @@ impl Module {
             .type_of(sym.sym)
             .unwrap()
     }
-    /// Visit all instances in the module
+    /// Visit all active instances in the module
     pub fn instances<'a>(&'a self) -> impl Iterator<Item = (InstanceID, &'a AstDocument)> {
         assert!(self.ok);
         self.instance_files
             .iter()
             .enumerate()
             .map(|(i, k)| (InstanceID(i), &*self.files[k].content))
+            .filter(|(i, _)| self.is_active(*i))
     }
     pub fn instances_depth<'a>(
         &'a self,
@@ -326,17 +483,17 @@ pub struct ConfigModule {
     pub source_map: HashMap<ModuleSymbol, Span>,
 }
 impl ConfigModule {
-    fn serialize_rec(&self, path: &[Ustr], i: InstanceID) -> ConfigEntry {
+    fn serialize_rec(&self, path: &[Ustr], i: InstanceID, skip: Option<InstanceID>) -> ConfigEntry {
         let file = self.file(i);
         let mut entries = Vec::new();
         for im in file.all_imports() {
-            let entry = self.serialize_rec(file.import_prefix(im), self.get_instance(i, im));
+            let entry = self.serialize_rec(file.import_prefix(im), self.get_instance(i, im), skip);
             if !entry.is_empty() {
                 entries.push(entry);
             }
         }
 
-        entries.append(&mut self.serialize_rec_file(Symbol::Root, file, i));
+        entries.append(&mut self.serialize_rec_file(Symbol::Root, file, i, skip));
         ConfigEntry::Import(
             Path {
                 names: path.to_vec(),
@@ -346,66 +503,60 @@ impl ConfigModule {
         )
     }
 
-    /// serialize file recursive while accommodate for cardinality
+    /// serialize file recursive, cardinality features are serialized as list of their instances
     fn serialize_rec_file(
         &self,
         sym: Symbol,
         file: &AstDocument,
         i: InstanceID,
+        skip: Option<InstanceID>,
     ) -> Vec<ConfigEntry> {
         let mut entries: Vec<ConfigEntry> = Vec::new();
-        // used for different entities of cardinality
-        let mut child_map: HashMap<Ustr, Vec<Vec<ConfigEntry>>> = hashbrown::HashMap::new();
-
         for child in file.direct_children(sym) {
             match child {
-                Symbol::Feature(id) => {
-                    let feature = file.get_feature(id).unwrap();
-                    match feature.cardinality {
-                        Some(Cardinality::Fixed) => {
-                            if let Some(config) = self.values.get(&i.sym(child)) {
+                Symbol::Feature(..) if file.cardinality(child).is_some() => {
+                    let feature = i.sym(child);
+                    let instances: Vec<Vec<ConfigEntry>> = self
+                        .instances_of(feature)
+                        .filter(|j| Some(*j) != skip)
+                        .map(|j| {
+                            let mut entries = Vec::new();
+                            if let Some(config) = self.values.get(&j.sym(child)) {
                                 entries.push(ConfigEntry::Value(
                                     Path {
                                         names: vec![file.name(child).unwrap()],
                                         spans: Vec::new(),
                                     },
                                     config.clone(),
-                                ))
-                            }
-                            entries.append(&mut self.serialize_rec_file(child, file, i));
-                        }
-                        Some(Cardinality::Range(_, _)) => {
-                            let mut cardinal_entry: Vec<ConfigEntry> = vec![];
-                            // add self to cardinality definition
-                            if let Some(config) = self.values.get(&i.sym(child)) {
-                                cardinal_entry.push(ConfigEntry::Value(
-                                    Path {
-                                        names: vec![file.name(child).unwrap()],
-                                        spans: Vec::new(),
-                                    },
-                                    config.clone(),
                                 ));
                             }
-                            cardinal_entry.append(&mut self.serialize_rec_file(child, file, i));
-                            if cardinal_entry.len() > 0 {
-                                child_map
-                                    .get_mut(&file.name(child).unwrap())
-                                    .and_then(|x| {
-                                        x.push(cardinal_entry.clone());
-                                        Some(())
-                                    })
-                                    .or_else(|| {
-                                        child_map.insert(
-                                            file.name(child).unwrap(),
-                                            vec![cardinal_entry],
-                                        );
-                                        Some(())
-                                    });
-                            }
-                        }
-                        None => (),
+                            entries.append(&mut self.serialize_rec_file(child, file, j, skip));
+                            entries
+                        })
+                        .collect();
+                    let default = default_instances(file.cardinality(child).unwrap());
+                    if instances.len() != default || instances.iter().any(|e| !e.is_empty()) {
+                        entries.push(ConfigEntry::Value(
+                            Path {
+                                names: vec![file.name(child).unwrap()],
+                                spans: Vec::new(),
+                            },
+                            ConfigValue::Cardinality(CardinalityEntry::CardinalityLvl(instances)),
+                        ));
                     }
                 }
+                Symbol::Feature(..) => {
+                    if let Some(config) = self.values.get(&i.sym(child)) {
+                        entries.push(ConfigEntry::Value(
+                            Path {
+                                names: vec![file.name(child).unwrap()],
+                                spans: Vec::new(),
+                            },
+                            config.clone(),
+                        ))
+                    }
+                    entries.append(&mut self.serialize_rec_file(child, file, i, skip));
+                }
                 Symbol::Attribute(_) => {
                     if let Some(config) = self.values.get(&i.sym(child)) {
                         entries.push(ConfigEntry::Value(
@@ -416,35 +567,38 @@ impl ConfigModule {
                             config.clone(),
                         ))
                     }
-                    entries.append(&mut self.serialize_rec_file(child, file, i));
+                    entries.append(&mut self.serialize_rec_file(child, file, i, skip));
                 }
                 _ => {
-                    entries.append(&mut self.serialize_rec_file(child, file, i));
+                    entries.append(&mut self.serialize_rec_file(child, file, i, skip));
                 }
             }
         }
-        for (cardinality_name, cardinality_childs) in child_map.iter() {
-            entries.push(ConfigEntry::Value(
-                Path {
-                    names: vec![cardinality_name.clone()],
-                    spans: Vec::new(),
-                },
-                ConfigValue::Cardinality(CardinalityEntry::CardinalityLvl(
-                    cardinality_childs.clone(),
-                )),
-            ))
-        }
-        return entries;
+        entries
     }
 
     /// Turns a the set of linear configuration values of this module into theire recusive from
     /// used in json
     pub fn serialize(&self) -> Vec<ConfigEntry> {
-        let ConfigEntry::Import(_, v) = self.serialize_rec(&[], InstanceID(0)) else {
+        let ConfigEntry::Import(_, v) = self.serialize_rec(&[], InstanceID(0), None) else {
             unreachable!()
         };
         v
     }
+    /// Configuration without a cardinality instance, the following instances move up
+    pub fn remove_instance(&self, instance: InstanceID) -> ConfigModule {
+        let ConfigEntry::Import(_, ser) = self.serialize_rec(&[], InstanceID(0), Some(instance))
+        else {
+            unreachable!()
+        };
+        let mut module = self.module.template();
+        let (values, source_map) = module.resolve_config(&ser, |_, _| {});
+        ConfigModule {
+            module: Arc::new(module),
+            values,
+            source_map,
+        }
+    }
 }
 impl std::ops::Deref for ConfigModule {
     type Target = Module;
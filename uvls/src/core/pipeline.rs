@@ -25,6 +25,7 @@ use hashbrown::HashMap;
 use log::info;
 use ropey::Rope;
 use std::{
+    path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -45,20 +46,40 @@ enum DraftMsg {
     Snapshot(oneshot::Sender<Draft>),
     Shutdown, //Not really needed, TODO remove this
 }
+/// Last red tree of a draft, used to transform the next version incrementally
+type LastAst = Arc<parking_lot::Mutex<Option<Arc<ast::AstDocument>>>>;
 /// Turn a tree-sitter trees into a usable rust structure and send it to the linker
-async fn make_red_tree(draft: Draft, uri: Url, tx_link: mpsc::Sender<LinkMsg>) {
+async fn make_red_tree(draft: Draft, uri: Url, tx_link: mpsc::Sender<LinkMsg>, last: LastAst) {
     info!("update red tree {uri}");
     match draft {
         Draft::UVL {
             timestamp,
             source,
             tree,
+            edits,
         } => {
-            let mut ast =
-                ast::AstDocument::new(source.clone(), tree.clone(), uri.clone(), timestamp);
+            let old = last.lock().clone();
+            let mut ast = match old {
+                Some(old) if old.timestamp < timestamp => ast::AstDocument::update(
+                    source.clone(),
+                    tree.clone(),
+                    uri.clone(),
+                    timestamp,
+                    &old,
+                    &edits,
+                ),
+                _ => ast::AstDocument::new(source.clone(), tree.clone(), uri.clone(), timestamp),
+            };
             ast.errors.append(&mut check::check_sanity(&tree, &source));
             ast.errors.append(&mut check::check_errors(&tree, &source));
-            let _ = tx_link.send(LinkMsg::UpdateAst(Arc::new(ast))).await;
+            let ast = Arc::new(ast);
+            {
+                let mut last = last.lock();
+                if last.as_ref().map_or(true, |old| old.timestamp < timestamp) {
+                    *last = Some(ast.clone());
+                }
+            }
+            let _ = tx_link.send(LinkMsg::UpdateAst(ast)).await;
         }
         Draft::JSON {
             tree,
@@ -94,10 +115,17 @@ async fn draft_handler(
             tree: parse::parse(&rope, None),
             source: rope,
             timestamp: initial_timestamp,
+            edits: EditLog::new(initial_timestamp),
         }
     };
     info!("started draft handler {uri}");
-    spawn(make_red_tree(draft.clone(), uri.clone(), tx_link.clone()));
+    let last: LastAst = Default::default();
+    spawn(make_red_tree(
+        draft.clone(),
+        uri.clone(),
+        tx_link.clone(),
+        last.clone(),
+    ));
     while let Some(msg) = rx.recv().await {
         match msg {
             DraftMsg::Delete(timestamp) => {
@@ -113,9 +141,12 @@ async fn draft_handler(
                     Draft::UVL {
                         mut source,
                         mut tree,
+                        mut edits,
                         ..
                     } => {
-                        let whole_file = update_text(&mut source, Some(&mut tree), params);
+                        let changes = update_text(&mut source, Some(&mut tree), params);
+                        let whole_file = changes.is_none();
+                        edits.record(timestamp, changes);
                         Draft::UVL {
                             timestamp,
                             tree: parse::parse(
@@ -123,6 +154,7 @@ async fn draft_handler(
                                 if whole_file { None } else { Some(&tree) },
                             ),
                             source,
+                            edits,
                         }
                     }
                     Draft::JSON {
@@ -130,7 +162,8 @@ async fn draft_handler(
                         mut tree,
                         ..
                     } => {
-                        let whole_file = update_text(&mut source, Some(&mut tree), params);
+                        let whole_file =
+                            update_text(&mut source, Some(&mut tree), params).is_none();
                         Draft::JSON {
                             timestamp,
                             tree: parse::parse_json(
@@ -141,7 +174,12 @@ async fn draft_handler(
                         }
                     }
                 };
-                spawn(make_red_tree(draft.clone(), uri.clone(), tx_link.clone()));
+                spawn(make_red_tree(
+                    draft.clone(),
+                    uri.clone(),
+                    tx_link.clone(),
+                    last.clone(),
+                ));
             }
             DraftMsg::Snapshot(out) => {
                 let _ = out.send(draft.clone());
@@ -166,6 +204,9 @@ async fn link_handler(
     mut rx: mpsc::Receiver<LinkMsg>,
     tx_cache: watch::Sender<Arc<RootGraph>>,
     tx_err: mpsc::Sender<DiagnosticUpdate>,
+    mut rx_lint: watch::Receiver<WorkspaceLints>,
+    mut rx_smt: watch::Receiver<smt::SmtOptions>,
+    mut rx_workspace: watch::Receiver<ImportRoots>,
 ) {
     //First we gather changes to avoid redundant recomputation
     let mut latest_configs: HashMap<FileID, Arc<config::ConfigDocument>> = HashMap::new();
@@ -175,7 +216,14 @@ async fn link_handler(
     let mut dirty = false;
     let mut revision = 0; //Each change is one revision
     info!("started link handler");
-    spawn(link_executor(rx_execute, tx_cache, tx_err));
+    spawn(link_executor(
+        rx_execute,
+        tx_cache,
+        tx_err,
+        rx_lint.clone(),
+        rx_smt.clone(),
+        rx_workspace.clone(),
+    ));
     let mut timer = tokio::time::interval(tokio::time::Duration::from_millis(100));
     loop {
         select! {
@@ -218,6 +266,21 @@ async fn link_handler(
 
                 }
             }
+            Ok(())=rx_lint.changed()=>{//lint settings changed, relink to update lints
+                rx_lint.borrow_and_update();
+                revision +=1;
+                dirty=true;
+            }
+            Ok(())=rx_smt.changed()=>{//smt settings changed, relink to rerun the analysis
+                rx_smt.borrow_and_update();
+                revision +=1;
+                dirty=true;
+            }
+            Ok(())=rx_workspace.changed()=>{//import roots changed, imports may resolve differently
+                rx_workspace.borrow_and_update();
+                revision +=1;
+                dirty=true;
+            }
             _=timer.tick()=>{//every 100ms relink if there are changes
                 if dirty{
                     info!("link prepare");
@@ -240,6 +303,9 @@ async fn link_handler(
         )>,
         tx_cache: watch::Sender<Arc<RootGraph>>,
         tx_err: mpsc::Sender<DiagnosticUpdate>,
+        mut rx_lint: watch::Receiver<WorkspaceLints>,
+        mut rx_smt: watch::Receiver<smt::SmtOptions>,
+        mut rx_workspace: watch::Receiver<ImportRoots>,
     ) {
         let mut timestamps: HashMap<FileID, Instant> = HashMap::new();
         info!("started link execute");
@@ -250,6 +316,16 @@ async fn link_handler(
             info!("link execute");
             tx_cache.borrow().cancel();
             let (ast, configs, revision) = (*rx.borrow_and_update()).clone();
+            if rx_lint.has_changed().unwrap_or(false)
+                || rx_smt.has_changed().unwrap_or(false)
+                || rx_workspace.has_changed().unwrap_or(false)
+            {
+                //relink all files
+                timestamps.clear();
+            }
+            rx_smt.borrow_and_update();
+            let lints = rx_lint.borrow_and_update().clone();
+            let import_roots = rx_workspace.borrow_and_update().clone();
             let mut err = ErrorsAcc {
                 files: &ast,
                 configs: &configs,
@@ -258,13 +334,29 @@ async fn link_handler(
             let old = tx_cache.borrow().cache().clone();
 
             //link files incrementally
-            let root = RootGraph::new(&ast, &configs, revision, &old, &mut err, &mut timestamps);
+            let root = RootGraph::new(
+                &ast,
+                &configs,
+                revision,
+                &import_roots,
+                &old,
+                &mut err,
+                &mut timestamps,
+            );
+            //style lints for all files with new diagnostics
+            for (id, errors) in err.errors.iter_mut() {
+                if let Some(file) = ast.get(id) {
+                    errors.append(&mut lint_file(file, lints.of(*id, root.fs())));
+                }
+            }
 
             let _ = tx_cache.send(Arc::new(root));
             let _ = tx_err
                 .send(DiagnosticUpdate {
                     timestamp: revision,
                     error_state: err.errors,
+                    producer: Producer::Link,
+                    complete: Default::default(),
                 })
                 .await;
         }
@@ -287,6 +379,14 @@ pub struct AsyncPipeline {
     client: tower_lsp::Client,
     //code inlays are managed globally
     inlay_handler: InlayHandler,
+    //limits of the smt analysis
+    tx_smt_options: Arc<watch::Sender<smt::SmtOptions>>,
+    //severity overrides of lint rules
+    tx_lint_options: Arc<watch::Sender<WorkspaceLints>>,
+    //workspace folders and libraries imports are resolved against
+    tx_workspace: Arc<watch::Sender<ImportRoots>>,
+    //latest diagnostics for pull requests
+    diagnostics: DiagnosticStore,
 }
 impl AsyncPipeline {
     pub fn new(client: tower_lsp::Client) -> Self {
@@ -296,15 +396,37 @@ impl AsyncPipeline {
         let revision_counter = Arc::new(AtomicU64::new(0));
         let (tx_dirty, _) = broadcast::channel(1024);
         let inlay_handler = InlayHandler::new(client.clone());
-        spawn(link_handler(rx_link, tx_root, tx_err.clone()));
-        spawn(check::diagnostic_handler(rx_err, client.clone()));
+        let (tx_smt_options, rx_smt_options) = watch::channel(smt::SmtOptions::default());
+        let (tx_lint_options, rx_lint_options) = watch::channel(WorkspaceLints::default());
+        let (tx_workspace, rx_workspace) = watch::channel(ImportRoots::default());
+        spawn(link_handler(
+            rx_link,
+            tx_root,
+            tx_err.clone(),
+            rx_lint_options.clone(),
+            rx_smt_options.clone(),
+            rx_workspace,
+        ));
+        let diagnostics = DiagnosticStore::default();
+        spawn(check::diagnostic_handler(
+            rx_err,
+            rx_lint_options,
+            rx_root.clone(),
+            diagnostics.clone(),
+            client.clone(),
+        ));
         spawn(smt::check_handler(
             rx_root.clone(),
             tx_err.clone(),
             client.clone(),
             inlay_handler.clone(),
+            rx_smt_options,
         ));
         AsyncPipeline {
+            tx_smt_options: Arc::new(tx_smt_options),
+            tx_lint_options: Arc::new(tx_lint_options),
+            tx_workspace: Arc::new(tx_workspace),
+            diagnostics,
             inlay_handler,
             client,
             tx_dirty_tree: tx_dirty,
@@ -331,6 +453,39 @@ impl AsyncPipeline {
     pub fn client(&self) -> tower_lsp::Client {
         self.client.clone()
     }
+    pub fn smt_options(&self) -> watch::Receiver<smt::SmtOptions> {
+        self.tx_smt_options.subscribe()
+    }
+    pub fn set_smt_options(&self, options: smt::SmtOptions) {
+        self.tx_smt_options.send_replace(options);
+    }
+    pub fn lint_options(&self) -> watch::Receiver<WorkspaceLints> {
+        self.tx_lint_options.subscribe()
+    }
+    pub fn diagnostics(&self) -> &DiagnosticStore {
+        &self.diagnostics
+    }
+    pub fn set_lint_options(&self, options: WorkspaceLints) {
+        self.tx_lint_options.send_replace(options);
+    }
+    pub fn set_workspace_folders(&self, folders: Vec<PathBuf>) {
+        self.tx_workspace.send_if_modified(|old| {
+            let changed = old.folders != folders;
+            old.folders = folders;
+            changed
+        });
+    }
+    pub fn set_library_paths(&self, libraries: Vec<PathBuf>) {
+        self.tx_workspace.send_if_modified(|old| {
+            let changed = old.libraries != libraries;
+            old.libraries = libraries;
+            changed
+        });
+    }
+    /// uris of all known documents
+    pub fn documents(&self) -> Vec<Url> {
+        self.drafts.iter().map(|i| i.key().clone()).collect()
+    }
     pub fn subscribe_dirty_tree(&self) -> broadcast::Receiver<()> {
         self.tx_dirty_tree.subscribe()
     }
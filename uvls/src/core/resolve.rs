@@ -52,6 +52,30 @@ pub fn resolve<'a>(
     .flatten()
 }
 
+/// Find all symboles from origin under path, when scope is a feature its attributes are
+/// searched first. This is used for feature local constraints.
+pub fn resolve_scoped<'a>(
+    files: &'a impl AstContainer,
+    fs: &'a FileSystem,
+    origin: FileID,
+    scope: Symbol,
+    path: &'a [Ustr],
+) -> impl Iterator<Item = RootSymbol> + 'a {
+    let local = match scope {
+        Symbol::Feature(..) => Some(
+            files
+                .get(origin)
+                .lookup(scope, path, |_| true)
+                .map(move |sym| RootSymbol { file: origin, sym }),
+        ),
+        _ => None,
+    };
+    local
+        .into_iter()
+        .flatten()
+        .chain(resolve(files, fs, origin, path))
+}
+
 /// Find all symboles from origin under path while keeping track of what sections path are bound to what symbol
 pub fn resolve_with_bind<'a>(
     files: &'a impl AstContainer,
@@ -168,7 +192,14 @@ impl<'a> TypeResolveContext<'a> {
         resolve(self.files, &self.fs, origin, path)
     }
     pub fn resolve_sym<'d>(&'d self, sym: RootSymbol) -> impl Iterator<Item = RootSymbol> + 'd {
-        self.resolve(sym.file, self.file(sym.file).path(sym.sym))
+        let file = self.file(sym.file);
+        resolve_scoped(
+            self.files,
+            self.fs,
+            sym.file,
+            file.reference_scope(sym.sym),
+            file.path(sym.sym),
+        )
     }
     pub fn type_of(&self, sym: RootSymbol) -> Option<Type> {
         self.file(sym.file).type_of(sym.sym)
@@ -217,9 +248,15 @@ pub fn resolve_file(file: FileID, fs: &FileSystem, err: &mut ErrorsAcc) -> RefMa
             }
             if !ok {
                 if found_some {
-                    err.sym(r, file, 30, "expected a feature");
+                    err.sym_rule(r, file, 30, Rule::Type, "expected a feature");
                 } else {
-                    err.sym(r, file, 30, "unresolved reference");
+                    err.sym_rule(
+                        r,
+                        file,
+                        30,
+                        Rule::UnresolvedReference,
+                        "unresolved reference",
+                    );
                 }
             }
         }
@@ -271,14 +308,18 @@ fn resolve_constraint(
                     );
                 }
                 ResolveState::WrongType { expected, found } => {
-                    err.sym(
+                    err.sym_rule(
                         *sym,
                         file,
                         30,
+                        Rule::Type,
                         format!("expected {:?} found {:?}", expected, found),
                     );
                 }
                 ResolveState::Resolved(tgt) => {
+                    if matches!(tgt.sym, Symbol::Attribute(..)) {
+                        check_instance_access(ctx, file, *sym, tgt, err);
+                    }
                     ref_map.insert(*sym, tgt);
                 }
             }
@@ -306,10 +347,11 @@ fn resolve_constraint(
             }
 
             if (rhs_ty & lhs_ty).is_empty() {
-                err.span(
+                err.span_rule(
                     constraint.span.clone(),
                     file,
                     30,
+                    Rule::Type,
                     format!(
                         "type missmatch {:?} and {:?}",
                         select_type(lhs_ty),
@@ -341,10 +383,11 @@ fn resolve_constraint(
                 );
             }
             if ty.is_empty() {
-                err.span(
+                err.span_rule(
                     constraint.span.clone(),
                     file,
                     30,
+                    Rule::Type,
                     format!(
                         "unsupported operand type {:?}",
                         select_type(lhs_ty & rhs_ty),
@@ -379,10 +422,11 @@ fn gather_expr_options(
                 {
                     ref_map.insert(context, tgt);
                 } else {
-                    err.sym(
+                    err.sym_rule(
                         context,
                         file,
                         10,
+                        Rule::UnresolvedReference,
                         "unresolved context expected file root or feature",
                     );
                 }
@@ -394,10 +438,11 @@ fn gather_expr_options(
                 gather_expr_options(ctx, file, n, err, ref_map)
             });
             if (n_ty & Type::Real).is_empty() {
-                err.span(
+                err.span_rule(
                     expr.span.clone(),
                     file,
                     30,
+                    Rule::Type,
                     format!("type missmatch expected Real/Number",),
                 );
                 Default::default()
@@ -417,10 +462,11 @@ fn gather_expr_options(
                 return rhs_ty;
             }
             if (rhs_ty & lhs_ty).is_empty() {
-                err.span(
+                err.span_rule(
                     expr.span.clone(),
                     file,
                     30,
+                    Rule::Type,
                     format!(
                         "type missmatch {:?} and {:?}",
                         select_type(lhs_ty),
@@ -434,10 +480,11 @@ fn gather_expr_options(
                     _ => Type::Real.into(),
                 };
                 if (rhs_ty & lhs_ty & req).is_empty() {
-                    err.span(
+                    err.span_rule(
                         expr.span.clone(),
                         file,
                         30,
+                        Rule::Type,
                         format!("unsupported operator type {}", select_type(rhs_ty & lhs_ty),),
                     );
                 }
@@ -449,10 +496,11 @@ fn gather_expr_options(
                 gather_expr_options(ctx, file, lhs, err, ref_map)
             });
             if (lhs_ty & Type::String).is_empty() {
-                err.span(
+                err.span_rule(
                     expr.span.clone(),
                     file,
                     30,
+                    Rule::Type,
                     format!("type missmatch expected String",),
                 );
                 Default::default()
@@ -479,6 +527,41 @@ fn gather_expr_options(
         }
     }
 }
+/// Values below a feature cardinality exist once per instance, so they can only be
+/// referenced from constraints of the same instance
+fn check_instance_access(
+    ctx: &TypeResolveContext,
+    file: FileID,
+    sym: Symbol,
+    tgt: RootSymbol,
+    err: &mut ErrorsAcc,
+) {
+    let tgt_file = ctx.file(tgt.file);
+    let Some(feature) = tgt_file
+        .cardinality_path(Symbol::Root, tgt.sym)
+        .and_then(|path| path.last().copied())
+    else {
+        return;
+    };
+    let src_file = ctx.file(file);
+    if tgt.file == file
+        && src_file
+            .cardinality_path(feature, src_file.reference_scope(sym))
+            .is_some()
+    {
+        return;
+    }
+    err.sym_rule(
+        sym,
+        file,
+        30,
+        Rule::UnresolvedReference,
+        format!(
+            "only exists per instance of {}, reference it from inside {0}",
+            tgt_file.name(feature).unwrap()
+        ),
+    );
+}
 /// Fix types
 fn commit_expr(
     ctx: &TypeResolveContext,
@@ -511,6 +594,9 @@ fn commit_expr(
                 .resolve_sym(rs)
                 .find(|i| ctx.type_of(*i).unwrap() == ty)
                 .unwrap();
+            if matches!(tgt.sym, Symbol::Attribute(..)) || ty != Type::Bool {
+                check_instance_access(ctx, file, *sym, tgt, err);
+            }
             ref_map.insert(*sym, tgt);
         }
         _ => {}
@@ -565,7 +651,8 @@ pub fn estimate_types(
                 .slice(node.child_by_field_name("op").unwrap().byte_range())
                 .into();
             let req = match &*op {
-                "+" | "-" | "/" | "*" | ">" | "<" => Type::Real.into(),
+                "+" | ">" | "<" => Type::String | Type::Real,
+                "-" | "/" | "*" => Type::Real.into(),
                 "&" | "|" | "<=>" | "=>" => Type::Bool.into(),
                 _ => Type::String | Type::Real | Type::Bool,
             };
@@ -130,6 +130,25 @@ impl RootGraph {
     ) -> impl Iterator<Item = RootSymbol> + 'a {
         resolve::resolve(&self.files, &self.cache.fs, origin, path)
     }
+    /// find all symbols from origin under path, attributes of scope are preferred
+    pub fn resolve_scoped<'a>(
+        &'a self,
+        origin: FileID,
+        scope: Symbol,
+        path: &'a [Ustr],
+    ) -> impl Iterator<Item = RootSymbol> + 'a {
+        resolve::resolve_scoped(&self.files, &self.cache.fs, origin, scope, path)
+    }
+    /// find all symbols a reference in origin can be bound to, respecting the scope of
+    /// feature local constraints
+    pub fn resolve_reference<'a>(
+        &'a self,
+        origin: FileID,
+        sym: Symbol,
+    ) -> impl Iterator<Item = RootSymbol> + 'a {
+        let file = &self.files[&origin];
+        self.resolve_scoped(origin, file.reference_scope(sym), file.path(sym))
+    }
     pub fn revision(&self) -> u64 {
         self.revision
     }
@@ -164,6 +183,7 @@ impl RootGraph {
         files: &HashMap<FileID, Arc<AstDocument>>,
         configs: &HashMap<FileID, Arc<ConfigDocument>>,
         revision: u64,
+        import_roots: &ImportRoots,
         old: &Cache,
         err: &mut ErrorsAcc,
         check_state: &mut HashMap<FileID, Instant>,
@@ -205,7 +225,7 @@ impl RootGraph {
         }
         Self {
             cancel: CancellationToken::new(),
-            cache: Cache::new(old, files, configs, &dirty, revision, err),
+            cache: Cache::new(old, files, configs, &dirty, revision, import_roots, err),
             revision,
             files: files.clone(),
             configs: configs.clone(),
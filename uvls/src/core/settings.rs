@@ -0,0 +1,213 @@
+//! User facing server settings
+//!
+//! Settings are read from the project file `uvls.toml` of each workspace folder, the
+//! `initializationOptions` and the client configuration of section `uvls`.
+//! Lints, excluded directories and library paths apply to the files of their folder,
+//! everything else is taken from the first folder.
+//! All sources use the same keys, later sources override earlier ones key by key, eg.
+//! ```toml
+//! exclude = ["target", "build/generated"]
+//! libraryPaths = ["../shared-models", "/opt/uvl/lib"]
+//! logLevel = "debug"
+//! [smt]
+//! solver = { path = "/opt/z3/bin/z3" }
+//! checkDeadFeatures = false
+//! [lints]
+//! "uvl::tautology" = "off"
+//! ```
+use crate::core::*;
+use crate::smt::SmtOptions;
+use hashbrown::HashMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+pub const PROJECT_FILE: &str = "uvls.toml";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WebviewOptions {
+    /// first free port in this range is used for the configuration editor
+    pub ports: (u16, u16),
+}
+impl Default for WebviewOptions {
+    fn default() -> Self {
+        Self {
+            ports: (3000, 6000),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerSettings {
+    pub smt: SmtOptions,
+    pub lints: LintOptions,
+    pub webview: WebviewOptions,
+    /// directories skipped when loading the workspace, either names or paths relative to the root
+    pub exclude: Vec<String>,
+    /// log filter, eg. "debug" or "info, uvls::smt=debug"
+    pub log_level: Option<String>,
+    /// read only directories searched for imports after the local paths,
+    /// relative paths start at the workspace folder
+    pub library_paths: Vec<String>,
+}
+impl ServerSettings {
+    pub fn libraries(&self, root: Option<&Path>) -> Vec<PathBuf> {
+        self.library_paths
+            .iter()
+            .map(|path| match root {
+                Some(root) => root.join(path),
+                None => PathBuf::from(path),
+            })
+            .collect()
+    }
+    pub fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        self.exclude.iter().any(|ex| {
+            relative.starts_with(ex)
+                || path
+                    .file_name()
+                    .map(|name| name == ex.as_str())
+                    .unwrap_or(false)
+        })
+    }
+}
+
+/// Raw settings of each source, project files are kept per workspace folder
+#[derive(Clone, Debug, Default)]
+pub struct SettingsLayers {
+    pub projects: HashMap<PathBuf, Value>,
+    pub init: Value,
+    pub client: Value,
+}
+impl SettingsLayers {
+    /// Settings of a workspace folder, without folder only the init and client settings are used
+    pub fn resolve(&self, folder: Option<&Path>) -> std::result::Result<ServerSettings, String> {
+        let project = folder
+            .and_then(|folder| self.projects.get(folder))
+            .unwrap_or(&Value::Null);
+        let mut merged = Value::Object(Map::new());
+        for layer in [project, &self.init, &self.client] {
+            merge(&mut merged, layer);
+        }
+        serde_json::from_value(merged).map_err(|e| e.to_string())
+    }
+}
+/// Recursively override the keys of base with overlay
+fn merge(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (k, v) in overlay {
+                merge(base.entry(k.clone()).or_insert(Value::Null), v);
+            }
+        }
+        (_, Value::Null) => {}
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// Read the project file under root, Null if there is none
+pub fn read_project_settings(root: &Path) -> std::result::Result<Value, String> {
+    match std::fs::read_to_string(root.join(PROJECT_FILE)) {
+        Ok(source) => parse_toml(&source).map_err(|e| format!("{PROJECT_FILE}: {e}")),
+        Err(_) => Ok(Value::Null),
+    }
+}
+
+pub fn parse_toml(source: &str) -> std::result::Result<Value, String> {
+    toml::from_str(source).map_err(|e: toml::de::Error| {
+        let pos = e.span().map(|span| span.start).unwrap_or(source.len());
+        let line = source[..pos].matches('\n').count() + 1;
+        format!("line {line}: {}", e.message())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_toml() {
+        let value = parse_toml(
+            r#"
+# project settings
+exclude = ["target", 'build/gen',] # trailing comma
+logLevel = "debug"
+[smt]
+queryTimeout = 1_000
+solver = { path = "/opt/z3", args = ["-in", "-smt2"] }
+[lints]
+"uvl::tautology" = "off"
+max-depth.max = 6
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "exclude": ["target", "build/gen"],
+                "logLevel": "debug",
+                "smt": {"queryTimeout": 1000, "solver": {"path": "/opt/z3", "args": ["-in", "-smt2"]}},
+                "lints": {"uvl::tautology": "off", "max-depth": {"max": 6}}
+            })
+        );
+        let layers = SettingsLayers {
+            projects: [(PathBuf::from("/ws"), value)].into_iter().collect(),
+            init: serde_json::json!({"smt": {"queryTimeout": 5}}),
+            client: Value::Null,
+        };
+        let settings = layers.resolve(Some(Path::new("/ws"))).unwrap();
+        assert_eq!(settings.smt.query_timeout, 5);
+        assert_eq!(settings.smt.solver.path, "/opt/z3");
+        assert_eq!(settings.lints.max_depth, 6);
+        let other = layers.resolve(Some(Path::new("/other"))).unwrap();
+        assert_eq!(other.smt.query_timeout, 5);
+        assert_eq!(other.lints.max_depth, LintOptions::default().max_depth);
+        assert!(other.exclude.is_empty());
+        assert_eq!(settings.smt.solver.backend, crate::smt::SolverBackend::Z3);
+    }
+    #[test]
+    fn test_solver_backend() {
+        let layers = |project: &str| SettingsLayers {
+            projects: [(PathBuf::from("/ws"), parse_toml(project).unwrap())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let settings = layers("[smt]\nsolver = { path = \"cvc5\", backend = \"cvc5\" }")
+            .resolve(Some(Path::new("/ws")))
+            .unwrap();
+        assert_eq!(settings.smt.solver.backend, crate::smt::SolverBackend::Cvc5);
+        assert_eq!(
+            settings.smt.solver.args(),
+            vec!["--lang=smt2", "--incremental"]
+        );
+        assert!(layers("[smt]\nsolver = { backend = \"yices\" }")
+            .resolve(Some(Path::new("/ws")))
+            .is_err());
+    }
+    #[test]
+    fn test_malformed_toml() {
+        for source in [
+            "a = 1\na = 2",
+            "a = [1, 2",
+            "a = \"ü",
+            "a = \"\\u00\"",
+            "a = \"\\uD800\"",
+            "[a\nb = 1",
+            "= 1",
+            "a = { b = 1",
+            "a = 1 2",
+            "ä = \"ö",
+        ] {
+            assert!(parse_toml(source).is_err(), "{source}");
+        }
+        assert_eq!(
+            parse_toml("\"ä\" = \"\\u00fc ö\"").unwrap(),
+            serde_json::json!({"ä": "ü ö"})
+        );
+        assert!(parse_toml("a = 1\n[b]\nc = [")
+            .unwrap_err()
+            .starts_with("line 3:"));
+    }
+}
@@ -9,7 +9,7 @@ use std::fmt::Display;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 use tower_lsp::lsp_types::{Position, Range, Url};
-use tree_sitter::{Language, Node};
+use tree_sitter::{InputEdit, Language, Node, Tree};
 
 pub struct ParseConstants {
     pub queries: Queries,
@@ -68,6 +68,43 @@ pub fn lsp_range(span: std::ops::Range<usize>, source: &Rope) -> Option<Range> {
     lsp_position(span.start, source)
         .and_then(|start| lsp_position(span.end, source).map(|end| Range { start, end }))
 }
+/// Bytes of the new document touched by edits, which lead from old_tree to tree, extended by
+/// the syntax changes of the tree. Without edits the whole document is dirty
+pub fn changed_span<'a>(
+    old_tree: &Tree,
+    edits: Option<impl Iterator<Item = &'a InputEdit>>,
+    tree: &Tree,
+    len: usize,
+) -> std::ops::Range<usize> {
+    let Some(edits) = edits else {
+        return 0..len;
+    };
+    let mut edited = old_tree.clone();
+    let mut dirty: Option<std::ops::Range<usize>> = None;
+    for e in edits {
+        edited.edit(e);
+        //move the previous edits into the coordinates after e
+        let shift = |i: usize| {
+            if i <= e.start_byte {
+                i
+            } else if i >= e.old_end_byte {
+                i + e.new_end_byte - e.old_end_byte
+            } else {
+                e.new_end_byte
+            }
+        };
+        dirty = Some(match dirty {
+            Some(d) => shift(d.start).min(e.start_byte)..shift(d.end).max(e.new_end_byte),
+            None => e.start_byte..e.new_end_byte,
+        });
+    }
+    let mut dirty = dirty.unwrap_or(len..len);
+    for range in edited.changed_ranges(tree) {
+        dirty.start = dirty.start.min(range.start_byte);
+        dirty.end = dirty.end.max(range.end_byte);
+    }
+    dirty.start.min(len)..dirty.end.min(len)
+}
 pub fn char_offset(pos: &Position, source: &Rope) -> usize {
     if let Some(line) = source.get_line(pos.line as usize) {
         if let Ok(end) = line.try_utf16_cu_to_char(pos.character as usize) {
@@ -94,10 +131,42 @@ pub fn header_kind(node: Node) -> &str {
     node.child_by_field_name("header").unwrap().kind()
 }
 
+/// Tokens of the grammar that can not be used as unquoted names
+pub const KEYWORDS: &[&str] = &[
+    "Boolean",
+    "Integer",
+    "Real",
+    "String",
+    "alternative",
+    "as",
+    "cardinality",
+    "constraint",
+    "constraints",
+    "false",
+    "features",
+    "imports",
+    "include",
+    "mandatory",
+    "namespace",
+    "optional",
+    "or",
+    "true",
+];
+/// name is a valid unquoted name token
+pub fn is_plain_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name)
+}
+/// name can be written as a quoted name token
+pub fn is_quotable_name(name: &str) -> bool {
+    !name.contains(['"', '\\', '\n'])
+}
+
 pub type Result<T> = std::result::Result<T, Box<dyn error::Error + Send + Sync>>;
 
 #[derive(Debug)]
-struct CancelledError {}
+pub struct CancelledError {}
 impl Display for CancelledError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "cancelled")
@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use crate::core::*;
 use regex::Regex;
+use ropey::Rope;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 
@@ -337,3 +338,153 @@ pub fn add_language_level(
         return Ok(None);
     }
 }
+
+fn quickfix(uri: &Url, diagnostic: &Diagnostic, title: &str, edit: TextEdit) -> CodeAction {
+    CodeAction {
+        title: title.into(),
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::<Url, Vec<TextEdit>>::from([(
+                uri.clone(),
+                vec![edit],
+            )])),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        is_preferred: Some(true),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        ..Default::default()
+    }
+}
+
+pub fn remove_import(
+    params: CodeActionParams,
+    diagnostic: Diagnostic,
+    snapshot: std::result::Result<Option<(Draft, Arc<RootGraph>)>, tower_lsp::jsonrpc::Error>,
+) -> Result<Option<CodeActionResponse>> {
+    let Ok(Some((_, root))) = snapshot else {
+        return Ok(None);
+    };
+    let uri = &params.text_document.uri;
+    let Some(file) = root.file_id(uri).map(|id| root.file(id)) else {
+        return Ok(None);
+    };
+    let Some(edit) = file
+        .all_imports()
+        .find(|i| file.lsp_range(*i) == Some(diagnostic.range))
+        .and_then(|i| crate::ide::refactor::remove_import(file, i))
+    else {
+        return Ok(None);
+    };
+    Ok(Some(vec![CodeActionOrCommand::CodeAction(quickfix(
+        uri,
+        &diagnostic,
+        "Remove unused import",
+        edit,
+    ))]))
+}
+
+pub fn convert_single_child_group(
+    params: CodeActionParams,
+    diagnostic: Diagnostic,
+) -> Result<Option<CodeActionResponse>> {
+    let edit = TextEdit {
+        range: diagnostic.range,
+        new_text: "mandatory".into(),
+    };
+    Ok(Some(vec![CodeActionOrCommand::CodeAction(quickfix(
+        &params.text_document.uri,
+        &diagnostic,
+        "Convert group to mandatory",
+        edit,
+    ))]))
+}
+
+pub fn remove_empty_group(
+    params: CodeActionParams,
+    diagnostic: Diagnostic,
+) -> Result<Option<CodeActionResponse>> {
+    let line = diagnostic.range.start.line;
+    let edit = TextEdit {
+        range: Range::new(Position::new(line, 0), Position::new(line + 1, 0)),
+        new_text: String::new(),
+    };
+    Ok(Some(vec![CodeActionOrCommand::CodeAction(quickfix(
+        &params.text_document.uri,
+        &diagnostic,
+        "Remove empty group",
+        edit,
+    ))]))
+}
+
+/// Suppress a diagnostic with a comment on the line before it or for the whole file,
+/// unused suppressions are removed instead
+pub fn suppress(uri: &Url, diagnostic: &Diagnostic, source: &Rope) -> Vec<CodeActionOrCommand> {
+    let Some(rule) = diagnostic.code.as_ref().and_then(|code| match code {
+        NumberOrString::String(name) => Rule::from_name(name),
+        _ => None,
+    }) else {
+        return Vec::new();
+    };
+    let edit = |range: Range, new_text: String| {
+        Some(WorkspaceEdit {
+            changes: Some(HashMap::<Url, Vec<TextEdit>>::from([(
+                uri.clone(),
+                vec![TextEdit { range, new_text }],
+            )])),
+            document_changes: None,
+            change_annotations: None,
+        })
+    };
+    let line = diagnostic.range.start.line;
+    if rule == Rule::UnusedSuppression {
+        //only remove whole comments, names in a list are left to the user
+        if suppressions(source)
+            .iter()
+            .filter(|s| source.byte_to_line(s.span.start) == line as usize)
+            .count()
+            == 1
+        {
+            return vec![CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Remove unused suppression".into(),
+                kind: Some(CodeActionKind::QUICKFIX),
+                edit: edit(
+                    Range::new(Position::new(line, 0), Position::new(line + 1, 0)),
+                    String::new(),
+                ),
+                is_preferred: Some(true),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                ..Default::default()
+            })];
+        }
+        return Vec::new();
+    }
+    let name = rule.name().trim_start_matches("uvl::");
+    let indent: String = source
+        .line(line as usize)
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect();
+    vec![
+        CodeActionOrCommand::CodeAction(CodeAction {
+            title: format!("Suppress {} for this line", rule.name()),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: edit(
+                Range::new(Position::new(line, 0), Position::new(line, 0)),
+                format!("{indent}// uvls-ignore {name}\n"),
+            ),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            ..Default::default()
+        }),
+        CodeActionOrCommand::CodeAction(CodeAction {
+            title: format!("Suppress {} for this file", rule.name()),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: edit(
+                Range::new(Position::new(0, 0), Position::new(0, 0)),
+                format!("// uvls-ignore-file {name}\n"),
+            ),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            ..Default::default()
+        }),
+    ]
+}
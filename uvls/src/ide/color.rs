@@ -3,6 +3,8 @@ use crate::ide::completion::find_section;
 use log::info;
 use ropey::Rope;
 use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::time::Instant;
 use tower_lsp::lsp_types::*;
 use tree_sitter::{Node, QueryCursor, Tree};
@@ -10,17 +12,30 @@ use ustr::Ustr;
 /// Syntax highlight happens in here
 /// we mainly use tree-sitter queries to extract token and serialize them
 /// according to the lsp spec
-/// TODO make use of incremental parsing and updates
-/// this is fast enough for medium sized files but sinks at huge files
+/// After an edit only the lines changed by tree-sitter are colored again, tokens of the other
+/// lines are taken from the last state of the file
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct AbsToken {
     range: Range,
     kind: u32,
 }
+impl AbsToken {
+    fn intersects(&self, lines: &std::ops::Range<u32>) -> bool {
+        self.range.start.line < lines.end && self.range.end.line >= lines.start
+    }
+}
 struct FileState {
+    result_id: String,
+    //absolute tokens sorted by their start
+    tokens: Vec<AbsToken>,
     state: Vec<SemanticToken>,
+    draft: Draft,
+    //hash of the symbols visible to attribute paths when the state was created
+    key: u64,
 }
+//diffs which need more edits are sent as a single replacement
+const MAX_DIFF_EDITS: usize = 512;
 pub fn token_types() -> Vec<SemanticTokenType> {
     vec![
         SemanticTokenType::KEYWORD,
@@ -102,58 +117,120 @@ fn fast_lsp_range(
     }
 }
 
-impl FileState {
-    //calculate the diffrence of two states using a crude single change or all diff algorithm
-    fn diff(&self, new: &FileState) -> SemanticTokensFullDeltaResult {
-        //TODO use a proper diffing algorithm
-        let prefix = self
-            .state
-            .iter()
-            .zip(new.state.iter())
-            .take_while(|(i, j)| i == j)
-            .count();
-        let diff = self.state.len().abs_diff(new.state.len());
-        if self.state.len() < new.state.len() {
-            if self.state[prefix..]
-                .iter()
-                .zip(new.state[prefix + diff..].iter())
-                .all(|(i, k)| i == k)
-            {
-                return SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
-                    result_id: None,
-                    edits: vec![SemanticTokensEdit {
-                        start: prefix as u32,
-                        delete_count: 0,
-                        data: Some(new.state[prefix..prefix + diff].to_vec()),
-                    }],
-                });
+/// Minimal edit script turning old into new using the Myers diff algorithm, edits are
+/// relative to old and sorted
+fn diff_tokens(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(i, j)| i == j)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(i, j)| i == j)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    let edit = |start: usize, delete: usize, data: &[SemanticToken]| SemanticTokensEdit {
+        //offsets are counted in integers, each token has five
+        start: 5 * (prefix + start) as u32,
+        delete_count: 5 * delete as u32,
+        data: if data.is_empty() {
+            None
+        } else {
+            Some(data.to_vec())
+        },
+    };
+    if a.is_empty() && b.is_empty() {
+        return vec![];
+    }
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_DIFF_EDITS) as isize;
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    let idx = |k: isize| (k + max) as usize;
+    let mut trace = Vec::new();
+    let mut end = None;
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                end = Some(d);
+                break 'search;
             }
-        } else if self.state.len() > new.state.len()
-            && self.state[prefix + diff..]
-                .iter()
-                .zip(new.state[prefix..].iter())
-                .all(|(i, k)| i == k)
-        {
-            return SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
-                result_id: None,
-                edits: vec![SemanticTokensEdit {
-                    start: prefix as u32,
-                    delete_count: diff as u32,
-                    data: None,
-                }],
-            });
         }
-        SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
-            result_id: None,
-            edits: vec![SemanticTokensEdit {
-                start: prefix as u32,
-                delete_count: (self.state.len() - prefix) as u32,
-                data: Some(new.state[prefix..].to_vec()),
-            }],
-        })
     }
+    let Some(depth) = end else {
+        return vec![edit(0, a.len(), b)];
+    };
+    //walk back to collect the matching tokens
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..=depth).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { v[idx(prev_k)] };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    matches.reverse();
+    matches.push((a.len(), b.len()));
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (x, y) in matches {
+        if x > i || y > j {
+            edits.push(edit(i, x - i, &b[j..y]));
+        }
+        i = x + 1;
+        j = y + 1;
+    }
+    edits
+}
+/// Lines containing characters which have different utf8 and utf16 lengths
+fn utf16_lines(source: &Rope, lines: &std::ops::Range<u32>) -> HashSet<usize> {
+    (lines.start as usize..(lines.end as usize).min(source.len_lines()))
+        .filter(|i| {
+            let line = source.line(*i);
+            line.len_bytes() != line.len_chars()
+        })
+        .collect()
+}
+/// Hash of the symbols attribute paths are resolved against, tokens can only be reused while
+/// it stays the same
+fn semantic_key(root: &Snapshot) -> u64 {
+    root.files.values().fold(0u64, |acc, file| {
+        let mut state = std::collections::hash_map::DefaultHasher::new();
+        (file.id, file.exports()).hash(&mut state);
+        acc.wrapping_add(state.finish())
+    })
+}
+impl FileState {
     fn color_section(
         origin: Node,
+        bytes: std::ops::Range<usize>,
         root: &Snapshot,
         source: &Rope,
         file: &AstDocument,
@@ -162,7 +239,7 @@ impl FileState {
     ) {
         let _section = find_section(origin);
         let mut cursor = QueryCursor::new();
-
+        cursor.set_byte_range(bytes);
         let captures = TS.queries.highlight.capture_names();
         for i in cursor.matches(&TS.queries.highlight, origin, node_source(source)) {
             for c in i.captures {
@@ -290,35 +367,51 @@ impl FileState {
         }
         return false;
     }
-    fn new(origin: &Url, tree: Tree, source: &ropey::Rope, root: &Snapshot) -> Self {
+    /// Color all lines in lines, tokens are sorted by their start
+    fn color_lines(
+        origin: &Url,
+        tree: &Tree,
+        source: &Rope,
+        root: &Snapshot,
+        lines: std::ops::Range<u32>,
+    ) -> Vec<AbsToken> {
         let mut token = vec![];
-
-        let time = Instant::now();
+        let Some(file) = root.file_by_uri(origin) else {
+            return token;
+        };
         //Keep track of bad utf16 lines, only perform byte->utf8->utf16 transformation when needed
         //61ms->34ms performance improvment for pure ascii!
-        //TODO make a better uniform byte->utf16 provider as ropey is to slow
-        //or just use more threads
-        let mut utf16_line = HashSet::new();
-        for (i, line) in source.lines().enumerate() {
-            for c in line.chars() {
-                if c.len_utf8() != c.len_utf16() {
-                    utf16_line.insert(i);
-                }
-            }
-        }
+        let utf16_line = utf16_lines(source, &lines);
+        let bytes = source.line_to_byte((lines.start as usize).min(source.len_lines()))
+            ..source.line_to_byte((lines.end as usize).min(source.len_lines()));
         let mut sections = tree.walk();
-        let file = root.file_by_uri(origin).unwrap();
         //iterate captures and create colors token, we currently allow diffrent color for diffrent
         //sections (currently unsed)
         sections.goto_first_child();
         loop {
-            Self::color_section(sections.node(), root, source, file, &utf16_line, &mut token);
+            let section = sections.node();
+            if section.start_byte() <= bytes.end && section.end_byte() >= bytes.start {
+                Self::color_section(
+                    section,
+                    bytes.clone(),
+                    root,
+                    source,
+                    file,
+                    &utf16_line,
+                    &mut token,
+                );
+            }
             if !sections.goto_next_sibling() {
                 break;
             }
         }
+        token.retain(|i| i.intersects(&lines));
         token.sort_by_key(|a| (a.range.start.line, a.range.start.character));
         token.dedup();
+        token
+    }
+    /// Translate sorted absolute tokens to relative lsp tokens
+    fn encode(token: &[AbsToken], source: &Rope) -> Vec<SemanticToken> {
         let mut filtered = Vec::new();
         let mut last: Option<AbsToken> = None;
         //translate to relative lsp tokens
@@ -409,57 +502,191 @@ impl FileState {
             }
             last = Some(i.clone());
         }
-
+        filtered
+    }
+    fn new(origin: &Url, draft: Draft, root: &Snapshot, result_id: String) -> Self {
+        let time = Instant::now();
+        let source = draft.source();
+        let tokens = Self::color_lines(origin, draft.tree(), source, root, 0..u32::MAX);
         info!("Semantic highlight took {:?}", time.elapsed());
-
-        FileState { state: filtered }
+        FileState {
+            result_id,
+            state: Self::encode(&tokens, source),
+            tokens,
+            key: semantic_key(root),
+            draft,
+        }
+    }
+    /// Create the next state, only lines touched by the edit are colored again
+    fn update(&self, origin: &Url, draft: Draft, root: &Snapshot, result_id: String) -> Self {
+        let key = semantic_key(root);
+        if key != self.key {
+            return Self::new(origin, draft, root, result_id);
+        }
+        let time = Instant::now();
+        let (source, old_source) = (draft.source(), self.draft.source());
+        let dirty = changed_span(
+            self.draft.tree(),
+            draft
+                .edits()
+                .and_then(|edits| edits.since(self.draft.timestamp())),
+            draft.tree(),
+            source.len_bytes(),
+        );
+        let line_delta = source.len_lines() as i64 - old_source.len_lines() as i64;
+        let lines =
+            source.byte_to_line(dirty.start) as u32..source.byte_to_line(dirty.end) as u32 + 1;
+        let old_lines = lines.start..(lines.end as i64 - line_delta) as u32;
+        let mut tokens: Vec<AbsToken> = self
+            .tokens
+            .iter()
+            .filter(|i| i.range.end.line < lines.start)
+            .cloned()
+            .collect();
+        tokens.append(&mut Self::color_lines(
+            origin,
+            draft.tree(),
+            source,
+            root,
+            lines.clone(),
+        ));
+        tokens.extend(
+            self.tokens
+                .iter()
+                .filter(|i| i.range.start.line >= old_lines.end)
+                .map(|i| {
+                    let mut i = i.clone();
+                    i.range.start.line = (i.range.start.line as i64 + line_delta) as u32;
+                    i.range.end.line = (i.range.end.line as i64 + line_delta) as u32;
+                    i
+                }),
+        );
+        tokens.sort_by_key(|a| (a.range.start.line, a.range.start.character));
+        tokens.dedup();
+        info!(
+            "Semantic highlight of lines {:?} took {:?}",
+            lines,
+            time.elapsed()
+        );
+        FileState {
+            result_id,
+            state: Self::encode(&tokens, source),
+            tokens,
+            key,
+            draft,
+        }
     }
 }
 pub struct State {
     files: dashmap::DashMap<Url, FileState>,
+    result_ids: AtomicU64,
 }
 impl State {
     pub fn new() -> Self {
         State {
             files: Default::default(),
+            result_ids: AtomicU64::new(0),
         }
     }
     pub fn remove(&self, uri: &Url) {
         self.files.remove(uri);
     }
-    pub fn get(&self, root: Snapshot, uri: Url, tree: Tree, source: ropey::Rope) -> SemanticTokens {
-        let state = FileState::new(&uri, tree, &source, &root);
-        let out = state.state.clone();
-        self.files.insert(uri, state);
-
-        SemanticTokens {
-            result_id: None,
-            data: out,
+    /// Compute the tokens of the current file version from the last state if there is one
+    fn update(&self, root: &Snapshot, uri: &Url, draft: Draft) -> FileState {
+        let result_id = self.result_ids.fetch_add(1, Ordering::SeqCst).to_string();
+        match self.files.get(uri) {
+            Some(old) => old.update(uri, draft, root, result_id),
+            None => FileState::new(uri, draft, root, result_id),
         }
     }
+    pub fn get(&self, root: Snapshot, uri: Url, draft: Draft) -> SemanticTokens {
+        let state = self.update(&root, &uri, draft);
+        let out = SemanticTokens {
+            result_id: Some(state.result_id.clone()),
+            data: state.state.clone(),
+        };
+        self.files.insert(uri, state);
+        out
+    }
     pub fn delta(
         &self,
         root: Snapshot,
         uri: Url,
-        tree: Tree,
-        source: ropey::Rope,
+        draft: Draft,
+        previous_result_id: &str,
     ) -> SemanticTokensFullDeltaResult {
-        if let Some(old) = self.files.get(&uri) {
-            let state = FileState::new(&uri, tree, &source, &root);
-            let diff = old.diff(&state);
-            self.files.insert(uri.clone(), state);
-            diff
-        } else {
-            info!("Start color");
-            let state = FileState::new(&uri, tree, &source, &root);
-            let out = state.state.clone();
-            self.files.insert(uri.clone(), state);
-
-            info!("End color");
-            SemanticTokensFullDeltaResult::Tokens(SemanticTokens {
-                result_id: None,
-                data: out,
+        let state = self.update(&root, &uri, draft);
+        let out = match self.files.get(&uri) {
+            Some(old) if old.result_id == previous_result_id => {
+                SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                    result_id: Some(state.result_id.clone()),
+                    edits: diff_tokens(&old.state, &state.state),
+                })
+            }
+            _ => SemanticTokensFullDeltaResult::Tokens(SemanticTokens {
+                result_id: Some(state.result_id.clone()),
+                data: state.state.clone(),
+            }),
+        };
+        self.files.insert(uri, state);
+        out
+    }
+    /// Tokens of the lines in range, used by clients for the visible part of large files
+    pub fn range(
+        &self,
+        root: Snapshot,
+        uri: Url,
+        tree: Tree,
+        source: Rope,
+        range: Range,
+    ) -> SemanticTokens {
+        let lines = range.start.line..range.end.line.saturating_add(1);
+        let tokens = FileState::color_lines(&uri, &tree, &source, &root, lines);
+        SemanticTokens {
+            result_id: None,
+            data: FileState::encode(&tokens, &source),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn tokens(kinds: &[u32]) -> Vec<SemanticToken> {
+        kinds
+            .iter()
+            .map(|k| SemanticToken {
+                delta_line: 1,
+                delta_start: 0,
+                length: 1,
+                token_type: *k,
+                token_modifiers_bitset: 0,
             })
+            .collect()
+    }
+    fn apply(old: &[SemanticToken], edits: &[SemanticTokensEdit]) -> Vec<SemanticToken> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        for e in edits {
+            let start = e.start as usize / 5;
+            out.extend_from_slice(&old[pos..start]);
+            out.extend(e.data.iter().flatten().cloned());
+            pos = start + e.delete_count as usize / 5;
         }
+        out.extend_from_slice(&old[pos..]);
+        out
+    }
+    #[test]
+    fn test_diff_tokens() {
+        let old = tokens(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        let new = tokens(&[0, 9, 2, 3, 5, 6, 8, 7, 7]);
+        let edits = diff_tokens(&old, &new);
+        assert_eq!(apply(&old, &edits), new);
+        //replace 1, delete 4, insert 8 and 7
+        assert_eq!(edits.len(), 3);
+        assert_eq!(edits[0].start, 5);
+        assert_eq!(edits[1].delete_count, 5);
+        assert!(diff_tokens(&new, &new).is_empty());
+        assert_eq!(apply(&new, &diff_tokens(&new, &old)), old);
+        assert_eq!(apply(&old, &diff_tokens(&old, &[])), vec![]);
     }
 }
@@ -0,0 +1,189 @@
+use crate::core::*;
+use crate::ide::location::reverse_resolve;
+use hashbrown::HashMap;
+use tower_lsp::lsp_types::SymbolKind;
+use tower_lsp::lsp_types::*;
+
+/// Items of a hierarchy are either features or the constraints referencing them,
+/// the kind is kept in the data field to recover the symbol from the item
+fn item(root: &RootGraph, sym: RootSymbol) -> Option<CallHierarchyItem> {
+    let file = root.file(sym.file);
+    let span = file.span(sym.sym)?;
+    let range = lsp_range(span.clone(), &file.source)?;
+    let detail = file
+        .uri
+        .path_segments()
+        .and_then(|mut p| p.next_back())
+        .map(String::from);
+    match sym.sym {
+        Symbol::Feature(..) => Some(CallHierarchyItem {
+            name: file.name(sym.sym)?.to_string(),
+            kind: SymbolKind::CLASS,
+            tags: None,
+            detail,
+            uri: file.uri.clone(),
+            range,
+            selection_range: range,
+            data: Some("feature".into()),
+        }),
+        Symbol::Constraint(..) => {
+            let text = file.source.byte_slice(span).to_string();
+            let mut name = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            if name.chars().count() > 60 {
+                name = name.chars().take(59).chain(['…']).collect();
+            }
+            Some(CallHierarchyItem {
+                name,
+                kind: SymbolKind::OPERATOR,
+                tags: None,
+                detail,
+                uri: file.uri.clone(),
+                range,
+                selection_range: range,
+                data: Some("constraint".into()),
+            })
+        }
+        _ => None,
+    }
+}
+/// Symbol described by an item
+fn item_symbol(root: &RootGraph, item: &CallHierarchyItem) -> Option<RootSymbol> {
+    let id = root.file_id(&item.uri).filter(|id| !id.is_config())?;
+    let file = root.file(id);
+    let offset = byte_offset(&item.selection_range.start, &file.source);
+    let sym = match item.data.as_ref().and_then(|d| d.as_str()) {
+        Some("constraint") => file
+            .all_constraints()
+            .find(|c| file.span(*c).map(|s| s.start == offset).unwrap_or(false))?,
+        _ => file
+            .find(offset)
+            .filter(|sym| matches!(sym, Symbol::Feature(..)))?,
+    };
+    Some(RootSymbol { file: id, sym })
+}
+/// Innermost constraint containing a reference
+fn constraint_of(file: &AstDocument, reference: Symbol) -> Option<Symbol> {
+    let start = file.span(reference)?.start;
+    file.all_constraints()
+        .filter_map(|c| Some((c, file.span(c)?)))
+        .filter(|(_, span)| span.contains(&start))
+        .min_by_key(|(_, span)| span.len())
+        .map(|(c, _)| c)
+}
+/// Feature bound by a reference, attributes stand for their feature
+fn referenced_feature(root: &RootGraph, origin: FileID, reference: Symbol) -> Option<RootSymbol> {
+    let tgt = root.resolve_reference(origin, reference).next()?;
+    let sym = root.file(tgt.file).scope(tgt.sym);
+    matches!(sym, Symbol::Feature(..)).then_some(RootSymbol {
+        file: tgt.file,
+        sym,
+    })
+}
+/// Constraints referencing feature grouped with the ranges of those references
+fn constraints_referencing(
+    root: &Snapshot,
+    feature: RootSymbol,
+) -> HashMap<RootSymbol, Vec<Range>> {
+    let mut out: HashMap<RootSymbol, Vec<Range>> = HashMap::new();
+    for (reference, range) in reverse_resolve(root, feature.file, feature.sym) {
+        let file = root.file(reference.file);
+        let Some(c) = constraint_of(file, reference.sym) else {
+            continue;
+        };
+        let Some(range) = range.or_else(|| file.lsp_range(reference.sym)) else {
+            continue;
+        };
+        out.entry(RootSymbol {
+            file: reference.file,
+            sym: c,
+        })
+        .or_default()
+        .push(range);
+    }
+    out
+}
+/// Features referenced by a constraint grouped with the ranges of those references
+fn constraint_features(
+    root: &RootGraph,
+    constraint: RootSymbol,
+) -> HashMap<RootSymbol, Vec<Range>> {
+    let file = root.file(constraint.file);
+    let mut out: HashMap<RootSymbol, Vec<Range>> = HashMap::new();
+    for r in file.constraint_references(constraint.sym) {
+        let (Some(feature), Some(range)) = (
+            referenced_feature(root, constraint.file, r),
+            file.lsp_range(r),
+        ) else {
+            continue;
+        };
+        out.entry(feature).or_default().push(range);
+    }
+    out
+}
+
+pub fn prepare(root: &Snapshot, uri: &Url, pos: &Position) -> Option<Vec<CallHierarchyItem>> {
+    let id = root.file_id(uri).filter(|id| !id.is_config())?;
+    let file = root.file(id);
+    let offset = byte_offset(pos, &file.source);
+    let sym = match file.find(offset)? {
+        sym @ Symbol::Feature(..) => RootSymbol { file: id, sym },
+        sym @ Symbol::Attribute(..) => RootSymbol {
+            file: id,
+            sym: file.scope(sym),
+        },
+        sym @ Symbol::Reference(..) => referenced_feature(root, id, sym)?,
+        _ => return None,
+    };
+    Some(vec![item(root, sym)?])
+}
+/// Constraints referencing a feature, constraints have no incoming items
+pub fn incoming(
+    root: &Snapshot,
+    item: &CallHierarchyItem,
+) -> Option<Vec<CallHierarchyIncomingCall>> {
+    let sym = item_symbol(root, item)?;
+    if !matches!(sym.sym, Symbol::Feature(..)) {
+        return Some(Vec::new());
+    }
+    Some(
+        constraints_referencing(root, sym)
+            .into_iter()
+            .filter_map(|(c, from_ranges)| {
+                Some(CallHierarchyIncomingCall {
+                    from: self::item(root, c)?,
+                    from_ranges,
+                })
+            })
+            .collect(),
+    )
+}
+/// Features related to a feature through constraints, or the features used in a constraint
+pub fn outgoing(
+    root: &Snapshot,
+    item: &CallHierarchyItem,
+) -> Option<Vec<CallHierarchyOutgoingCall>> {
+    let sym = item_symbol(root, item)?;
+    let mut related: HashMap<RootSymbol, Vec<Range>> = HashMap::new();
+    if matches!(sym.sym, Symbol::Constraint(..)) {
+        related = constraint_features(root, sym);
+    } else {
+        for c in constraints_referencing(root, sym).into_keys() {
+            for (feature, ranges) in constraint_features(root, c) {
+                if feature != sym {
+                    related.entry(feature).or_default().extend(ranges);
+                }
+            }
+        }
+    }
+    Some(
+        related
+            .into_iter()
+            .filter_map(|(feature, from_ranges)| {
+                Some(CallHierarchyOutgoingCall {
+                    to: self::item(root, feature)?,
+                    from_ranges,
+                })
+            })
+            .collect(),
+    )
+}
@@ -90,7 +90,7 @@ fn generate(model: &OwnedSMTModel, id: FileID, range: Span) -> Option<Vec<InlayH
         model
             .module
             .instances()
-            .filter(|(_, i)| doc.id == i.id)
+            .filter(|(m, i)| doc.id == i.id && model.module.cardinality_origin(*m).is_none())
             .flat_map(|(m, _)| match &model.model {
                 SMTModel::SAT { values, .. } => doc
                     .all_features()
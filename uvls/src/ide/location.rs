@@ -5,6 +5,7 @@ use log::info;
 use ropey::Rope;
 use tower_lsp::lsp_types::*;
 use tree_sitter::Node;
+use ustr::Ustr;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TextObjectKind {
@@ -22,6 +23,8 @@ pub struct TextObject {
     path: Path,
     kind: TextObjectKind,
     selected_segment: usize,
+    /// owning feature of a feature local constraint
+    scope: Option<Path>,
 }
 pub fn attribute_prefix(node: Node, source: &Rope) -> Option<Path> {
     match node.kind() {
@@ -41,9 +44,19 @@ pub fn attribute_prefix(node: Node, source: &Rope) -> Option<Path> {
         _ => None,
     }
 }
+/// Path of the feature owning a feature local constraint
+pub fn constraint_owner(mut node: Node, source: &Rope) -> Option<Path> {
+    while !matches!(
+        node.kind(),
+        "attribute_constraint" | "attribute_constraints"
+    ) {
+        node = node.parent()?;
+    }
+    attribute_prefix(containing_blk(node)?, source)
+}
 pub fn whole_expr(node: Node) -> Node {
     match node.parent().map(|i| i.kind()) {
-        Some("blk") => node,
+        Some("blk" | "attribute_constraint" | "attribute_constraints") => node,
         Some(_) => whole_expr(node.parent().unwrap()),
         _ => node,
     }
@@ -65,11 +78,13 @@ pub fn find_text_object_impl(
                     kind: TextObjectKind::ImportAlias,
                     selected_segment: path.segment(offset),
                     path,
+                    scope: None,
                 }),
                 "path" => Some(TextObject {
                     kind: TextObjectKind::ImportPath,
                     selected_segment: path.segment(offset),
                     path,
+                    scope: None,
                 }),
                 _ => None,
             }
@@ -81,11 +96,13 @@ pub fn find_text_object_impl(
                     kind: TextObjectKind::Feature,
                     selected_segment: 0,
                     path,
+                    scope: None,
                 }),
                 "path" => Some(TextObject {
                     kind: TextObjectKind::FeatureReference,
                     selected_segment: path.segment(offset),
                     path,
+                    scope: None,
                 }),
                 _ => None,
             }
@@ -97,6 +114,7 @@ pub fn find_text_object_impl(
                     kind: TextObjectKind::Attribute,
                     selected_segment: path.len() - 1,
                     path,
+                    scope: None,
                 })
             }
             _ => None,
@@ -127,6 +145,7 @@ pub fn find_text_object_impl(
                         ),
                         selected_segment: path.segment(offset),
                         path,
+                        scope: constraint_owner(p_node, source),
                     })
                 }
 
@@ -134,6 +153,7 @@ pub fn find_text_object_impl(
                     kind: TextObjectKind::Aggregate(context),
                     selected_segment: path.segment(offset),
                     path,
+                    scope: None,
                 }),
                 _ => None,
             }
@@ -170,6 +190,33 @@ pub fn find_text_object(
     }
 }
 
+/// Bind a reference path, selected_segment picks the symbol bound to that segment
+fn find_reference_definition(
+    root: &Snapshot,
+    file_id: FileID,
+    path: &[Ustr],
+    selected_segment: usize,
+    filter: &dyn Fn(Type) -> bool,
+) -> Option<RootSymbol> {
+    for bind in root.resolve_with_binding(file_id, path) {
+        let last = bind.last().unwrap().0;
+        let dst_file = root.file(last.file);
+        if dst_file.type_of(last.sym).map(filter).unwrap_or(false) {
+            return Some(
+                bind.iter()
+                    .find_map(|(sym, index)| {
+                        if selected_segment < *index {
+                            Some(*sym)
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap_or(last),
+            );
+        }
+    }
+    None
+}
 fn find_definitions(
     root: &Snapshot,
     draft: &Draft,
@@ -218,47 +265,31 @@ fn find_definitions(
         }
 
         TextObjectKind::Reference(ty) => {
-            for bind in root.resolve_with_binding(file_id, &obj.path.names) {
-                let last = bind.last().unwrap().0;
-                let dst_file = root.file(last.file);
-                info!("{:?}", bind);
-
-                if dst_file
-                    .type_of(last.sym)
-                    .map(|dty| ty == dty)
-                    .unwrap_or(false)
-                {
-                    return Some(vec![bind
-                        .iter()
-                        .find_map(|(sym, index)| {
-                            if obj.selected_segment < *index {
-                                Some(*sym)
-                            } else {
-                                None
-                            }
-                        })
-                        .unwrap_or(last)]);
-                }
-            }
-            for bind in root.resolve_with_binding(file_id, &obj.path.names) {
-                let last = bind.last().unwrap().0;
-                let dst_file = root.file(last.file);
-                info!("{:?}", bind);
-                if dst_file
-                    .type_of(last.sym)
-                    .map(|dty| matches!(dty, Type::String | Type::Real | Type::Bool))
-                    .unwrap_or(false)
-                {
-                    return Some(vec![bind
-                        .iter()
-                        .find_map(|(sym, index)| {
-                            if obj.selected_segment < *index {
-                                Some(*sym)
-                            } else {
-                                None
-                            }
-                        })
-                        .unwrap_or(last)]);
+            //feature local constraints first try to bind to the owning feature
+            let scoped = obj.scope.as_ref().map(|scope| {
+                (
+                    [&scope.names[..], &obj.path.names[..]].concat(),
+                    scope.len(),
+                )
+            });
+            let candidates: Vec<(&[Ustr], usize)> = scoped
+                .iter()
+                .map(|(path, offset)| (path.as_slice(), *offset))
+                .chain(std::iter::once((obj.path.names.as_slice(), 0)))
+                .collect();
+            let exact = |dty: Type| ty == dty;
+            let any = |dty: Type| matches!(dty, Type::String | Type::Real | Type::Bool);
+            for filter in [&exact as &dyn Fn(Type) -> bool, &any] {
+                for (path, offset) in candidates.iter() {
+                    if let Some(sym) = find_reference_definition(
+                        root,
+                        file_id,
+                        path,
+                        obj.selected_segment + offset,
+                        filter,
+                    ) {
+                        return Some(vec![sym]);
+                    }
                 }
             }
             None
@@ -328,7 +359,7 @@ pub fn goto_definition(
     ))
 }
 
-fn reverse_resolve(
+pub fn reverse_resolve(
     root: &Snapshot,
     dst_id: FileID,
     tgt: Symbol,
@@ -344,14 +375,16 @@ fn reverse_resolve(
             src_file
                 .all_references()
                 .filter(move |r| {
-                    root.resolve(src_id, src_file.path(*r)).any(|sym| {
+                    root.resolve_reference(src_id, *r).any(|sym| {
                         sym == RootSymbol {
                             file: dst_id,
                             sym: tgt,
-                        } || matches!(tgt, Symbol::Feature(_))
-                            && matches!(sym, RootSymbol {file, sym: Symbol::Attribute(n)}
+                        }
+                    }) || matches!(tgt, Symbol::Feature(_))
+                        && root.resolve(src_id, src_file.path(*r)).any(|sym| {
+                            matches!(sym, RootSymbol {file, sym: Symbol::Attribute(n)}
                                 if file == dst_id && dst_file.scope(Symbol::Attribute(n)) == tgt)
-                    })
+                        })
                 })
                 .map(move |sym| -> (RootSymbol, Option<Range>) {
                     fn get_range(
@@ -368,7 +401,11 @@ fn reverse_resolve(
                         };
                         for i in 0..reference.path.names.len() {
                             if root
-                                .resolve(src_file.id, &reference.path.names[0..=i])
+                                .resolve_scoped(
+                                    src_file.id,
+                                    reference.scope,
+                                    &reference.path.names[0..=i],
+                                )
                                 .any(|sym| {
                                     sym == RootSymbol {
                                         file: dst_id,
@@ -454,44 +491,421 @@ pub fn find_references(
     )
 }
 
+/// Occurrences of the symbol at pos in the current document, declarations are writes
+/// and references in constraints and attributes are reads
+pub fn document_highlight(
+    root: &Snapshot,
+    draft: &Draft,
+    pos: &Position,
+    uri: &Url,
+) -> Option<Vec<DocumentHighlight>> {
+    let file_id = root.file_id(uri).filter(|id| !id.is_config())?;
+    let file = root.file(file_id);
+    let mut out: Vec<DocumentHighlight> = find_definitions(root, draft, pos, uri)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|def| def.file == file_id && def.sym != Symbol::Root)
+        .filter_map(|def| {
+            Some(DocumentHighlight {
+                range: file.lsp_range(def.sym)?,
+                kind: Some(DocumentHighlightKind::WRITE),
+            })
+        })
+        .collect();
+    for (sym, range) in find_references_symboles(root, draft, pos, uri)? {
+        if sym.file != file_id {
+            continue;
+        }
+        let Some(range) = range.or_else(|| file.lsp_range(sym.sym)) else {
+            continue;
+        };
+        if out.iter().all(|h| h.range != range) {
+            out.push(DocumentHighlight {
+                range,
+                kind: Some(DocumentHighlightKind::READ),
+            });
+        }
+    }
+    Some(out)
+}
+fn file_link(range: Range, target: FileID) -> DocumentLink {
+    let url = target.url();
+    DocumentLink {
+        range,
+        tooltip: url
+            .to_file_path()
+            .ok()
+            .map(|path| path.display().to_string()),
+        target: Some(url),
+        data: None,
+    }
+}
+/// Links for import paths and the file key of configurations, unresolved ones are omitted
+pub fn document_links(root: &Snapshot, uri: &Url) -> Option<Vec<DocumentLink>> {
+    let id = root.file_id(uri)?;
+    if let Some(conf) = root.configs.get(&id) {
+        let config = conf.config.as_ref()?;
+        if !root.contains_id(config.file) {
+            return Some(Vec::new());
+        }
+        let range = lsp_range(config.file_span.clone(), &conf.source)?;
+        return Some(vec![file_link(range, config.file)]);
+    }
+    let file = root.file(id);
+    Some(
+        root.fs()
+            .imports(id)
+            .filter_map(|(sym, target)| {
+                let Symbol::Import(i) = sym else {
+                    return None;
+                };
+                let range = lsp_range(file.get_import(i)?.path.range(), &file.source)?;
+                Some(file_link(range, target))
+            })
+            .collect(),
+    )
+}
+/// Symbol renamed from pos together with the source range of the name under the cursor
+fn rename_target(
+    root: &Snapshot,
+    draft: &Draft,
+    pos: &Position,
+    uri: &Url,
+) -> Option<(RootSymbol, Range)> {
+    let Draft::UVL { source, .. } = draft else {
+        return None;
+    };
+    let obj = find_text_object(draft, pos, root.file_id(uri)?, root)?;
+    if !matches!(
+        obj.kind,
+        TextObjectKind::Feature
+            | TextObjectKind::Attribute
+            | TextObjectKind::FeatureReference
+            | TextObjectKind::Reference(..)
+    ) {
+        return None;
+    }
+    let def = find_definitions(root, draft, pos, uri)?
+        .into_iter()
+        .next()?;
+    //library files are read only
+    if !matches!(def.sym, Symbol::Feature(..) | Symbol::Attribute(..))
+        || root.fs().is_library(def.file)
+    {
+        return None;
+    }
+    let span = obj.path.spans.get(obj.selected_segment)?.clone();
+    Some((def, lsp_range(span, source)?))
+}
+pub fn prepare_rename(
+    root: &Snapshot,
+    draft: &Draft,
+    uri: &Url,
+    pos: &Position,
+) -> Option<PrepareRenameResponse> {
+    let (def, range) = rename_target(root, draft, pos, uri)?;
+    let name = root.file(def.file).name(def.sym)?;
+    Some(PrepareRenameResponse::RangeWithPlaceholder {
+        range,
+        placeholder: name.to_string(),
+    })
+}
+/// Split a new name into its value and source text, names are quoted if necessary
+fn check_name(name: &str) -> std::result::Result<(Ustr, String), String> {
+    if let Some(inner) = name
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    {
+        if is_quotable_name(inner) && !inner.is_empty() {
+            return Ok((inner.into(), name.into()));
+        }
+    } else if is_plain_name(name) {
+        return Ok((name.into(), name.into()));
+    } else if is_quotable_name(name) && !name.is_empty() {
+        return Err(format!(
+            "{name} is not a valid name, use \"{name}\" instead"
+        ));
+    }
+    Err(format!("{name} can not be used as a name"))
+}
+/// Configuration keys naming tgt, each location covers the key segment bound to tgt
+fn config_references(root: &Snapshot, tgt: RootSymbol) -> Vec<Location> {
+    let mut out = Vec::new();
+    for conf in root.configs.values() {
+        let Some(config) = conf.config.as_ref() else {
+            continue;
+        };
+        let mut stack = vec![(config.file, config.config.as_slice())];
+        while let Some((file_id, entries)) = stack.pop() {
+            let Some(file) = root.files.get(&file_id) else {
+                continue;
+            };
+            for entry in entries {
+                match entry {
+                    ConfigEntry::Value(path, val) => {
+                        if file_id == tgt.file {
+                            if let Some(span) = (0..path.len())
+                                .find(|i| {
+                                    file.lookup(Symbol::Root, &path.names[..=*i], |_| true)
+                                        .any(|sym| sym == tgt.sym)
+                                })
+                                .and_then(|i| path.spans.get(i))
+                            {
+                                if let Some(range) = lsp_range(span.clone(), &conf.source) {
+                                    out.push(Location {
+                                        uri: conf.uri.clone(),
+                                        range,
+                                    });
+                                }
+                            }
+                        }
+                        if let ConfigValue::Cardinality(CardinalityEntry::CardinalityLvl(
+                            instances,
+                        )) = val
+                        {
+                            stack.extend(instances.iter().map(|i| (file_id, i.as_slice())));
+                        }
+                    }
+                    ConfigEntry::Import(path, val) => {
+                        let import = file
+                            .lookup(Symbol::Root, &path.names, |sym| {
+                                matches!(sym, Symbol::Import(..) | Symbol::Dir(..))
+                            })
+                            .find(|sym| matches!(sym, Symbol::Import(..)));
+                        if let Some((_, dst)) = import.and_then(|import| {
+                            root.fs().imports(file_id).find(|(sym, _)| *sym == import)
+                        }) {
+                            stack.push((dst, val.as_slice()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
 pub fn rename(
     root: &Snapshot,
     draft: &Draft,
     uri: &Url,
     pos: &Position,
     new_text: String,
-) -> Option<WorkspaceEdit> {
+) -> std::result::Result<Option<WorkspaceEdit>, String> {
+    let Some((def, _)) = rename_target(root, draft, pos, uri) else {
+        return Ok(None);
+    };
+    let (name, new_text) = check_name(new_text.trim())?;
+    //names have to be unique among features of a file or attributes of a parent
+    let file = root.file(def.file);
+    let clash = match def.sym {
+        Symbol::Feature(..) => file
+            .all_features()
+            .any(|f| f != def.sym && file.name(f) == Some(name)),
+        _ => file
+            .parent(def.sym, false)
+            .map(|p| {
+                file.direct_children(p)
+                    .any(|a| a != def.sym && file.name(a) == Some(name))
+            })
+            .unwrap_or(false),
+    };
+    if clash {
+        return Err(format!("{name} already exists"));
+    }
     let mut changes = std::collections::HashMap::<Url, Vec<TextEdit>>::new();
+    let mut push = |uri: &Url, range: Range, new_text: String| {
+        changes
+            .entry(uri.clone())
+            .or_default()
+            .push(TextEdit { range, new_text });
+    };
 
     // Add definition changes
-    let binding = find_definitions(root, draft, pos, uri)?;
-    let RootSymbol { file, sym } = binding.iter().next()?;
-    changes.insert(
-        file.url(),
-        vec![TextEdit {
-            range: root.file(*file).lsp_range(*sym)?,
-            new_text: new_text.clone(),
-        }],
-    );
-
+    if let Some(range) = file.lsp_range(def.sym) {
+        push(&file.uri, range, new_text.clone());
+    }
     // Add reference changes
-    find_references(root, draft, pos, uri)?
-        .iter()
-        .for_each(|location| {
-            let edit = TextEdit {
-                range: location.range,
-                new_text: new_text.clone(),
-            };
-            if let Some(edits) = changes.get_mut(&location.uri) {
-                edits.push(edit);
-            } else {
-                changes.insert(location.uri.clone(), vec![edit]);
-            }
-        });
+    for location in find_references(root, draft, pos, uri).unwrap_or_default() {
+        push(&location.uri, location.range, new_text.clone());
+    }
+    // Add configuration keys, they always use the raw name
+    for location in config_references(root, def) {
+        push(&location.uri, location.range, name.to_string());
+    }
 
-    Some(WorkspaceEdit {
+    Ok(Some(WorkspaceEdit {
         changes: Some(changes),
         document_changes: None,
         change_annotations: None,
-    })
+    }))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ide::refactor::tests::{apply, root_graph};
+    use std::sync::Arc;
+    const MAIN: &str = "file:///ws/main.uvl";
+    const CONFIG: &str = "file:///ws/main.json";
+    fn setup() -> (Snapshot, Draft) {
+        let root = root_graph(
+            &[
+                (
+                    MAIN,
+                    "features\n    Main\n        optional\n            Engine\nconstraints\n    Engine => Main\n",
+                ),
+                (CONFIG, r#"{"file":"main.uvl","config":{"Engine":true}}"#),
+            ],
+            &ImportRoots::default(),
+        );
+        let file = root.file_by_uri(&Url::parse(MAIN).unwrap()).unwrap();
+        let draft = Draft::UVL {
+            source: file.source.clone(),
+            tree: file.tree.clone(),
+            timestamp: file.timestamp,
+            edits: EditLog::new(file.timestamp),
+        };
+        (Arc::new(root), draft)
+    }
+    fn rename_engine(new_name: &str) -> std::result::Result<Option<WorkspaceEdit>, String> {
+        let (root, draft) = setup();
+        let uri = Url::parse(MAIN).unwrap();
+        rename(&root, &draft, &uri, &Position::new(3, 14), new_name.into())
+    }
+    fn edited(edit: &WorkspaceEdit, uri: &str) -> String {
+        let (root, _) = setup();
+        let changes = edit.changes.as_ref().unwrap();
+        apply(&root, uri, &changes[&Url::parse(uri).unwrap()])
+    }
+    #[test]
+    fn test_prepare_rename() {
+        let (root, draft) = setup();
+        let uri = Url::parse(MAIN).unwrap();
+        let Some(PrepareRenameResponse::RangeWithPlaceholder { placeholder, .. }) =
+            prepare_rename(&root, &draft, &uri, &Position::new(5, 5))
+        else {
+            panic!()
+        };
+        assert_eq!(placeholder, "Engine");
+        //keywords are no symbols
+        assert!(prepare_rename(&root, &draft, &uri, &Position::new(0, 2)).is_none());
+    }
+    #[test]
+    fn test_rename_validation() {
+        //keywords have to be quoted
+        assert_eq!(
+            rename_engine("constraints"),
+            Err("constraints is not a valid name, use \"constraints\" instead".into())
+        );
+        assert_eq!(
+            rename_engine("a\"b"),
+            Err("a\"b can not be used as a name".into())
+        );
+        assert_eq!(rename_engine("Main"), Err("Main already exists".into()));
+        assert_eq!(
+            rename_engine("Big Engine"),
+            Err("Big Engine is not a valid name, use \"Big Engine\" instead".into())
+        );
+        let edit = rename_engine("\"Big Engine\"").unwrap().unwrap();
+        assert_eq!(
+            edited(&edit, MAIN),
+            "features\n    Main\n        optional\n            \"Big Engine\"\nconstraints\n    \"Big Engine\" => Main\n"
+        );
+        //configuration keys always use the raw name
+        assert_eq!(
+            edited(&edit, CONFIG),
+            r#"{"file":"main.uvl","config":{"Big Engine":true}}"#
+        );
+    }
+    #[test]
+    fn test_rename_config_key() {
+        let edit = rename_engine("Motor").unwrap().unwrap();
+        assert_eq!(
+            edited(&edit, CONFIG),
+            r#"{"file":"main.uvl","config":{"Motor":true}}"#
+        );
+    }
+    #[test]
+    fn test_selection_range_nesting() {
+        let (_, draft) = setup();
+        let selection = crate::ide::selection::selection_ranges(&draft, &[Position::new(5, 5)]);
+        let mut ranges = Vec::new();
+        let mut next = selection.first();
+        while let Some(s) = next {
+            ranges.push(s.range);
+            next = s.parent.as_deref();
+        }
+        //from the name over the constraint and its block up to the document
+        assert_eq!(
+            ranges,
+            [
+                Range::new(Position::new(5, 4), Position::new(5, 10)),
+                Range::new(Position::new(5, 4), Position::new(5, 18)),
+                Range::new(Position::new(4, 0), Position::new(5, 18)),
+                Range::new(Position::new(0, 0), Position::new(5, 18)),
+            ]
+        );
+    }
+    #[test]
+    fn test_highlight_kinds() {
+        let (root, draft) = setup();
+        let uri = Url::parse(MAIN).unwrap();
+        let highlights = document_highlight(&root, &draft, &Position::new(5, 5), &uri).unwrap();
+        assert_eq!(
+            highlights,
+            [
+                DocumentHighlight {
+                    range: Range::new(Position::new(3, 12), Position::new(3, 18)),
+                    kind: Some(DocumentHighlightKind::WRITE),
+                },
+                DocumentHighlight {
+                    range: Range::new(Position::new(5, 4), Position::new(5, 10)),
+                    kind: Some(DocumentHighlightKind::READ),
+                },
+            ]
+        );
+        //configurations have no highlights
+        assert!(document_highlight(
+            &root,
+            &draft,
+            &Position::new(0, 2),
+            &Url::parse(CONFIG).unwrap()
+        )
+        .is_none());
+    }
+    #[test]
+    fn test_document_links() {
+        let root = Arc::new(root_graph(
+            &[
+                (
+                    MAIN,
+                    "imports\n    sub.Engine as E\n    Missing\nfeatures\n    Main\n",
+                ),
+                ("file:///ws/sub/Engine.uvl", "features\n    Engine\n"),
+                (CONFIG, r#"{"file":"main.uvl","config":{}}"#),
+            ],
+            &ImportRoots::default(),
+        ));
+        let links = |uri: &str| {
+            document_links(&root, &Url::parse(uri).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|l| (l.range, l.target.unwrap().to_string()))
+                .collect::<Vec<_>>()
+        };
+        //unresolved imports have no link
+        assert_eq!(
+            links(MAIN),
+            [(
+                Range::new(Position::new(1, 4), Position::new(1, 14)),
+                "file:///ws/sub/Engine.uvl".to_string()
+            )]
+        );
+        assert_eq!(
+            links(CONFIG),
+            [(
+                Range::new(Position::new(0, 8), Position::new(0, 18)),
+                MAIN.to_string()
+            )]
+        );
+    }
 }
@@ -28,6 +28,11 @@ pub mod color;
 ///  like length or type correctness
 ///
 pub mod completion;
+/// Dependency view between features and constraints
+///
+/// Uses the call hierarchy of the LSP, incoming items of a feature are the constraints
+/// referencing it and outgoing items are the features those constraints relate it to
+pub mod hierarchy;
 /// Inlay hints are used to display the configuration in the source code view.
 ///
 /// Inlays are managed as a global token state,
@@ -37,3 +42,14 @@ pub mod completion;
 pub mod inlays;
 /// handles text jumps, like go to definition etc
 pub mod location;
+/// Workspace wide refactorings
+///
+/// Edits that span multiple files, like fixing imports and configurations when files are moved
+pub mod refactor;
+/// Expand selection along the syntax tree
+///
+/// Each position is mapped to the chain of named nodes containing it, eg. name, path,
+/// sub expression, constraint and the enclosing blocks
+pub mod selection;
+/// Signature help for the built-in functions of constraints
+pub mod signature;
@@ -0,0 +1,1260 @@
+use crate::core::*;
+use crate::smt::{group_change_delta, GroupChange, SmtOptions, COUNT_LIMIT};
+use futures::future::join_all;
+use hashbrown::{HashMap, HashSet};
+use log::info;
+use ropey::Rope;
+use std::path::{Path as FsPath, PathBuf};
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+use ustr::Ustr;
+
+/// Format a path segment, names that are no plain identifiers are quoted
+pub fn path_segment(name: &str) -> String {
+    if is_plain_name(name) {
+        name.to_string()
+    } else {
+        format!("\"{name}\"")
+    }
+}
+pub fn path_to_string(path: &[Ustr]) -> String {
+    path.iter()
+        .map(|i| path_segment(i.as_str()))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+fn push_edit(
+    changes: &mut std::collections::HashMap<Url, Vec<TextEdit>>,
+    uri: &Url,
+    edit: TextEdit,
+) {
+    changes.entry(uri.clone()).or_default().push(edit);
+}
+
+/// Computes the edits needed before files or directories are renamed:
+/// imports from or to moved files, namespaces that mirror the file location and
+/// the file keys of configurations. Library files are never edited. Returns the edit
+/// and a description of every reference that can not be expressed after the move,
+/// eg. an import of a file that is no longer below any directory imports are resolved in.
+pub fn will_rename_files(
+    root: &RootGraph,
+    renames: &[FileRename],
+) -> (Option<WorkspaceEdit>, Vec<String>) {
+    let renames: Vec<(PathBuf, PathBuf)> = renames
+        .iter()
+        .filter_map(|r| {
+            let old = Url::parse(&r.old_uri).ok()?.to_file_path().ok()?;
+            let new = Url::parse(&r.new_uri).ok()?.to_file_path().ok()?;
+            Some((old, new))
+        })
+        .collect();
+    let moved = |path: &FsPath| -> PathBuf {
+        renames
+            .iter()
+            .find_map(|(old, new)| path.strip_prefix(old).ok().map(|rel| new.join(rel)))
+            .unwrap_or_else(|| path.to_path_buf())
+    };
+    let fs = root.fs();
+    let (folders, libraries) = fs.import_dirs();
+    let mut changes = std::collections::HashMap::new();
+    let mut unresolved = Vec::new();
+    //files inside the renamed paths
+    let mut in_renames = HashSet::new();
+    //logical paths after the move
+    let mut new_paths: HashMap<FileID, Vec<Ustr>> = HashMap::new();
+    for (id, file) in root.files.iter() {
+        let Ok(old_fs) = file.uri.to_file_path() else {
+            continue;
+        };
+        let new_fs = moved(&old_fs);
+        if new_fs != old_fs {
+            in_renames.insert(*id);
+        }
+        let new_fs = Url::from_file_path(new_fs)
+            .ok()
+            .and_then(|uri| uri_to_path(&uri));
+        let (Some(old_fs), Some(mut new_fs)) = (uri_to_path(&file.uri), new_fs) else {
+            continue;
+        };
+        if let Some(ns) = file.namespace() {
+            let len = new_fs.len().saturating_sub(ns.len());
+            if old_fs.ends_with(&ns.names) && !fs.is_library(*id) {
+                //the namespace mirrors the file location, so it moves along
+                if new_fs[len..] != ns.names[..] {
+                    push_edit(
+                        &mut changes,
+                        &file.uri,
+                        TextEdit {
+                            range: lsp_range(ns.range(), &file.source).unwrap_or_default(),
+                            new_text: path_to_string(&new_fs[len..]),
+                        },
+                    );
+                }
+            } else {
+                new_fs.truncate(len);
+                new_fs.extend_from_slice(&ns.names);
+            }
+        }
+        new_paths.insert(*id, new_fs);
+    }
+    let files_at: HashMap<&[Ustr], FileID> = new_paths
+        .iter()
+        .map(|(id, path)| (path.as_slice(), *id))
+        .collect();
+    //imports are resolved like Cache::candidates: relative to the importer directory,
+    //its workspace folder and the libraries
+    for (id, file) in root.files.iter() {
+        let Some(src) = new_paths.get(id) else {
+            continue;
+        };
+        let dir = &src[..src.len() - 1];
+        //the innermost folder wins for nested workspace folders
+        let workspace = folders
+            .iter()
+            .chain(libraries.iter())
+            .filter(|root| src.starts_with(root))
+            .max_by_key(|root| root.len());
+        let bases: Vec<&[Ustr]> = std::iter::once(dir)
+            .chain(workspace.cloned())
+            .chain(libraries.iter().cloned())
+            .collect();
+        let resolve = |path: &[Ustr]| {
+            bases.iter().find_map(|base| {
+                let full: Vec<Ustr> = base.iter().chain(path.iter()).cloned().collect();
+                files_at.get(full.as_slice()).cloned()
+            })
+        };
+        for (sym, tgt) in fs.imports(*id) {
+            if !in_renames.contains(id) && !in_renames.contains(&tgt) {
+                continue;
+            }
+            let Some(tgt_path) = new_paths.get(&tgt) else {
+                continue;
+            };
+            let old = file.path(sym);
+            let Symbol::Import(i) = sym else {
+                continue;
+            };
+            if resolve(old) == Some(tgt) {
+                continue;
+            }
+            let import = file.get_import(i).unwrap();
+            let new = bases
+                .iter()
+                .filter(|base| tgt_path.starts_with(base) && tgt_path.len() > base.len())
+                .map(|base| &tgt_path[base.len()..])
+                .find(|new| resolve(new) == Some(tgt));
+            let Some(new) = new else {
+                unresolved.push(format!(
+                    "{}: import {} can not be expressed after the move",
+                    file.uri,
+                    path_to_string(old)
+                ));
+                continue;
+            };
+            if fs.is_library(*id) {
+                unresolved.push(format!(
+                    "{}: import {} is part of a read only library",
+                    file.uri,
+                    path_to_string(old)
+                ));
+                continue;
+            }
+            //keep the old name so references through the import stay valid
+            let alias = match (&import.alias, new.last() == old.last()) {
+                (None, false) => format!(" as {}", path_segment(old.last().unwrap())),
+                _ => String::new(),
+            };
+            push_edit(
+                &mut changes,
+                &file.uri,
+                TextEdit {
+                    range: lsp_range(import.path.range(), &file.source).unwrap_or_default(),
+                    new_text: format!("{}{alias}", path_to_string(new)),
+                },
+            );
+        }
+    }
+    //configuration file keys are relative to the configuration directory
+    for conf in root.configs.values() {
+        let (Some(config), Ok(conf_path)) = (conf.config.as_ref(), conf.uri.to_file_path()) else {
+            continue;
+        };
+        let old_tgt = config.file.filepath();
+        let new_tgt = moved(&old_tgt);
+        let new_conf = moved(&conf_path);
+        if new_tgt == old_tgt && new_conf == conf_path {
+            continue;
+        }
+        let in_library = uri_to_path(&conf.uri)
+            .map(|path| libraries.iter().any(|lib| path.starts_with(lib)))
+            .unwrap_or(false);
+        if in_library {
+            continue;
+        }
+        let Some(rel) = new_conf
+            .parent()
+            .and_then(|dir| new_tgt.strip_prefix(dir).ok())
+            .and_then(|rel| rel.to_str())
+        else {
+            unresolved.push(format!(
+                "{}: {} is not below the configuration directory after the move",
+                conf.uri,
+                new_tgt.display()
+            ));
+            continue;
+        };
+        let old_rel = conf_path
+            .parent()
+            .and_then(|dir| old_tgt.strip_prefix(dir).ok())
+            .and_then(|rel| rel.to_str());
+        if old_rel == Some(rel) {
+            continue;
+        }
+        push_edit(
+            &mut changes,
+            &conf.uri,
+            TextEdit {
+                range: lsp_range(config.file_span.clone(), &conf.source).unwrap_or_default(),
+                new_text: serde_json::to_string(rel).unwrap(),
+            },
+        );
+    }
+    info!("rename files: {} edited files", changes.len());
+    if changes.is_empty() {
+        (None, unresolved)
+    } else {
+        (
+            Some(WorkspaceEdit {
+                changes: Some(changes),
+                document_changes: None,
+                change_annotations: None,
+            }),
+            unresolved,
+        )
+    }
+}
+
+/// Lines covered by node without trailing whitespace
+fn node_lines(node: Node, source: &Rope) -> std::ops::Range<usize> {
+    let text = source.byte_slice(node.byte_range()).to_string();
+    let end = node.start_byte() + text.trim_end().len().max(1) - 1;
+    source.byte_to_line(node.start_byte())..source.byte_to_line(end) + 1
+}
+fn lines_range(lines: &std::ops::Range<usize>) -> Range {
+    Range {
+        start: Position::new(lines.start as u32, 0),
+        end: Position::new(lines.end as u32, 0),
+    }
+}
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+/// Text of lines, the common indentation is replaced by indent.
+/// Remaining indentation is translated between indent units if given.
+fn reindent(
+    source: &Rope,
+    lines: &std::ops::Range<usize>,
+    indent: &str,
+    units: Option<(&str, &str)>,
+) -> String {
+    let first = source.line(lines.start).to_string();
+    let base = leading_whitespace(&first).to_string();
+    let mut out = String::new();
+    for i in lines.clone() {
+        let line = source.line(i).to_string();
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            out.push('\n');
+            continue;
+        }
+        let mut line = line
+            .strip_prefix(base.as_str())
+            .unwrap_or(line.trim_start());
+        out.push_str(indent);
+        if let Some((from, to)) = units.filter(|(from, _)| !from.is_empty()) {
+            while let Some(rest) = line.strip_prefix(from) {
+                out.push_str(to);
+                line = rest;
+            }
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+/// top level block with a header of kind
+fn top_level_blk<'a>(file: &'a AstDocument, kind: &str) -> Option<Node<'a>> {
+    let root = file.tree.root_node();
+    let mut cursor = root.walk();
+    let blk = root
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "blk" && header_kind(*n) == kind);
+    blk
+}
+/// indentation of the first child of the features section
+fn indent_unit(file: &AstDocument) -> String {
+    top_level_blk(file, "features")
+        .and_then(|blk| blk.child_by_field_name("child"))
+        .map(|child| {
+            let line = file
+                .source
+                .line(file.source.byte_to_line(child.start_byte()));
+            leading_whitespace(&line.to_string()).to_string()
+        })
+        .filter(|i| !i.is_empty())
+        .unwrap_or("\t".into())
+}
+fn symbol_blk<'a>(file: &'a AstDocument, sym: Symbol) -> Option<Node<'a>> {
+    let span = file.span(sym)?;
+    containing_blk(
+        file.tree
+            .root_node()
+            .descendant_for_byte_range(span.start, span.start)?,
+    )
+}
+fn text_edit(uri: &Url, edits: Vec<TextEdit>) -> DocumentChangeOperation {
+    DocumentChangeOperation::Edit(TextDocumentEdit {
+        text_document: OptionalVersionedTextDocumentIdentifier {
+            uri: uri.clone(),
+            version: None,
+        },
+        edits: edits.into_iter().map(OneOf::Left).collect(),
+    })
+}
+
+/// Removes the lines of an import, or the whole imports section for the last import
+pub fn remove_import(file: &AstDocument, import: Symbol) -> Option<TextEdit> {
+    let imports = top_level_blk(file, "imports")?;
+    let import_lines = if file.all_imports().count() == 1 {
+        node_lines(imports, &file.source)
+    } else {
+        node_lines(symbol_blk(file, import)?, &file.source)
+    };
+    Some(TextEdit {
+        range: lines_range(&import_lines),
+        new_text: String::new(),
+    })
+}
+/// Move the subtree of the feature at offset into a new file next to the current one.
+/// Constraints that only reference the subtree move along, the feature is replaced by
+/// a reference to the imported root and remaining references are prefixed with the import.
+pub fn extract_subtree(root: &RootGraph, id: FileID, offset: usize) -> Option<CodeAction> {
+    let file = root.file(id);
+    let feature = file
+        .find(offset)
+        .filter(|sym| matches!(sym, Symbol::Feature(..)))?;
+    if file.parent(feature, false)? == Symbol::Root || file.cardinality(feature).is_some() {
+        return None;
+    }
+    let name = file.name(feature)?;
+    let mut subtree = HashSet::new();
+    subtree.insert(feature);
+    file.visit_children(feature, false, |sym| match sym {
+        Symbol::Feature(..) => {
+            subtree.insert(sym);
+            true
+        }
+        Symbol::Group(..) => true,
+        _ => false,
+    });
+    let blk = symbol_blk(file, feature)?;
+    let blk_span = blk.byte_range();
+    let target = |sym: Symbol| -> Option<bool> {
+        let tgt = root.resolve_reference(id, sym).next()?;
+        Some(tgt.file == id && subtree.contains(&file.scope(tgt.sym)))
+    };
+    //references leaving the subtree can not be extracted
+    let mut outer_refs = Vec::new();
+    for r in file.all_references() {
+        let span = file.span(r)?;
+        if blk_span.contains(&span.start) {
+            if !target(r)? {
+                return None;
+            }
+        } else if target(r) == Some(true) {
+            outer_refs.push(r);
+        }
+    }
+    let moved_constraints: Vec<Symbol> = file
+        .all_constraints()
+        .filter(|c| file.scope(*c) == Symbol::Root)
+        .filter(|c| {
+            let refs = file.constraint_references(*c);
+            !refs.is_empty() && refs.iter().all(|r| target(*r).unwrap_or(false))
+        })
+        .collect();
+    let dir = file.uri.to_file_path().ok()?.parent()?.to_path_buf();
+    let new_uri = Url::from_file_path(dir.join(format!("{name}.uvl"))).ok()?;
+    if root.file_id(&new_uri).is_some() || dir.join(format!("{name}.uvl")).exists() {
+        return None;
+    }
+    //the namespace keeps the logical path of the new file next to the current one
+    let mut namespace: Vec<Ustr> = file
+        .namespace()
+        .map(|ns| ns.names[..ns.names.len() - 1].to_vec())
+        .unwrap_or_default();
+    namespace.push(name);
+    let mut alias = name;
+    for i in 1.. {
+        //the subtree is removed, so its own names are free
+        if !file
+            .lookup(Symbol::Root, &[alias], |_| true)
+            .any(|sym| !subtree.contains(&sym))
+        {
+            break;
+        }
+        alias = format!("{name}{i}").as_str().into();
+    }
+    let unit = indent_unit(file);
+    let constraints_blk = top_level_blk(file, "constraints");
+    let constraint_lines: Vec<_> = moved_constraints
+        .iter()
+        .filter_map(|c| symbol_blk(file, *c).map(|n| node_lines(n, &file.source)))
+        .collect();
+    //new file
+    let mut content = format!("namespace {}\n\n", path_to_string(&namespace));
+    if let Some(include) = top_level_blk(file, "include") {
+        content.push_str(&reindent(
+            &file.source,
+            &node_lines(include, &file.source),
+            "",
+            None,
+        ));
+        content.push('\n');
+    }
+    content.push_str("features\n");
+    content.push_str(&reindent(
+        &file.source,
+        &node_lines(blk, &file.source),
+        &unit,
+        None,
+    ));
+    if !constraint_lines.is_empty() {
+        content.push_str("\nconstraints\n");
+        for lines in constraint_lines.iter() {
+            content.push_str(&reindent(&file.source, lines, &unit, None));
+        }
+    }
+    //current file
+    let mut edits = Vec::new();
+    let blk_lines = node_lines(blk, &file.source);
+    let indent = leading_whitespace(&file.source.line(blk_lines.start).to_string()).to_string();
+    let import = if alias == name {
+        path_segment(&name)
+    } else {
+        format!("{} as {}", path_segment(&name), path_segment(&alias))
+    };
+    if let Some(imports) = top_level_blk(file, "imports") {
+        let end = node_lines(imports, &file.source).end;
+        edits.push(TextEdit {
+            range: lines_range(&(end..end)),
+            new_text: format!("{unit}{import}\n"),
+        });
+    } else {
+        let features = top_level_blk(file, "features")?;
+        let start = file.source.byte_to_line(features.start_byte());
+        edits.push(TextEdit {
+            range: lines_range(&(start..start)),
+            new_text: format!("imports\n{unit}{import}\n\n"),
+        });
+    }
+    edits.push(TextEdit {
+        range: lines_range(&blk_lines),
+        new_text: format!("{indent}{}.{}\n", path_segment(&alias), path_segment(&name)),
+    });
+    let all_moved = file
+        .all_constraints()
+        .filter(|c| file.scope(*c) == Symbol::Root)
+        .count()
+        == moved_constraints.len();
+    match constraints_blk {
+        Some(blk) if all_moved => edits.push(TextEdit {
+            range: lines_range(&node_lines(blk, &file.source)),
+            new_text: String::new(),
+        }),
+        _ => {
+            for lines in constraint_lines.iter() {
+                edits.push(TextEdit {
+                    range: lines_range(lines),
+                    new_text: String::new(),
+                });
+            }
+        }
+    }
+    let moved_span = |r: Symbol| {
+        let start = file.span(r).unwrap().start;
+        moved_constraints
+            .iter()
+            .any(|c| file.span(*c).map(|s| s.contains(&start)).unwrap_or(false))
+    };
+    for r in outer_refs.into_iter().filter(|r| !moved_span(*r)) {
+        let start = lsp_position(file.span(r)?.start, &file.source)?;
+        edits.push(TextEdit {
+            range: Range { start, end: start },
+            new_text: format!("{}.", path_segment(&alias)),
+        });
+    }
+    Some(CodeAction {
+        title: format!("Extract {name} into {name}.uvl"),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        edit: Some(WorkspaceEdit {
+            changes: None,
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                    uri: new_uri.clone(),
+                    options: Some(CreateFileOptions {
+                        overwrite: Some(false),
+                        ignore_if_exists: Some(false),
+                    }),
+                    annotation_id: None,
+                })),
+                text_edit(
+                    &new_uri,
+                    vec![TextEdit {
+                        range: Range::default(),
+                        new_text: content,
+                    }],
+                ),
+                text_edit(&file.uri, edits),
+            ])),
+            change_annotations: None,
+        }),
+        ..Default::default()
+    })
+}
+
+/// Paste the feature tree of the imported file at offset in place of its reference.
+/// Constraints of the submodel are merged and references through the import become local.
+pub fn inline_import(root: &RootGraph, id: FileID, offset: usize) -> Option<CodeAction> {
+    let file = root.file(id);
+    let import = file
+        .find(offset)
+        .filter(|sym| matches!(sym, Symbol::Import(..)))?;
+    let (_, tgt) = root.fs().imports(id).find(|(sym, _)| *sym == import)?;
+    if tgt.is_config() {
+        return None;
+    }
+    let sub = root.file(tgt);
+    let path = path_to_string(file.path(import));
+    let title = format!("Inline {path}");
+    let disabled = |reason: String| CodeAction {
+        title: title.clone(),
+        kind: Some(CodeActionKind::REFACTOR_INLINE),
+        disabled: Some(CodeActionDisabled { reason }),
+        ..Default::default()
+    };
+    //nested imports would need to be rebased, keep it simple
+    if sub.all_imports().next().is_some() {
+        return Some(disabled(format!("{path} has imports of its own")));
+    }
+    let prefix = file.import_prefix(import);
+    let through_import = |r: Symbol| {
+        file.path(r).starts_with(prefix)
+            && root
+                .resolve_reference(id, r)
+                .next()
+                .map(|t| t.file == tgt)
+                .unwrap_or(false)
+    };
+    let refs: Vec<Symbol> = file
+        .all_references()
+        .filter(|r| through_import(*r))
+        .collect();
+    let mut tree_refs = refs
+        .iter()
+        .filter(|r| matches!(file.parent(**r, false), Some(Symbol::Group(..))));
+    let (Some(&tree_ref), None) = (tree_refs.next(), tree_refs.next()) else {
+        return None;
+    };
+    let sub_root = root.resolve_reference(id, tree_ref).next()?.sym;
+    if sub.parent(sub_root, false)? != Symbol::Root {
+        return None;
+    }
+    //features of the submodel must not shadow anything in the importer
+    let clashes: Vec<String> = sub
+        .all_features()
+        .filter_map(|f| sub.name(f))
+        .filter(|name| {
+            file.lookup(Symbol::Root, std::slice::from_ref(name), |_| true)
+                .any(|sym| sym != import)
+        })
+        .map(|name| path_segment(&name))
+        .collect();
+    if !clashes.is_empty() {
+        return Some(disabled(format!("name clash: {}", clashes.join(", "))));
+    }
+    let unit = indent_unit(file);
+    let sub_unit = indent_unit(sub);
+    let mut edits = Vec::new();
+    //tree
+    let ref_lines = node_lines(symbol_blk(file, tree_ref)?, &file.source);
+    let indent = leading_whitespace(&file.source.line(ref_lines.start).to_string()).to_string();
+    edits.push(TextEdit {
+        range: lines_range(&ref_lines),
+        new_text: reindent(
+            &sub.source,
+            &node_lines(symbol_blk(sub, sub_root)?, &sub.source),
+            &indent,
+            Some((&sub_unit, &unit)),
+        ),
+    });
+    //references
+    for r in refs.iter().filter(|r| **r != tree_ref) {
+        let Some(reference) = (match r {
+            Symbol::Reference(i) => file.get_reference(*i),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let spans = &reference.path.spans;
+        let (Some(start), Some(end)) = (spans.first(), spans.get(prefix.len())) else {
+            continue;
+        };
+        edits.push(TextEdit {
+            range: lsp_range(start.start..end.start, &file.source)?,
+            new_text: String::new(),
+        });
+    }
+    edits.push(remove_import(file, import)?);
+    //constraints
+    if let Some(constraints) = top_level_blk(sub, "constraints") {
+        let lines = node_lines(constraints, &sub.source);
+        let text = reindent(
+            &sub.source,
+            &(lines.start + 1..lines.end),
+            &unit,
+            Some((&sub_unit, &unit)),
+        );
+        if let Some(own) = top_level_blk(file, "constraints") {
+            let end = node_lines(own, &file.source).end;
+            edits.push(TextEdit {
+                range: lines_range(&(end..end)),
+                new_text: text,
+            });
+        } else {
+            let end = lsp_position(file.source.len_bytes(), &file.source)?;
+            let newline = if file.source.len_chars() == 0
+                || file.source.char(file.source.len_chars() - 1) == '\n'
+            {
+                ""
+            } else {
+                "\n"
+            };
+            edits.push(TextEdit {
+                range: Range { start: end, end },
+                new_text: format!("{newline}\nconstraints\n{text}"),
+            });
+        }
+    }
+    Some(CodeAction {
+        title,
+        kind: Some(CodeActionKind::REFACTOR_INLINE),
+        edit: Some(WorkspaceEdit {
+            changes: Some([(file.uri.clone(), edits)].into_iter().collect()),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        ..Default::default()
+    })
+}
+
+/// Source text of a group mode, unset cardinalities have none
+fn group_text(mode: &GroupMode) -> Option<String> {
+    match mode {
+        GroupMode::Mandatory => Some("mandatory".into()),
+        GroupMode::Or => Some("or".into()),
+        GroupMode::Optional => Some("optional".into()),
+        GroupMode::Alternative => Some("alternative".into()),
+        GroupMode::Cardinality(Cardinality::Range(min, max)) => Some(format!("[{min}..{max}]")),
+        GroupMode::Cardinality(Cardinality::AtLeast(min)) => Some(format!("[{min}..*]")),
+        GroupMode::Cardinality(Cardinality::Fixed) => None,
+    }
+}
+/// Inverse of group_text
+fn parse_group_mode(text: &str) -> Option<GroupMode> {
+    match text {
+        "mandatory" => Some(GroupMode::Mandatory),
+        "or" => Some(GroupMode::Or),
+        "optional" => Some(GroupMode::Optional),
+        "alternative" => Some(GroupMode::Alternative),
+        _ => {
+            let (min, max) = text
+                .strip_prefix('[')?
+                .strip_suffix(']')?
+                .split_once("..")?;
+            let min = min.parse().ok()?;
+            match max {
+                "*" => Some(GroupMode::Cardinality(Cardinality::AtLeast(min))),
+                max => Some(GroupMode::Cardinality(Cardinality::Range(
+                    min,
+                    max.parse().ok().filter(|max| *max >= min)?,
+                ))),
+            }
+        }
+    }
+}
+/// Innermost group whose span contains offset
+fn group_at(file: &AstDocument, offset: usize) -> Option<(Symbol, Span)> {
+    let mut group = None;
+    file.visit_children(Symbol::Root, true, |sym| {
+        if matches!(sym, Symbol::Group(..)) {
+            if let Some(span) = file.span(sym) {
+                if span.start <= offset && offset <= span.end {
+                    group = Some((sym, span));
+                }
+            }
+        }
+        true
+    });
+    group
+}
+/// Convert the group at offset into the other group kinds, keyword groups are also
+/// converted into the cardinality with the same semantics
+pub fn change_group_mode(root: &RootGraph, id: FileID, offset: usize) -> Vec<CodeAction> {
+    let file = root.file(id);
+    let Some((group, span)) = group_at(file, offset) else {
+        return Vec::new();
+    };
+    let Some(range) = lsp_range(span.clone(), &file.source) else {
+        return Vec::new();
+    };
+    let current = file.group_mode(group);
+    let children = file.direct_children(group).count();
+    let cardinality = match current {
+        Some(GroupMode::Mandatory) => Some((children, children)),
+        Some(GroupMode::Or) => Some((1, children)),
+        Some(GroupMode::Optional) => Some((0, children)),
+        Some(GroupMode::Alternative) => Some((1, 1)),
+        _ => None,
+    };
+    let current = current.and_then(|m| group_text(&m));
+    [
+        GroupMode::Mandatory,
+        GroupMode::Or,
+        GroupMode::Optional,
+        GroupMode::Alternative,
+    ]
+    .into_iter()
+    .chain(cardinality.map(|(min, max)| GroupMode::Cardinality(Cardinality::Range(min, max))))
+    .filter_map(|mode| group_text(&mode))
+    .filter(|text| Some(text) != current.as_ref())
+    .map(|text| CodeAction {
+        title: format!("Change group to {text}"),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(
+                [(
+                    file.uri.clone(),
+                    vec![TextEdit {
+                        range,
+                        new_text: text.clone(),
+                    }],
+                )]
+                .into_iter()
+                .collect(),
+            ),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        data: Some(serde_json::json!({
+            "groupChange": {
+                "uri": file.uri,
+                "offset": span.start,
+                "mode": text,
+            }
+        })),
+        ..Default::default()
+    })
+    .collect()
+}
+/// Uri of the document a group change action belongs to
+pub fn group_change_uri(action: &CodeAction) -> Option<Url> {
+    let uri = action
+        .data
+        .as_ref()?
+        .get("groupChange")?
+        .get("uri")?
+        .as_str()?;
+    Url::parse(uri).ok()
+}
+/// Time to compute the semantics of group changes when they are offered,
+/// slower ones are computed on resolve
+const GROUP_CHANGE_OFFER_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(300);
+/// Document, group offset and new mode of a group change
+type GroupChangeKey = (Url, usize, String);
+/// Semantics summaries of group changes of the latest revision, None if the analysis failed
+#[derive(Default)]
+pub struct GroupChangeCache {
+    state: parking_lot::Mutex<(u64, HashMap<GroupChangeKey, Option<String>>)>,
+}
+impl GroupChangeCache {
+    fn get(&self, revision: u64, key: &GroupChangeKey) -> Option<Option<String>> {
+        let state = self.state.lock();
+        if state.0 != revision {
+            return None;
+        }
+        state.1.get(key).cloned()
+    }
+    fn insert(&self, revision: u64, key: GroupChangeKey, summary: Option<String>) {
+        let mut state = self.state.lock();
+        if state.0 != revision {
+            *state = (revision, HashMap::new());
+        }
+        state.1.insert(key, summary);
+    }
+}
+/// Change in configurations of a group change action, eg. "adds 2 / removes 0 configurations"
+async fn group_change_summary(
+    root: &RootGraph,
+    action: &CodeAction,
+    options: &SmtOptions,
+    cache: &GroupChangeCache,
+) -> Option<String> {
+    let data = action.data.as_ref()?.get("groupChange")?;
+    let key = (
+        group_change_uri(action)?,
+        data.get("offset")?.as_u64()? as usize,
+        data.get("mode")?.as_str()?.to_string(),
+    );
+    if let Some(summary) = cache.get(root.revision(), &key) {
+        return summary;
+    }
+    let mode = parse_group_mode(&key.2)?;
+    let id = root.file_id(&key.0).filter(|id| !id.is_config())?;
+    let (group, _) = group_at(root.file(id), key.1).filter(|(_, span)| span.start == key.1)?;
+    let change = GroupChange {
+        file: id,
+        group,
+        mode,
+    };
+    let count = |n: usize| {
+        if n >= COUNT_LIMIT {
+            format!("{n}+")
+        } else {
+            n.to_string()
+        }
+    };
+    let summary = match group_change_delta(root, &change, options).await {
+        Ok((0, 0)) => Some("preserves semantics".to_string()),
+        Ok((added, removed)) => Some(format!(
+            "adds {} / removes {} configurations",
+            count(added),
+            count(removed)
+        )),
+        Err(e) => {
+            info!("group change analysis failed: {e}");
+            None
+        }
+    };
+    cache.insert(root.revision(), key, summary.clone());
+    summary
+}
+fn add_summary(action: &mut CodeAction, summary: Option<String>) {
+    let mode = action.data.as_ref().and_then(|d| {
+        d.get("groupChange")?
+            .get("mode")?
+            .as_str()
+            .map(String::from)
+    });
+    if let (Some(mode), Some(summary)) = (mode, summary) {
+        action.title = format!("Change group to {mode} ({summary})");
+    }
+}
+/// Add the change in configurations to the titles of group change actions,
+/// changes which take too long to analyse are left for resolve
+pub async fn summarize_group_changes(
+    root: &RootGraph,
+    actions: &mut [CodeActionOrCommand],
+    options: &SmtOptions,
+    cache: &GroupChangeCache,
+) {
+    let summaries = join_all(actions.iter().map(|action| async move {
+        match action {
+            CodeActionOrCommand::CodeAction(action) if group_change_uri(action).is_some() => {
+                tokio::time::timeout(
+                    GROUP_CHANGE_OFFER_TIMEOUT,
+                    group_change_summary(root, action, options, cache),
+                )
+                .await
+                .ok()
+                .flatten()
+            }
+            _ => None,
+        }
+    }))
+    .await;
+    for (action, summary) in actions.iter_mut().zip(summaries) {
+        if let CodeActionOrCommand::CodeAction(action) = action {
+            add_summary(action, summary);
+        }
+    }
+}
+/// Add the change in configurations to the title of a group change action
+pub async fn resolve_group_change(
+    root: &RootGraph,
+    mut action: CodeAction,
+    options: &SmtOptions,
+    cache: &GroupChangeCache,
+) -> CodeAction {
+    let summary = group_change_summary(root, &action, options, cache).await;
+    add_summary(&mut action, summary);
+    action
+}
+
+/// Refactorings available at range, independent of diagnostics
+pub fn code_actions(root: &RootGraph, uri: &Url, range: Range) -> Vec<CodeActionOrCommand> {
+    let Some(id) = root.file_id(uri).filter(|id| !id.is_config()) else {
+        return Vec::new();
+    };
+    let offset = byte_offset(&range.start, &root.file(id).source);
+    [
+        extract_subtree(root, id, offset),
+        inline_import(root, id, offset),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(change_group_mode(root, id, offset))
+    .map(CodeActionOrCommand::CodeAction)
+    .collect()
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::Instant;
+    /// Link uvl and json sources given by uri
+    pub(crate) fn root_graph(sources: &[(&str, &str)], roots: &ImportRoots) -> RootGraph {
+        let mut files = AstFiles::new();
+        let mut configs = ConfigFiles::new();
+        for (uri, source) in sources {
+            let rope = Rope::from_str(source);
+            let uri = Url::parse(uri).unwrap();
+            if is_config(&uri) {
+                let tree = parse::parse_json(&rope, None);
+                let conf = config::parse_json(tree, rope, uri, Instant::now());
+                configs.insert(conf.id, Arc::new(conf));
+            } else {
+                let tree = parse::parse(&rope, None);
+                let ast = AstDocument::new(rope, tree, uri, Instant::now());
+                files.insert(ast.id, Arc::new(ast));
+            }
+        }
+        let mut err = ErrorsAcc {
+            errors: HashMap::new(),
+            files: &files,
+            configs: &configs,
+        };
+        RootGraph::new(
+            &files,
+            &configs,
+            0,
+            roots,
+            &Cache::default(),
+            &mut err,
+            &mut HashMap::new(),
+        )
+    }
+    /// Source of uri after applying the edits
+    pub(crate) fn apply(root: &RootGraph, uri: &str, edits: &[TextEdit]) -> String {
+        let uri = Url::parse(uri).unwrap();
+        let mut source = root
+            .file_by_uri(&uri)
+            .map(|f| f.source.clone())
+            .or_else(|| root.config_by_uri(&uri).map(|c| c.source.clone()))
+            .unwrap();
+        let mut edits = edits.to_vec();
+        edits.sort_by_key(|e| std::cmp::Reverse(e.range.start));
+        for e in edits {
+            let start = char_offset(&e.range.start, &source);
+            let end = char_offset(&e.range.end, &source);
+            source.remove(start..end);
+            source.insert(start, &e.new_text);
+        }
+        source.to_string()
+    }
+    fn rename(root: &RootGraph, old: &str, new: &str) -> (WorkspaceEdit, Vec<String>) {
+        let (edit, unresolved) = will_rename_files(
+            root,
+            &[FileRename {
+                old_uri: old.into(),
+                new_uri: new.into(),
+            }],
+        );
+        (edit.unwrap_or_default(), unresolved)
+    }
+    fn changes(edit: &WorkspaceEdit, uri: &str) -> Vec<TextEdit> {
+        edit.changes
+            .as_ref()
+            .and_then(|c| c.get(&Url::parse(uri).unwrap()))
+            .cloned()
+            .unwrap_or_default()
+    }
+    /// Edits of a code action for uri
+    fn action_edits(action: &CodeAction, uri: &str) -> Vec<TextEdit> {
+        let uri = Url::parse(uri).unwrap();
+        let edit = action.edit.as_ref().unwrap();
+        if let Some(changes) = edit.changes.as_ref() {
+            return changes.get(&uri).cloned().unwrap_or_default();
+        }
+        let Some(DocumentChanges::Operations(ops)) = edit.document_changes.as_ref() else {
+            return Vec::new();
+        };
+        ops.iter()
+            .filter_map(|op| match op {
+                DocumentChangeOperation::Edit(e) if e.text_document.uri == uri => Some(
+                    e.edits
+                        .iter()
+                        .filter_map(|e| match e {
+                            OneOf::Left(e) => Some(e.clone()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+    fn offset(root: &RootGraph, uri: &str, text: &str) -> (FileID, usize) {
+        let id = root.file_id(&Url::parse(uri).unwrap()).unwrap();
+        let source = root.file(id).source.to_string();
+        (id, source.find(text).unwrap())
+    }
+    #[test]
+    fn test_rename_nested_directory() {
+        let root = root_graph(
+            &[
+                (
+                    "file:///ws/main.uvl",
+                    "imports\n    sub.Engine\nfeatures\n    Main\n        optional\n            Engine.Engine\n",
+                ),
+                (
+                    "file:///ws/sub/Engine.uvl",
+                    "imports\n    Bolt\nfeatures\n    Engine\n        optional\n            Bolt.Bolt\n",
+                ),
+                ("file:///ws/sub/Bolt.uvl", "features\n    Bolt\n"),
+                (
+                    "file:///ws/main.json",
+                    r#"{"file":"sub/Engine.uvl","config":{}}"#,
+                ),
+            ],
+            &ImportRoots::default(),
+        );
+        let (edit, unresolved) = rename(&root, "file:///ws/sub", "file:///ws/parts/sub");
+        assert!(unresolved.is_empty(), "{unresolved:?}");
+        let main = changes(&edit, "file:///ws/main.uvl");
+        assert!(apply(&root, "file:///ws/main.uvl", &main)
+            .starts_with("imports\n    parts.sub.Engine\n"));
+        //files moving together keep their imports
+        assert!(changes(&edit, "file:///ws/sub/Engine.uvl").is_empty());
+        let config = changes(&edit, "file:///ws/main.json");
+        assert_eq!(
+            apply(&root, "file:///ws/main.json", &config),
+            r#"{"file":"parts/sub/Engine.uvl","config":{}}"#
+        );
+    }
+    #[test]
+    fn test_rename_with_import_roots() {
+        let sources = [
+            (
+                "file:///ws/a/main.uvl",
+                "imports\n    b.Engine\n    Hardware\nfeatures\n    Main\n",
+            ),
+            ("file:///ws/b/Engine.uvl", "features\n    Engine\n"),
+            (
+                "file:///ws/c/other.uvl",
+                "imports\n    b.Engine\nfeatures\n    Other\n",
+            ),
+            (
+                "file:///lib/Hardware.uvl",
+                "imports\n    Bolt\nfeatures\n    Hardware\n",
+            ),
+            ("file:///lib/Bolt.uvl", "features\n    Bolt\n"),
+        ];
+        let root = root_graph(
+            &sources,
+            &ImportRoots {
+                folders: vec!["/ws".into()],
+                libraries: vec!["/lib".into()],
+            },
+        );
+        //imports through the workspace folder and the libraries still resolve
+        let (edit, unresolved) = rename(&root, "file:///ws/a/main.uvl", "file:///ws/x/main.uvl");
+        assert!(unresolved.is_empty(), "{unresolved:?}");
+        assert!(edit.changes.is_none());
+        let (edit, unresolved) = rename(&root, "file:///ws/b", "file:///ws/d");
+        assert!(unresolved.is_empty(), "{unresolved:?}");
+        for uri in ["file:///ws/a/main.uvl", "file:///ws/c/other.uvl"] {
+            assert!(apply(&root, uri, &changes(&edit, uri)).starts_with("imports\n    d.Engine\n"));
+        }
+        //library files are read only
+        let (edit, unresolved) = rename(&root, "file:///lib/Bolt.uvl", "file:///lib/Screw.uvl");
+        assert!(edit.changes.is_none());
+        assert_eq!(unresolved.len(), 1);
+    }
+    #[test]
+    fn test_rename_namespace() {
+        let root = root_graph(
+            &[
+                (
+                    "file:///ws/main.uvl",
+                    "imports\n    sub.Engine\nfeatures\n    Main\n        optional\n            Engine.Engine\n",
+                ),
+                (
+                    "file:///ws/sub/Engine.uvl",
+                    "namespace sub.Engine\nfeatures\n    Engine\n",
+                ),
+            ],
+            &ImportRoots::default(),
+        );
+        let (edit, _) = rename(
+            &root,
+            "file:///ws/sub/Engine.uvl",
+            "file:///ws/sub/Motor.uvl",
+        );
+        let engine = changes(&edit, "file:///ws/sub/Engine.uvl");
+        assert_eq!(
+            apply(&root, "file:///ws/sub/Engine.uvl", &engine),
+            "namespace sub.Motor\nfeatures\n    Engine\n"
+        );
+        //the alias keeps references through the import valid
+        let main = changes(&edit, "file:///ws/main.uvl");
+        assert!(apply(&root, "file:///ws/main.uvl", &main)
+            .starts_with("imports\n    sub.Motor as Engine\n"));
+    }
+    #[test]
+    fn test_extract_subtree() {
+        let uri = "file:///ws/main.uvl";
+        let root = root_graph(
+            &[(
+                uri,
+                "features\n    Main\n        optional\n            Engine\n                mandatory\n                    Piston\n            Wheel\nconstraints\n    Piston => Engine\n    Wheel => Main\n",
+            )],
+            &ImportRoots::default(),
+        );
+        let (id, offset) = offset(&root, uri, "Engine");
+        let action = extract_subtree(&root, id, offset).unwrap();
+        assert_eq!(
+            apply(&root, uri, &action_edits(&action, uri)),
+            "imports\n    Engine\n\nfeatures\n    Main\n        optional\n            Engine.Engine\n            Wheel\nconstraints\n    Wheel => Main\n"
+        );
+        let new = action_edits(&action, "file:///ws/Engine.uvl");
+        assert_eq!(
+            new[0].new_text,
+            "namespace Engine\n\nfeatures\n    Engine\n        mandatory\n            Piston\n\nconstraints\n    Piston => Engine\n"
+        );
+    }
+    #[test]
+    fn test_inline_aliased_import() {
+        let uri = "file:///ws/main.uvl";
+        let root = root_graph(
+            &[
+                (
+                    uri,
+                    "imports\n    sub.Engine as E\nfeatures\n    Main\n        optional\n            E.Engine\nconstraints\n    E.Piston => Main\n",
+                ),
+                (
+                    "file:///ws/sub/Engine.uvl",
+                    "features\n\tEngine\n\t\tmandatory\n\t\t\tPiston\nconstraints\n\tPiston => Engine\n",
+                ),
+            ],
+            &ImportRoots::default(),
+        );
+        let (id, offset) = offset(&root, uri, "sub.Engine");
+        let action = inline_import(&root, id, offset).unwrap();
+        assert_eq!(
+            apply(&root, uri, &action_edits(&action, uri)),
+            "features\n    Main\n        optional\n            Engine\n                mandatory\n                    Piston\nconstraints\n    Piston => Main\n    Piston => Engine\n"
+        );
+    }
+    #[test]
+    fn test_inline_nested_import() {
+        let uri = "file:///ws/main.uvl";
+        let root = root_graph(
+            &[
+                (
+                    uri,
+                    "imports\n    Engine\nfeatures\n    Main\n        optional\n            Engine.Engine\n",
+                ),
+                (
+                    "file:///ws/Engine.uvl",
+                    "imports\n    Piston\nfeatures\n    Engine\n        mandatory\n            Piston.Piston\n",
+                ),
+                ("file:///ws/Piston.uvl", "features\n    Piston\n"),
+            ],
+            &ImportRoots::default(),
+        );
+        let (id, offset) = offset(&root, uri, "Engine\nfeatures");
+        let action = inline_import(&root, id, offset).unwrap();
+        assert_eq!(action.title, "Inline Engine");
+        assert_eq!(
+            action.disabled.map(|d| d.reason),
+            Some("Engine has imports of its own".to_string())
+        );
+        assert!(action.edit.is_none());
+    }
+    #[test]
+    fn test_change_group_mode() {
+        let uri = "file:///ws/main.uvl";
+        let root = root_graph(
+            &[(
+                uri,
+                "features\n    Main\n        or\n            A\n            B\n",
+            )],
+            &ImportRoots::default(),
+        );
+        let (id, offset) = offset(&root, uri, "or");
+        let actions = change_group_mode(&root, id, offset);
+        let titles: Vec<_> = actions.iter().map(|a| a.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "Change group to mandatory",
+                "Change group to optional",
+                "Change group to alternative",
+                "Change group to [1..2]"
+            ]
+        );
+        assert_eq!(
+            group_change_uri(&actions[0]),
+            Some(Url::parse(uri).unwrap())
+        );
+    }
+    #[test]
+    fn test_change_group_cardinality() {
+        let uri = "file:///ws/main.uvl";
+        let root = root_graph(
+            &[(
+                uri,
+                "features\n    Main\n        [1..*]\n            A\n            B\n",
+            )],
+            &ImportRoots::default(),
+        );
+        let (id, offset) = offset(&root, uri, "[1..*]");
+        let actions = change_group_mode(&root, id, offset);
+        let modes: Vec<_> = actions
+            .iter()
+            .map(|a| {
+                a.data.as_ref().unwrap()["groupChange"]["mode"]
+                    .as_str()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(modes, ["mandatory", "or", "optional", "alternative"]);
+        assert_eq!(
+            apply(&root, uri, &action_edits(&actions[1], uri)),
+            "features\n    Main\n        or\n            A\n            B\n"
+        );
+        for text in ["or", "[0..3]", "[2..*]"] {
+            assert_eq!(
+                parse_group_mode(text)
+                    .and_then(|m| group_text(&m))
+                    .as_deref(),
+                Some(text)
+            );
+        }
+        assert!(parse_group_mode("[3..1]").is_none());
+        assert!(parse_group_mode("[a..1]").is_none());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::core::*;
+use ropey::Rope;
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+/// Byte range of node without trailing whitespace, blocks include the newlines and
+/// indentation tokens following them
+fn trimmed_range(node: Node, source: &Rope) -> std::ops::Range<usize> {
+    let text = source.byte_slice(node.byte_range()).to_string();
+    node.start_byte()..node.start_byte() + text.trim_end().len()
+}
+/// Nested selection ranges from the innermost named node at pos up to the whole document
+fn selection_range(tree: &tree_sitter::Tree, source: &Rope, pos: &Position) -> SelectionRange {
+    let offset = byte_offset(pos, source);
+    let mut ranges: Vec<Range> = Vec::new();
+    let mut node = tree
+        .root_node()
+        .named_descendant_for_byte_range(offset, offset);
+    while let Some(n) = node {
+        if let Some(range) = lsp_range(trimmed_range(n, source), source) {
+            //names and paths as well as blocks and their header often share ranges
+            if ranges.last() != Some(&range) && range.start != range.end {
+                ranges.push(range);
+            }
+        }
+        node = n.parent();
+    }
+    ranges
+        .into_iter()
+        .rev()
+        .fold(None, |parent, range| {
+            Some(SelectionRange {
+                range,
+                parent: parent.map(Box::new),
+            })
+        })
+        .unwrap_or(SelectionRange {
+            range: Range {
+                start: *pos,
+                end: *pos,
+            },
+            parent: None,
+        })
+}
+pub fn selection_ranges(draft: &Draft, positions: &[Position]) -> Vec<SelectionRange> {
+    let (Draft::UVL { source, tree, .. } | Draft::JSON { source, tree, .. }) = draft;
+    positions
+        .iter()
+        .map(|pos| selection_range(tree, source, pos))
+        .collect()
+}
@@ -0,0 +1,161 @@
+use crate::core::*;
+use tower_lsp::lsp_types::*;
+
+/// A built-in function with its overloads as (label, parameters)
+struct Builtin {
+    name: &'static str,
+    overloads: &'static [(&'static str, &'static [&'static str])],
+    doc: &'static str,
+    lang_lvl: &'static str,
+}
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sum",
+        overloads: &[
+            ("sum(attribute)", &["attribute"]),
+            ("sum(feature, attribute)", &["feature", "attribute"]),
+        ],
+        doc: "Sum of a numeric attribute over all selected features, optionally only below feature",
+        lang_lvl: "Arithmetic.aggregate-function",
+    },
+    Builtin {
+        name: "avg",
+        overloads: &[
+            ("avg(attribute)", &["attribute"]),
+            ("avg(feature, attribute)", &["feature", "attribute"]),
+        ],
+        doc: "Average of a numeric attribute over all selected features, optionally only below feature",
+        lang_lvl: "Arithmetic.aggregate-function",
+    },
+    Builtin {
+        name: "len",
+        overloads: &[("len(string)", &["string"])],
+        doc: "Number of characters in a string expression",
+        lang_lvl: "Type.string-constraints",
+    },
+    Builtin {
+        name: "floor",
+        overloads: &[("floor(number)", &["number"])],
+        doc: "Round a numeric expression down to the next integer",
+        lang_lvl: "Type.numeric-constraints",
+    },
+    Builtin {
+        name: "ceil",
+        overloads: &[("ceil(number)", &["number"])],
+        doc: "Round a numeric expression up to the next integer",
+        lang_lvl: "Type.numeric-constraints",
+    },
+];
+
+/// Innermost unclosed call before the cursor and the number of arguments already passed.
+/// The line is scanned as text because the tree of incomplete calls contains errors
+fn open_call(line: &str) -> Option<(&str, usize)> {
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    let mut quoted = None;
+    for (i, c) in line.char_indices() {
+        match (quoted, c) {
+            (Some(q), c) if q == c => quoted = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quoted = Some(c),
+            (None, '(') => stack.push((i, 0)),
+            (None, ')') => {
+                stack.pop();
+            }
+            (None, ',') => {
+                if let Some((_, args)) = stack.last_mut() {
+                    *args += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    let (open, args) = stack.pop()?;
+    let head = line[..open].trim_end();
+    let start = head
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map(|i| i + head[i..].chars().next().map(char::len_utf8).unwrap_or(1))
+        .unwrap_or(0);
+    Some((&head[start..], args))
+}
+
+pub fn signature_help(draft: &Draft, pos: &Position) -> Option<SignatureHelp> {
+    let Draft::UVL { source, .. } = draft else {
+        return None;
+    };
+    let line_start = source.line_to_char(pos.line as usize);
+    let line = source
+        .slice(line_start..char_offset(pos, source))
+        .to_string();
+    let (name, args) = open_call(&line)?;
+    let builtin = BUILTINS.iter().find(|b| b.name == name)?;
+    let active = builtin
+        .overloads
+        .iter()
+        .position(|(_, params)| params.len() > args)
+        .unwrap_or(builtin.overloads.len() - 1);
+    Some(SignatureHelp {
+        signatures: builtin
+            .overloads
+            .iter()
+            .map(|(label, params)| SignatureInformation {
+                label: label.to_string(),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format!("{}\n\nrequires `include {}`", builtin.doc, builtin.lang_lvl),
+                })),
+                parameters: Some(
+                    params
+                        .iter()
+                        .map(|p| ParameterInformation {
+                            label: ParameterLabel::Simple(p.to_string()),
+                            documentation: None,
+                        })
+                        .collect(),
+                ),
+                active_parameter: None,
+            })
+            .collect(),
+        active_signature: Some(active as u32),
+        active_parameter: Some(args as u32),
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+    use tokio::time::Instant;
+    #[test]
+    fn test_open_call() {
+        assert_eq!(open_call("    sum("), Some(("sum", 0)));
+        assert_eq!(open_call("    x > 2 * floor(a.b"), Some(("floor", 0)));
+        //nested calls, closed ones do not count
+        assert_eq!(open_call("sum(a, avg(b"), Some(("avg", 0)));
+        assert_eq!(open_call("sum(a, avg(b, c), "), Some(("sum", 2)));
+        assert_eq!(open_call("sum(a) > avg(b)"), None);
+        //commas and parenthesis inside strings are not syntax
+        assert_eq!(open_call("len('a,(b', "), Some(("len", 1)));
+        assert_eq!(open_call("len(\"a,)b\""), Some(("len", 0)));
+        assert_eq!(open_call("len('it''s, ok'"), Some(("len", 0)));
+    }
+    #[test]
+    fn test_active_argument() {
+        let help = |line: &str| {
+            let source = Rope::from_str(&format!("constraints\n{line}"));
+            let timestamp = Instant::now();
+            let draft = Draft::UVL {
+                tree: parse::parse(&source, None),
+                source,
+                timestamp,
+                edits: EditLog::new(timestamp),
+            };
+            let pos = Position::new(1, line.len() as u32);
+            signature_help(&draft, &pos).map(|h| (h.active_signature, h.active_parameter))
+        };
+        assert_eq!(help("    sum("), Some((Some(0), Some(0))));
+        //the second argument selects the overload with a feature
+        assert_eq!(help("    sum(Root, "), Some((Some(1), Some(1))));
+        assert_eq!(help("    sum(Root, avg("), Some((Some(0), Some(0))));
+        assert_eq!(help("    len('a, b', "), Some((Some(0), Some(1))));
+        assert_eq!(help("    unknown("), None);
+    }
+}
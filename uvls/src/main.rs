@@ -21,7 +21,7 @@
 //!         - core/resolve.rs - resolve_file: Type resolving for an AstDocument, resolve_*: nameresultion under various conditions.
 //! - SMT
 //!     - core/module.rs: Module is a uvl instance containing all sub instances of an arbitrary root file.
-//!     - smt/parse.rs: Turn smt-lib strings to rust. (currently incomplete)
+//!     - smt/parse.rs: Turn smt-lib strings to rust.
 //!     - smt/smt_lib.rs: SMTModule: A smt-lib module equivalent to some UVL source module. uvl2smt: Turn some uvl module into a SMTModule.
 //!     - smt/smt.rs: SmtSolver: Z3 process interface(over stdio). check_handler: Runs smt-analysis on new files when the Rootgraph changes. web_view_handler runs smt-analysis on configurations.
 //! - Configuration:
@@ -62,10 +62,40 @@ use crate::smt::{smt_lib::Expr, uvl2smt, SmtSolver};
 /// ie. client==vscode
 struct Settings {
     has_webview: bool,
+    //diagnostic providers are registered separately by the client
+    dynamic_diagnostics: bool,
+    //the client answers workspace/configuration requests
+    configuration: bool,
+    //the client supports work done progress
+    progress: bool,
+    //the client accepts watchers relative to a folder
+    relative_watchers: bool,
+    workspace_folders: Vec<PathBuf>,
+    //loaded library directories
+    libraries: Vec<PathBuf>,
+    //file watchers are registered
+    watching: bool,
+    //raw settings of each source and the resolved result
+    layers: SettingsLayers,
+    server: ServerSettings,
+    //resolved settings of each workspace folder
+    folders: HashMap<PathBuf, ServerSettings>,
 }
 impl Default for Settings {
     fn default() -> Self {
-        Settings { has_webview: false }
+        Settings {
+            has_webview: false,
+            dynamic_diagnostics: false,
+            configuration: false,
+            progress: false,
+            relative_watchers: false,
+            workspace_folders: Vec::new(),
+            libraries: Vec::new(),
+            watching: false,
+            layers: SettingsLayers::default(),
+            server: ServerSettings::default(),
+            folders: HashMap::new(),
+        }
     }
 }
 /// The LSP
@@ -73,8 +103,11 @@ struct Backend {
     client: Client,
     coloring: Arc<ide::color::State>,
     pipeline: AsyncPipeline,
-    web_handler_uri: String,
+    web_handler_uri: parking_lot::Mutex<String>,
     settings: parking_lot::Mutex<Settings>,
+    logger: parking_lot::Mutex<flexi_logger::LoggerHandle>,
+    //semantics of group changes offered as code actions
+    group_changes: ide::refactor::GroupChangeCache,
 }
 impl Backend {
     fn load(&self, uri: Url) {
@@ -89,6 +122,264 @@ impl Backend {
             .await
             .map_err(|_| shutdown_error())
     }
+    /// Resolve the settings of all sources and hand them to the subsystems,
+    /// settings which are not per folder are taken from the first folder
+    async fn apply_settings(&self) {
+        let (layers, progress, workspace_folders) = {
+            let settings = self.settings.lock();
+            (
+                settings.layers.clone(),
+                settings.progress,
+                settings.workspace_folders.clone(),
+            )
+        };
+        let resolved = layers
+            .resolve(workspace_folders.first().map(|f| f.as_path()))
+            .and_then(|server| {
+                let folders = workspace_folders
+                    .iter()
+                    .map(|f| Ok((f.clone(), layers.resolve(Some(f))?)))
+                    .collect::<std::result::Result<HashMap<_, _>, String>>()?;
+                Ok((server, folders))
+            });
+        match resolved {
+            Ok((mut server, folders)) => {
+                server.smt.progress = progress;
+                if let Some(level) = server.log_level.as_ref() {
+                    if let Err(e) = self.logger.lock().parse_new_spec(level) {
+                        info!("invalid log level {level}: {e}");
+                    }
+                }
+                self.pipeline.set_smt_options(server.smt.clone());
+                self.pipeline.set_lint_options(WorkspaceLints {
+                    default: server.lints.clone(),
+                    folders: folders
+                        .iter()
+                        .map(|(f, s)| (f.clone(), s.lints.clone()))
+                        .collect(),
+                });
+                {
+                    let mut settings = self.settings.lock();
+                    settings.server = server;
+                    settings.folders = folders;
+                }
+                self.update_libraries().await;
+            }
+            Err(e) => {
+                self.client
+                    .show_message(MessageType::WARNING, format!("UVLS: invalid settings: {e}"))
+                    .await
+            }
+        }
+    }
+    /// Reload the project files of all workspace folders, invalid files keep their old settings
+    async fn load_project_settings(&self) {
+        let (folders, mut old) = {
+            let settings = self.settings.lock();
+            (
+                settings.workspace_folders.clone(),
+                settings.layers.projects.clone(),
+            )
+        };
+        let mut projects = HashMap::new();
+        for folder in folders {
+            let project = match read_project_settings(&folder) {
+                Ok(project) => project,
+                Err(e) => {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("UVLS: {}: {e}", folder.display()),
+                        )
+                        .await;
+                    old.remove(&folder).unwrap_or_default()
+                }
+            };
+            projects.insert(folder, project);
+        }
+        self.settings.lock().layers.projects = projects;
+    }
+    /// Load new library directories and drop the removed ones
+    async fn update_libraries(&self) {
+        let (added, removed, watching) = {
+            let mut settings = self.settings.lock();
+            //relative library paths start at the folder of their settings
+            let mut libraries = if settings.workspace_folders.is_empty() {
+                settings.server.libraries(None)
+            } else {
+                Vec::new()
+            };
+            for folder in settings.workspace_folders.iter() {
+                let Some(folder_settings) = settings.folders.get(folder) else {
+                    continue;
+                };
+                for library in folder_settings.libraries(Some(folder)) {
+                    if !libraries.contains(&library) {
+                        libraries.push(library);
+                    }
+                }
+            }
+            if libraries == settings.libraries {
+                return;
+            }
+            let added: Vec<_> = libraries
+                .iter()
+                .filter(|l| !settings.libraries.contains(l))
+                .cloned()
+                .collect();
+            let removed: Vec<_> = settings
+                .libraries
+                .iter()
+                .filter(|l| !libraries.contains(l))
+                .cloned()
+                .collect();
+            settings.libraries = libraries.clone();
+            self.pipeline.set_library_paths(libraries);
+            (added, removed, settings.watching)
+        };
+        self.unload(&removed).await;
+        for library in added {
+            self.load_folder(library);
+        }
+        if watching {
+            self.register_watchers().await;
+        }
+    }
+    /// Remove files under dirs which are not part of the workspace anymore,
+    /// files still open in the editor stay loaded
+    async fn unload(&self, dirs: &[PathBuf]) {
+        if dirs.is_empty() {
+            return;
+        }
+        let remaining: Vec<PathBuf> = {
+            let settings = self.settings.lock();
+            settings
+                .workspace_folders
+                .iter()
+                .chain(settings.libraries.iter())
+                .cloned()
+                .collect()
+        };
+        for uri in self.pipeline.documents() {
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            if dirs.iter().any(|f| path.starts_with(f))
+                && !remaining.iter().any(|f| path.starts_with(f))
+            {
+                self.pipeline
+                    .delete(&uri, DocumentState::OwnedByOs(SystemTime::now()))
+                    .await;
+            }
+        }
+    }
+    /// Load all files of a workspace folder in the background
+    fn load_folder(&self, folder: PathBuf) {
+        let semantic = self.pipeline.clone();
+        let settings = {
+            let settings = self.settings.lock();
+            settings
+                .folders
+                .get(&folder)
+                .unwrap_or(&settings.server)
+                .clone()
+        };
+        //cheap fix for better intial load, we should really use priority model to prefer
+        //editor owned files
+        spawn(async move {
+            tokio::task::spawn_blocking(move || {
+                load_all_blocking(&folder, semantic, &settings);
+            })
+            .await
+        });
+    }
+    /// Is uri part of any workspace folder or library, without folders every file is
+    fn in_workspace(&self, uri: &Url) -> bool {
+        let settings = self.settings.lock();
+        settings.workspace_folders.is_empty()
+            || uri
+                .to_file_path()
+                .map(|path| {
+                    settings
+                        .workspace_folders
+                        .iter()
+                        .chain(settings.libraries.iter())
+                        .any(|folder| path.starts_with(folder))
+                })
+                .unwrap_or(false)
+    }
+    /// (Re)register file watchers, uvl files are only watched inside the workspace folders
+    /// and libraries
+    async fn register_watchers(&self) {
+        let (folders, relative) = {
+            let mut settings = self.settings.lock();
+            settings.watching = true;
+            let mut folders = settings.workspace_folders.clone();
+            //only an empty workspace watches everything
+            if !folders.is_empty() {
+                folders.extend(settings.libraries.iter().cloned());
+            }
+            (folders, settings.relative_watchers)
+        };
+        let mut watchers = vec![FileSystemWatcher {
+            glob_pattern: GlobPattern::String(format!("**/{PROJECT_FILE}")),
+            kind: None,
+        }];
+        for pattern in ["**/*.uvl", "**/*.uvl.json"] {
+            let folders: Vec<_> = folders
+                .iter()
+                .filter_map(|folder| Url::from_directory_path(folder).ok())
+                .collect();
+            if relative && !folders.is_empty() {
+                watchers.extend(folders.into_iter().map(|base_uri| FileSystemWatcher {
+                    glob_pattern: GlobPattern::Relative(RelativePattern {
+                        base_uri: OneOf::Right(base_uri),
+                        pattern: pattern.into(),
+                    }),
+                    kind: None,
+                }));
+            } else {
+                watchers.push(FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(pattern.into()),
+                    kind: None,
+                });
+            }
+        }
+        let _ = self
+            .client
+            .unregister_capability(vec![Unregistration {
+                id: "watcher".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+            }])
+            .await;
+        let reg = Registration {
+            id: "watcher".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers,
+            })
+            .ok(),
+        };
+        if self.client.register_capability(vec![reg]).await.is_err() {
+            info!("failed to initialize file watchers");
+        }
+    }
+    /// Request the section uvls of the client configuration
+    async fn load_client_settings(&self) {
+        if !self.settings.lock().configuration {
+            return;
+        }
+        if let Ok(mut config) = self
+            .client
+            .configuration(vec![ConfigurationItem {
+                scope_uri: None,
+                section: Some("uvls".into()),
+            }])
+            .await
+        {
+            self.settings.lock().layers.client = config.pop().unwrap_or_default();
+        }
+    }
 }
 /// load a file, this is tricky because the editor can also load it at the same time
 fn load_blocking(uri: Url, pipeline: &AsyncPipeline) {
@@ -108,9 +399,10 @@ fn load_blocking(uri: Url, pipeline: &AsyncPipeline) {
     }
 }
 /// load all files under given a path
-fn load_all_blocking(path: &Path, pipeline: AsyncPipeline) {
+fn load_all_blocking(path: &Path, pipeline: AsyncPipeline, settings: &ServerSettings) {
     for e in walkdir::WalkDir::new(path)
         .into_iter()
+        .filter_entry(|e| !settings.is_excluded(path, e.path()))
         .filter_map(|e| e.ok())
         .filter(|e| e.path().is_file())
         .filter(|e| {
@@ -134,23 +426,87 @@ fn shutdown_error() -> tower_lsp::jsonrpc::Error {
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, init_params: InitializeParams) -> Result<InitializeResult> {
+        //older clients only send a single root
         #[allow(deprecated)]
-        let root_folder = init_params
-            .root_path
-            .as_deref()
-            .or_else(|| init_params.root_uri.as_ref().map(|p| p.path()))
-            .map(PathBuf::from);
-        if let Some(root_folder) = root_folder {
-            let semantic = self.pipeline.clone();
-            //cheap fix for better intial load, we should really use priority model to prefer
-            //editor owned files
-            spawn(async move {
-                tokio::task::spawn_blocking(move || {
-                    load_all_blocking(&root_folder, semantic);
-                })
-                .await
-            });
+        let workspace_folders: Vec<PathBuf> = match init_params.workspace_folders.as_ref() {
+            Some(folders) => folders
+                .iter()
+                .filter_map(|f| f.uri.to_file_path().ok())
+                .collect(),
+            None => init_params
+                .root_path
+                .as_deref()
+                .or_else(|| init_params.root_uri.as_ref().map(|p| p.path()))
+                .map(PathBuf::from)
+                .into_iter()
+                .collect(),
+        };
+        self.pipeline
+            .set_workspace_folders(workspace_folders.clone());
+        {
+            let mut settings = self.settings.lock();
+            settings.workspace_folders = workspace_folders.clone();
+            settings.relative_watchers = init_params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.did_change_watched_files.as_ref())
+                .and_then(|w| w.relative_pattern_support)
+                .unwrap_or(false);
+            settings.progress = init_params
+                .capabilities
+                .window
+                .as_ref()
+                .and_then(|w| w.work_done_progress)
+                .unwrap_or(false);
+            settings.configuration = init_params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.configuration)
+                .unwrap_or(false);
+            //settings are passed like in uvls.toml, eg. {"smt":{"queryTimeout":ms},"lints":{..}}
+            settings.layers.init = init_params
+                .initialization_options
+                .clone()
+                .unwrap_or_default();
+        }
+        self.load_project_settings().await;
+        self.apply_settings().await;
+        let server = self.settings.lock().server.clone();
+        for folder in workspace_folders {
+            self.load_folder(folder);
+        }
+        let (min, max) = server.webview.ports;
+        if let Some(port) =
+            get_port::tcp::TcpPort::in_range("127.0.0.1", get_port::Range { min, max })
+        {
+            spawn(webview::web_handler(self.pipeline.clone(), port));
+            *self.web_handler_uri.lock() = format!("http://localhost:{port}");
+        } else {
+            info!("no free port for the configuration editor in {min}..{max}");
         }
+        //clients supporting pull diagnostics request them, everyone else gets them pushed
+        let pull_diagnostics = init_params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.diagnostic.as_ref());
+        let dynamic_diagnostics = pull_diagnostics
+            .and_then(|d| d.dynamic_registration)
+            .unwrap_or(false);
+        let refresh_diagnostics = init_params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|w| w.diagnostic.as_ref())
+            .and_then(|d| d.refresh_support)
+            .unwrap_or(false);
+        self.pipeline
+            .diagnostics()
+            .set_pull(pull_diagnostics.is_some(), refresh_diagnostics);
+        self.settings.lock().dynamic_diagnostics = dynamic_diagnostics;
+        let pull_diagnostics = pull_diagnostics.is_some();
         if init_params
             .client_info
             .map(|info| matches!(info.name.as_str(), "Visual Studio Code"))
@@ -185,19 +541,77 @@ impl LanguageServer for Backend {
                                 token_types: ide::color::token_types(),
                                 token_modifiers: ide::color::modifiers(),
                             },
-                            range: None,
+                            range: Some(true),
                             full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                         },
                     ),
                 ),
                 folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
                 references_provider: Some(OneOf::Left(true)),
-                rename_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".into(), ",".into()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: Default::default(),
+                }),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
                 code_lens_provider: Some(CodeLensOptions {
                     resolve_provider: Some(true),
                 }),
                 inlay_hint_provider: Some(OneOf::Left(true)),
-                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        resolve_provider: Some(true),
+                        ..Default::default()
+                    },
+                )),
+                //with dynamic registration each provider is registered on its own
+                diagnostic_provider: (pull_diagnostics && !dynamic_diagnostics).then(|| {
+                    DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                        identifier: None,
+                        inter_file_dependencies: true,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: Default::default(),
+                    })
+                }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                        will_rename: Some(FileOperationRegistrationOptions {
+                            filters: vec![
+                                FileOperationFilter {
+                                    scheme: Some("file".into()),
+                                    pattern: FileOperationPattern {
+                                        glob: "**/*.{uvl,json}".into(),
+                                        matches: Some(FileOperationPatternKind::File),
+                                        options: None,
+                                    },
+                                },
+                                FileOperationFilter {
+                                    scheme: Some("file".into()),
+                                    pattern: FileOperationPattern {
+                                        glob: "**".into(),
+                                        matches: Some(FileOperationPatternKind::Folder),
+                                        options: None,
+                                    },
+                                },
+                            ],
+                        }),
+                        ..Default::default()
+                    }),
+                }),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: vec![
                         "uvls/show_config".into(),
@@ -221,24 +635,39 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, "server initialized!")
             .await;
 
-        let watcher_uvl = FileSystemWatcher {
-            glob_pattern: GlobPattern::String("**/*.uvl".to_string()),
-            kind: None,
-        };
-        let watcher_config = FileSystemWatcher {
-            glob_pattern: GlobPattern::String("**/*.uvl.json".to_string()),
-            kind: None,
-        };
-        let reg = Registration {
-            id: "watcher".to_string(),
-            method: "workspace/didChangeWatchedFiles".to_string(),
-            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
-                watchers: vec![watcher_config, watcher_uvl],
-            })
-            .ok(),
-        };
-        if self.client.register_capability(vec![reg]).await.is_err() {
-            info!("failed to initialize file watchers");
+        self.register_watchers().await;
+        if self.settings.lock().configuration {
+            self.load_client_settings().await;
+            self.apply_settings().await;
+        }
+        if self.settings.lock().dynamic_diagnostics {
+            let regs = DiagnosticProvider::ALL
+                .iter()
+                .map(|p| Registration {
+                    id: p.identifier().to_string(),
+                    method: "textDocument/diagnostic".to_string(),
+                    register_options: serde_json::to_value(DiagnosticRegistrationOptions {
+                        text_document_registration_options: TextDocumentRegistrationOptions {
+                            document_selector: Some(vec![DocumentFilter {
+                                language: None,
+                                scheme: Some("file".into()),
+                                pattern: Some("**/*.{uvl,json}".into()),
+                            }]),
+                        },
+                        diagnostic_options: DiagnosticOptions {
+                            identifier: Some(p.identifier().to_string()),
+                            inter_file_dependencies: true,
+                            workspace_diagnostics: true,
+                            work_done_progress_options: Default::default(),
+                        },
+                        static_registration_options: Default::default(),
+                    })
+                    .ok(),
+                })
+                .collect();
+            if self.client.register_capability(regs).await.is_err() {
+                info!("failed to register diagnostic providers");
+            }
         }
     }
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
@@ -253,7 +682,9 @@ impl LanguageServer for Backend {
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri.clone();
         self.pipeline.update(params);
-        self.client.publish_diagnostics(uri, vec![], None).await;
+        if !self.pipeline.diagnostics().is_pull() {
+            self.client.publish_diagnostics(uri, vec![], None).await;
+        }
         info!("done did_change");
     }
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
@@ -315,16 +746,116 @@ impl LanguageServer for Backend {
             return Ok(None);
         }
     }
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        if let Some((draft, root)) = self.snapshot(uri, true).await? {
+            Ok(ide::location::document_highlight(
+                &root,
+                &draft,
+                &params.text_document_position_params.position,
+                uri,
+            ))
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        if let Some((draft, _)) = self.snapshot(&params.text_document.uri, false).await? {
+            Ok(Some(ide::selection::selection_ranges(
+                &draft,
+                &params.positions,
+            )))
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = &params.text_document.uri;
+        if let Some((_, root)) = self.snapshot(uri, true).await? {
+            Ok(ide::location::document_links(&root, uri))
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        if let Some((_, root)) = self.snapshot(uri, true).await? {
+            Ok(ide::hierarchy::prepare(
+                &root,
+                uri,
+                &params.text_document_position_params.position,
+            ))
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        if let Some((_, root)) = self.snapshot(&params.item.uri, true).await? {
+            Ok(ide::hierarchy::incoming(&root, &params.item))
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        if let Some((_, root)) = self.snapshot(&params.item.uri, true).await? {
+            Ok(ide::hierarchy::outgoing(&root, &params.item))
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        if let Some((draft, _)) = self.snapshot(uri, false).await? {
+            Ok(ide::signature::signature_help(
+                &draft,
+                &params.text_document_position_params.position,
+            ))
+        } else {
+            return Ok(None);
+        }
+    }
     async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
         info!("[RENAME] params: {:?}", params);
         let uri = &params.text_document_position.text_document.uri;
         if let Some((draft, root)) = self.snapshot(uri, true).await? {
-            Ok(ide::location::rename(
+            ide::location::rename(
                 &root,
                 &draft,
                 uri,
                 &params.text_document_position.position,
                 params.new_name,
+            )
+            .map_err(tower_lsp::jsonrpc::Error::invalid_params)
+        } else {
+            return Ok(None);
+        }
+    }
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let uri = &params.text_document.uri;
+        if let Some((draft, root)) = self.snapshot(uri, true).await? {
+            Ok(ide::location::prepare_rename(
+                &root,
+                &draft,
+                uri,
+                &params.position,
             ))
         } else {
             return Ok(None);
@@ -338,8 +869,8 @@ impl LanguageServer for Backend {
         if let Some((draft, root)) = self.snapshot(&uri, false).await? {
             let color = self.coloring.clone();
             match draft {
-                Draft::UVL { source, tree, .. } => Ok(Some(SemanticTokensResult::Tokens(
-                    color.get(root, uri, tree, source),
+                Draft::UVL { .. } => Ok(Some(SemanticTokensResult::Tokens(
+                    color.get(root, uri, draft),
                 ))),
                 Draft::JSON { .. } => Ok(None),
             }
@@ -355,7 +886,29 @@ impl LanguageServer for Backend {
         if let Some((draft, root)) = self.snapshot(&uri, false).await? {
             let color = self.coloring.clone();
             match draft {
-                Draft::UVL { source, tree, .. } => Ok(Some(color.delta(root, uri, tree, source))),
+                Draft::UVL { .. } => Ok(Some(color.delta(
+                    root,
+                    uri,
+                    draft,
+                    &params.previous_result_id,
+                ))),
+                Draft::JSON { .. } => Ok(None),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let uri = params.text_document.uri;
+        if let Some((draft, root)) = self.snapshot(&uri, false).await? {
+            let color = self.coloring.clone();
+            match draft {
+                Draft::UVL { source, tree, .. } => Ok(Some(SemanticTokensRangeResult::Tokens(
+                    color.range(root, uri, tree, source, params.range),
+                ))),
                 Draft::JSON { .. } => Ok(None),
             }
         } else {
@@ -378,9 +931,76 @@ impl LanguageServer for Backend {
             .await;
         self.load(params.text_document.uri);
     }
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        info!("configuration change {:?}", params);
+        //pull based clients only notify, others send the new settings
+        if self.settings.lock().configuration {
+            self.load_client_settings().await;
+        } else {
+            let settings = match params.settings {
+                serde_json::Value::Object(mut o) if o.contains_key("uvls") => {
+                    o.remove("uvls").unwrap()
+                }
+                settings => settings,
+            };
+            self.settings.lock().layers.client = settings;
+        }
+        self.apply_settings().await;
+    }
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        info!("workspace folders change {:?}", params);
+        let added: Vec<PathBuf> = params
+            .event
+            .added
+            .iter()
+            .filter_map(|f| f.uri.to_file_path().ok())
+            .collect();
+        let removed: Vec<PathBuf> = params
+            .event
+            .removed
+            .iter()
+            .filter_map(|f| f.uri.to_file_path().ok())
+            .collect();
+        let folders = {
+            let mut settings = self.settings.lock();
+            settings.workspace_folders.retain(|f| !removed.contains(f));
+            for folder in added.iter() {
+                if !settings.workspace_folders.contains(folder) {
+                    settings.workspace_folders.push(folder.clone());
+                }
+            }
+            settings.workspace_folders.clone()
+        };
+        self.pipeline.set_workspace_folders(folders);
+        self.unload(&removed).await;
+        self.load_project_settings().await;
+        self.apply_settings().await;
+        for folder in added {
+            self.load_folder(folder);
+        }
+        self.register_watchers().await;
+    }
     async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
         info!("file change {:?}", params);
-        for i in params.changes {
+        let project_files: Vec<PathBuf> = self
+            .settings
+            .lock()
+            .workspace_folders
+            .iter()
+            .map(|f| f.join(PROJECT_FILE))
+            .collect();
+        let is_project_file = |uri: &Url| {
+            uri.to_file_path()
+                .map(|path| project_files.contains(&path))
+                .unwrap_or(false)
+        };
+        if params.changes.iter().any(|i| is_project_file(&i.uri)) {
+            self.load_project_settings().await;
+            self.apply_settings().await;
+        }
+        for i in params.changes.into_iter().filter(|i| {
+            !i.uri.path().ends_with(&format!("/{PROJECT_FILE}")) && self.in_workspace(&i.uri)
+        }) {
             match i.typ {
                 FileChangeType::CREATED => {
                     self.load(i.uri);
@@ -397,6 +1017,17 @@ impl LanguageServer for Backend {
             }
         }
     }
+    async fn will_rename_files(&self, params: RenameFilesParams) -> Result<Option<WorkspaceEdit>> {
+        info!("will rename {:?}", params.files);
+        let root = self.pipeline.root().borrow().clone();
+        let (edit, unresolved) = ide::refactor::will_rename_files(&root, &params.files);
+        if !unresolved.is_empty() {
+            self.client
+                .show_message(MessageType::WARNING, unresolved.join("\n"))
+                .await;
+        }
+        Ok(edit)
+    }
     async fn execute_command(
         &self,
         params: ExecuteCommandParams,
@@ -408,13 +1039,13 @@ impl LanguageServer for Backend {
         }
         match params.command.as_str() {
             "uvls/load_config" => {
-                let target = format!("{}/load{}", self.web_handler_uri, uri.path());
+                let target = format!("{}/load{}", self.web_handler_uri.lock(), uri.path());
                 let response: serde_json::Value =
                     serde_json::to_value(OpenArgs { uri: target }).unwrap();
                 return Ok(Some(response));
             }
             "uvls/open_config" => {
-                let target = format!("{}/create{}", self.web_handler_uri, uri.path());
+                let target = format!("{}/create{}", self.web_handler_uri.lock(), uri.path());
                 info!("{}", target);
                 let response: serde_json::Value =
                     serde_json::to_value(OpenArgs { uri: target }).unwrap();
@@ -485,8 +1116,10 @@ impl LanguageServer for Backend {
 
                 let smt_module = uvl2smt(&module, &HashMap::new());
 
+                let solver = self.pipeline.smt_options().borrow().solver.clone();
                 let solver = SmtSolver::new(
                     smt_module.to_source(&module),
+                    &solver,
                     &root_graph.cancellation_token(),
                 )
                 .await;
@@ -518,7 +1151,13 @@ impl LanguageServer for Backend {
                                         .unwrap_or(String::from(""));
 
                                     let values_parsed: HashMap<ModuleSymbol, ConfigValue> =
-                                        smt_module.parse_values(&values, &module).collect();
+                                        match smt_module.parse_values(&values, &module) {
+                                            Ok(values) => values.into_iter().collect(),
+                                            Err(e) => {
+                                                error!("{e}");
+                                                break;
+                                            }
+                                        };
 
                                     // Store solution in file
                                     let config_module = ConfigModule {
@@ -732,54 +1371,182 @@ impl LanguageServer for Backend {
         }
     }
 
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        let uri = params.text_document.uri;
+        let provider = params
+            .identifier
+            .as_deref()
+            .and_then(DiagnosticProvider::from_identifier);
+        let root = self.pipeline.root().borrow().clone();
+        let options = self.pipeline.lint_options().borrow().clone();
+        let (result_id, items) = self.pipeline.diagnostics().report(
+            FileID::new(uri.as_str()),
+            provider,
+            &options,
+            &root,
+        );
+        if params.previous_result_id.as_ref() == Some(&result_id) {
+            return Ok(DocumentDiagnosticReportResult::Report(
+                DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                        result_id,
+                    },
+                }),
+            ));
+        }
+        Ok(DocumentDiagnosticReportResult::Report(
+            DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(result_id),
+                    items,
+                },
+            }),
+        ))
+    }
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        let provider = params
+            .identifier
+            .as_deref()
+            .and_then(DiagnosticProvider::from_identifier);
+        let previous: HashMap<Url, String> = params
+            .previous_result_ids
+            .into_iter()
+            .map(|p| (p.uri, p.value))
+            .collect();
+        let root = self.pipeline.root().borrow().clone();
+        let options = self.pipeline.lint_options().borrow().clone();
+        let store = self.pipeline.diagnostics();
+        let items = store
+            .files()
+            .into_iter()
+            .map(|id| {
+                let uri = id.url();
+                let (result_id, items) = store.report(id, provider, &options, &root);
+                if previous.get(&uri) == Some(&result_id) {
+                    WorkspaceDocumentDiagnosticReport::Unchanged(
+                        WorkspaceUnchangedDocumentDiagnosticReport {
+                            uri,
+                            version: None,
+                            unchanged_document_diagnostic_report:
+                                UnchangedDocumentDiagnosticReport { result_id },
+                        },
+                    )
+                } else {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri,
+                        version: None,
+                        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                            result_id: Some(result_id),
+                            items,
+                        },
+                    })
+                }
+            })
+            .collect();
+        Ok(WorkspaceDiagnosticReportResult::Report(
+            WorkspaceDiagnosticReport { items },
+        ))
+    }
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let mut actions = Vec::new();
+        let draft = self.snapshot(&params.text_document.uri, false).await;
         for diagnostic in params.clone().context.diagnostics {
             // Checks if there is a quick fix for the current diagnostic message
-            match diagnostic.clone().data {
+            let fix = match diagnostic.clone().data {
                 Some(serde_json::value::Value::Number(number)) => {
                     match ErrorType::from_u32(number.as_u64().unwrap_or(0) as u32) {
-                        ErrorType::Any => info!("No Quickfix for this Error"),
-                        ErrorType::FeatureNameContainsDashes => {
-                            return ide::actions::rename_dash(
-                                params.clone(),
-                                diagnostic,
-                                self.snapshot(&params.text_document.uri, false).await,
-                            )
+                        ErrorType::Any => {
+                            info!("No Quickfix for this Error");
+                            None
                         }
-                        ErrorType::ReferenceToString => {
-                            return actions::reference_to_string(
-                                params.clone(),
-                                diagnostic,
-                                self.snapshot(&params.text_document.uri, false).await,
-                            )
+                        ErrorType::FeatureNameContainsDashes => ide::actions::rename_dash(
+                            params.clone(),
+                            diagnostic.clone(),
+                            draft.clone(),
+                        )?,
+                        ErrorType::ReferenceToString => actions::reference_to_string(
+                            params.clone(),
+                            diagnostic.clone(),
+                            draft.clone(),
+                        )?,
+                        ErrorType::AddIndentation => actions::add_indentation(
+                            params.clone(),
+                            diagnostic.clone(),
+                            draft.clone(),
+                        )?,
+                        ErrorType::StartsWithNumber => actions::starts_with_number(
+                            params.clone(),
+                            diagnostic.clone(),
+                            draft.clone(),
+                        )?,
+                        ErrorType::WrongLanguageLevel => actions::add_language_level(
+                            params.clone(),
+                            diagnostic.clone(),
+                            draft.clone(),
+                        )?,
+                        ErrorType::UnusedImport => actions::remove_import(
+                            params.clone(),
+                            diagnostic.clone(),
+                            draft.clone(),
+                        )?,
+                        ErrorType::SingleChildGroup => {
+                            actions::convert_single_child_group(params.clone(), diagnostic.clone())?
                         }
-                        ErrorType::AddIndentation => {
-                            return actions::add_indentation(
-                                params.clone(),
-                                diagnostic,
-                                self.snapshot(&params.text_document.uri, false).await,
-                            )
-                        }
-                        ErrorType::StartsWithNumber => {
-                            return actions::starts_with_number(
-                                params.clone(),
-                                diagnostic,
-                                self.snapshot(&params.text_document.uri, false).await,
-                            )
-                        }
-                        ErrorType::WrongLanguageLevel => {
-                            return actions::add_language_level(
-                                params.clone(),
-                                diagnostic,
-                                self.snapshot(&params.text_document.uri, false).await,
-                            );
+                        ErrorType::EmptyGroup => {
+                            actions::remove_empty_group(params.clone(), diagnostic.clone())?
                         }
                     }
                 }
-                _ => (),
+                _ => None,
+            };
+            actions.extend(fix.unwrap_or_default());
+            if let Ok(Some((Draft::UVL { source, .. }, _))) = &draft {
+                actions.extend(actions::suppress(
+                    &params.text_document.uri,
+                    &diagnostic,
+                    source,
+                ));
             }
         }
-        return Ok(None);
+        if let Some((_, root)) = self.snapshot(&params.text_document.uri, true).await? {
+            let mut refactors =
+                ide::refactor::code_actions(&root, &params.text_document.uri, params.range);
+            let options = self.pipeline.smt_options().borrow().clone();
+            ide::refactor::summarize_group_changes(
+                &root,
+                &mut refactors,
+                &options,
+                &self.group_changes,
+            )
+            .await;
+            actions.extend(refactors);
+        }
+        if actions.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(actions));
+    }
+    async fn code_action_resolve(&self, action: CodeAction) -> Result<CodeAction> {
+        let Some(uri) = ide::refactor::group_change_uri(&action) else {
+            return Ok(action);
+        };
+        if let Some((_, root)) = self.snapshot(&uri, true).await? {
+            let options = self.pipeline.smt_options().borrow().clone();
+            Ok(
+                ide::refactor::resolve_group_change(&root, action, &options, &self.group_changes)
+                    .await,
+            )
+        } else {
+            Ok(action)
+        }
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -812,7 +1579,7 @@ async fn server_main() {
         return;
     }
 
-    let _logger = flexi_logger::Logger::try_with_env_or_str("info")
+    let logger = flexi_logger::Logger::try_with_env_or_str("info")
         .expect("Log spec string broken")
         .log_to_file(
             FileSpec::default()
@@ -829,18 +1596,12 @@ async fn server_main() {
     let (service, socket) = LspService::new(|client| {
         let pipeline = AsyncPipeline::new(client.clone());
         info!("create service");
-        let port = get_port::tcp::TcpPort::in_range(
-            "127.0.0.1",
-            get_port::Range {
-                min: 3000,
-                max: 6000,
-            },
-        )
-        .unwrap();
-        spawn(webview::web_handler(pipeline.clone(), port));
         Backend {
             settings: parking_lot::Mutex::new(Settings::default()),
-            web_handler_uri: format!("http://localhost:{port}"),
+            //the configuration editor is started once the port range is known
+            web_handler_uri: parking_lot::Mutex::new(String::new()),
+            logger: parking_lot::Mutex::new(logger),
+            group_changes: Default::default(),
             pipeline,
             coloring: Arc::new(ide::color::State::new()),
             client,
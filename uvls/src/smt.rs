@@ -9,8 +9,8 @@
 //! Using functions might be more efficient, but this way we can reconfigure and detect if
 //! an attribute value contributes to the unsat core.
 //! Variables are named as v{n} where n is an index into a lookup table of UVL ModuleSymbols
-//! Asserts are encoded similarly as a{n} where n is and index into a list of naming information
-//! that links uvl expression to asserts.
+//! Asserts are encoded similarly as a{n} where n is derived from the assert itself, see SMTModule::assert_ids,
+//! and links to the naming information of the uvl expression.
 
 use crate::{
     core::*,
@@ -23,8 +23,11 @@ use hashbrown::{HashMap, HashSet};
 use lazy_static::lazy_static;
 use log::info;
 
+use serde::Deserialize;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::{
     io::Lines,
     process::{ChildStdin, ChildStdout, Command},
@@ -51,12 +54,16 @@ pub struct SmtSolver {
     stdin: BufWriter<ChildStdin>,
     stdout: Lines<BufReader<ChildStdout>>,
     cancel: CancellationToken,
+    backend: SolverBackend,
 }
 impl SmtSolver {
-    pub async fn new(model: String, cancel: &CancellationToken) -> Result<Self> {
-        let mut proc = Command::new("z3")
-            .arg("-in")
-            .arg("-smt2")
+    pub async fn new(
+        model: String,
+        solver: &SolverOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Self> {
+        let mut proc = solver
+            .command()
             .stdin(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
@@ -76,12 +83,27 @@ impl SmtSolver {
         stdin.flush().await?;
         Ok(SmtSolver {
             cancel: cancel.clone(),
+            backend: solver.backend,
             _proc: proc,
             stdin,
             stdout,
         })
     }
 
+    /// limit the time of each following check-sat, the solver answers unknown when it is
+    /// exceeded. Generic solvers get no limit, only the analysis deadline applies
+    pub async fn set_timeout(&mut self, ms: u64) -> Result<()> {
+        match self.backend {
+            SolverBackend::Z3 => self.push(format!("(set-option :timeout {ms})\n")).await,
+            SolverBackend::Cvc5 => self.push(format!("(set-option :tlimit-per {ms})\n")).await,
+            SolverBackend::Generic => Ok(()),
+        }
+    }
+    /// replace the token used to abort pending reads
+    pub fn set_cancel(&mut self, cancel: &CancellationToken) {
+        self.cancel = cancel.clone();
+    }
+
     /// returns the output of Z3
     pub async fn read_block(&mut self) -> Result<String> {
         let mut out = String::new();
@@ -112,6 +134,7 @@ impl SmtSolver {
         match ret.as_str() {
             "sat" => Ok(true),
             "unsat" => Ok(false),
+            "unknown" => Err(TimeoutError {})?,
             s => Err(format!("Bad response {s}"))?,
         }
     }
@@ -148,11 +171,336 @@ impl SmtSolver {
     }
 }
 
-/// This function is a helper function which checks if a LSP can run z3.
+/// The solver gave up on a query, usually because the timeout was exceeded
+#[derive(Debug)]
+pub struct TimeoutError {}
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "solver timeout")
+    }
+}
+impl std::error::Error for TimeoutError {}
+
+/// Solver specific commands, only the query timeout differs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolverBackend {
+    #[default]
+    Z3,
+    Cvc5,
+    /// plain smt-lib2 without options
+    Generic,
+}
+impl SolverBackend {
+    /// arguments to read smt-lib2 incrementally from stdin
+    fn default_args(self) -> Vec<String> {
+        let args: &[&str] = match self {
+            SolverBackend::Z3 => &["-in", "-smt2"],
+            SolverBackend::Cvc5 => &["--lang=smt2", "--incremental"],
+            SolverBackend::Generic => &[],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+}
+/// Solver process, any solver reading smt-lib2 from stdin can be used
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SolverOptions {
+    pub path: String,
+    /// None for the default arguments of the backend
+    pub args: Option<Vec<String>>,
+    pub backend: SolverBackend,
+}
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self {
+            path: "z3".into(),
+            args: None,
+            backend: SolverBackend::Z3,
+        }
+    }
+}
+impl SolverOptions {
+    pub fn args(&self) -> Vec<String> {
+        self.args
+            .clone()
+            .unwrap_or_else(|| self.backend.default_args())
+    }
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(self.args());
+        cmd
+    }
+}
+
+/// Limits for the background analysis, times are in milliseconds
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SmtOptions {
+    /// time for a single solver query
+    pub query_timeout: u64,
+    /// time for all queries of one module
+    pub analysis_timeout: u64,
+    pub solver: SolverOptions,
+    /// check feature models for satisfiability
+    pub check_void: bool,
+    /// search dead features and tautologies, requires check_void
+    pub check_dead_features: bool,
+    /// check configuration files against their models
+    pub check_configs: bool,
+    /// the client supports work done progress
+    #[serde(skip)]
+    pub progress: bool,
+}
+impl Default for SmtOptions {
+    fn default() -> Self {
+        Self {
+            query_timeout: 10_000,
+            analysis_timeout: 120_000,
+            solver: SolverOptions::default(),
+            check_void: true,
+            check_dead_features: true,
+            check_configs: true,
+            progress: false,
+        }
+    }
+}
+
+/// Work done progress shown by the client while an analysis runs
+pub struct Progress {
+    client: tower_lsp::Client,
+    token: NumberOrString,
+}
+impl Progress {
+    /// None if the client can not display the progress
+    pub async fn begin(client: &tower_lsp::Client, title: String) -> Option<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let token = NumberOrString::String(format!(
+            "uvls/smt/{}",
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .ok()?;
+        let progress = Self {
+            client: client.clone(),
+            token,
+        };
+        progress
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title,
+                cancellable: Some(false),
+                message: None,
+                percentage: Some(0),
+            }))
+            .await;
+        Some(progress)
+    }
+    pub async fn report(&self, message: String, percentage: usize) {
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(message),
+            percentage: Some(percentage as u32),
+        }))
+        .await;
+    }
+    pub async fn end(self, message: Option<String>) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd { message }))
+            .await;
+    }
+    async fn send(&self, progress: WorkDoneProgress) {
+        self.client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: self.token.clone(),
+                value: ProgressParamsValue::WorkDone(progress),
+            })
+            .await;
+    }
+}
+
+/// Number of asserts sent in one push scope of a session
+const SESSION_BLOCK: usize = 64;
+
+/// How a session brings its running solver to a new module
+#[derive(Debug, PartialEq)]
+enum SessionPlan {
+    /// reset the solver and declare the variables again
+    Reset,
+    /// keep the variables and the scopes that are still valid
+    Reuse,
+}
+
+/// Long lived Z3 process that keeps the last loaded module.
+///
+/// Variables are declared at the base level and asserts are sent in blocks, each inside its
+/// own push scope. Asserts are named by their content, see [SMTModule::assert_ids], so a scope
+/// is kept as long as all of its asserts are still part of the new module. Only the scopes
+/// above the first invalid one are popped, the remaining asserts are pushed in new scopes.
+/// When the variables change the solver is reset, any failure drops the process so the next
+/// load starts from scratch.
+#[derive(Default)]
+pub struct SmtSession {
+    solver: Option<SmtSolver>,
+    //command the solver was started with
+    command: SolverOptions,
+    decls: String,
+    scopes: Vec<Vec<String>>,
+}
+impl SmtSession {
+    /// Load all asserts of module
+    pub async fn load(
+        &mut self,
+        smt: &SMTModule,
+        module: &Module,
+        solver: &SolverOptions,
+        cancel: &CancellationToken,
+    ) -> Result<&mut SmtSolver> {
+        self.load_asserts(smt, module, smt.assert_sources(), solver, cancel)
+            .await
+    }
+    /// Load only the variable declarations of module
+    pub async fn load_variables(
+        &mut self,
+        smt: &SMTModule,
+        module: &Module,
+        solver: &SolverOptions,
+        cancel: &CancellationToken,
+    ) -> Result<&mut SmtSolver> {
+        self.load_asserts(smt, module, Vec::new(), solver, cancel)
+            .await
+    }
+    async fn load_asserts(
+        &mut self,
+        smt: &SMTModule,
+        module: &Module,
+        asserts: Vec<String>,
+        command: &SolverOptions,
+        cancel: &CancellationToken,
+    ) -> Result<&mut SmtSolver> {
+        let time = Instant::now();
+        let decls = smt.variable_to_source(module);
+        //scopes are only valid with the solver, both are dropped on failure
+        let mut old = std::mem::take(&mut self.scopes);
+        let plan = match self.solver.take() {
+            Some(solver) => self.plan(command, &decls).map(|plan| (plan, solver)),
+            None => None,
+        };
+        if !matches!(plan, Some((SessionPlan::Reuse, _))) {
+            old.clear();
+        }
+        let (keep, scopes) = scope_delta(&old, asserts, SESSION_BLOCK);
+        let mut solver = match plan {
+            Some((SessionPlan::Reuse, mut solver)) => {
+                solver.set_cancel(cancel);
+                if old.len() > keep {
+                    solver.push(format!("(pop {})\n", old.len() - keep)).await?;
+                }
+                solver
+            }
+            Some((SessionPlan::Reset, mut solver)) => {
+                solver.set_cancel(cancel);
+                solver
+                    .push(format!("(reset)\n{}{decls}", smt.config_to_source()))
+                    .await?;
+                solver
+            }
+            None => {
+                self.command = command.clone();
+                SmtSolver::new(
+                    format!("{}{decls}", smt.config_to_source()),
+                    command,
+                    cancel,
+                )
+                .await?
+            }
+        };
+        let mut source = String::new();
+        for scope in &scopes {
+            source.push_str("(push 1)\n");
+            for assert in scope {
+                let _ = writeln!(source, "{assert}");
+            }
+        }
+        solver.push(source).await?;
+        info!(
+            "smt session: reused {keep}/{} scopes in {:?}",
+            keep + scopes.len(),
+            time.elapsed()
+        );
+        old.truncate(keep);
+        old.extend(scopes);
+        self.scopes = old;
+        self.decls = decls;
+        Ok(self.solver.insert(solver))
+    }
+    /// How to load decls into the running solver, None if a new solver is needed
+    fn plan(&self, command: &SolverOptions, decls: &str) -> Option<SessionPlan> {
+        if self.command != *command {
+            None
+        } else if self.decls != decls {
+            Some(SessionPlan::Reset)
+        } else {
+            Some(SessionPlan::Reuse)
+        }
+    }
+    /// Drop the solver, eg. when it is in an unknown state after an error
+    pub fn reset(&mut self) {
+        self.solver = None;
+        self.scopes.clear();
+    }
+}
+
+/// Number of leading scopes that only contain asserts that are still loaded and
+/// the new scopes for all remaining asserts, each with at most n asserts
+fn scope_delta(
+    scopes: &[Vec<String>],
+    asserts: Vec<String>,
+    n: usize,
+) -> (usize, Vec<Vec<String>>) {
+    let mut remaining: HashSet<String> = asserts.iter().cloned().collect();
+    let mut keep = 0;
+    for scope in scopes {
+        if !scope.iter().all(|a| remaining.contains(a)) {
+            break;
+        }
+        for a in scope {
+            remaining.remove(a);
+        }
+        keep += 1;
+    }
+    let mut new = Vec::new();
+    for assert in asserts {
+        if !remaining.contains(&assert) {
+            continue;
+        }
+        if new
+            .last()
+            .map(|s: &Vec<String>| s.len() >= n)
+            .unwrap_or(true)
+        {
+            new.push(Vec::new());
+        }
+        new.last_mut().unwrap().push(assert);
+    }
+    (keep, new)
+}
+
+/// Sessions used to analyse a single module
+#[derive(Default)]
+pub struct ModuleSession {
+    model: SmtSession,
+    constraints: SmtSession,
+}
+
+/// This function is a helper function which checks if a LSP can run the solver.
 ///
 /// This mainly focuses on self compiled LSPs
-pub fn can_run_z3() -> bool {
-    Command::new("z3")
+pub fn can_run_solver(solver: &SolverOptions) -> bool {
+    solver
+        .command()
         .stdin(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
@@ -162,7 +510,7 @@ pub fn can_run_z3() -> bool {
 
 lazy_static! {
     /// A static reference wether a client has z3 installed
-    static ref HAS_Z3: bool = can_run_z3();
+    static ref HAS_Z3: bool = can_run_solver(&SolverOptions::default());
 }
 
 #[derive(Debug, Clone)]
@@ -177,6 +525,8 @@ pub enum SMTModel {
     SAT {
         values: HashMap<ModuleSymbol, ConfigValue>,
         fixed: HashMap<ModuleSymbol, SMTValueState>,
+        /// every query of the fixed value analysis finished in time
+        complete: bool,
     },
     UNSAT {
         reasons: Vec<AssertInfo>,
@@ -191,19 +541,63 @@ impl Default for SMTModel {
         Self::SAT {
             values: HashMap::new(),
             fixed: HashMap::new(),
+            complete: false,
+        }
+    }
+}
+/// Limits and feedback of a single analysis run
+struct AnalysisContext<'a> {
+    cancel: CancellationToken,
+    options: &'a SmtOptions,
+    progress: Option<&'a Progress>,
+    deadline: Instant,
+}
+impl<'a> AnalysisContext<'a> {
+    fn new(
+        cancel: CancellationToken,
+        options: &'a SmtOptions,
+        progress: Option<&'a Progress>,
+    ) -> Self {
+        Self {
+            cancel,
+            options,
+            progress,
+            deadline: Instant::now() + Duration::from_millis(options.analysis_timeout),
+        }
+    }
+    /// Err if the analysis was cancelled, false if it ran out of time
+    fn proceed(&self) -> Result<bool> {
+        if self.cancel.is_cancelled() {
+            Err(CancelledError {})?
+        }
+        Ok(Instant::now() < self.deadline)
+    }
+    /// report step done of total, only whole percentages are sent to the client
+    async fn report(&self, task: &str, done: usize, total: usize) {
+        if let Some(progress) = self.progress {
+            if done == 0 || (done * 100 / total) != ((done - 1) * 100 / total) {
+                progress
+                    .report(format!("{task} {done}/{total}"), done * 100 / total)
+                    .await;
+            }
         }
     }
 }
+
 /// find constant boolean values for dead features and other cool analysis
 //this is quite naive and should be improved with a better solver
+//when the analysis runs out of time only verified values are returned,
+//the flag is false if any query did not finish
 async fn find_fixed(
     solve: &mut SmtSolver,
+    constraint_session: &mut SmtSession,
     base_module: &Module,
     module: &SMTModule,
     initial_model: impl Iterator<Item = (ModuleSymbol, ConfigValue)>,
-    cancel: CancellationToken,
-) -> Result<HashMap<ModuleSymbol, SMTValueState>> {
+    ctx: &AnalysisContext<'_>,
+) -> Result<(HashMap<ModuleSymbol, SMTValueState>, bool)> {
     let mut state = HashMap::new();
+    let mut complete = true;
     for (s, v) in initial_model {
         match v {
             ConfigValue::Bool(true) => {
@@ -216,8 +610,17 @@ async fn find_fixed(
         }
     }
     let keys: Vec<ModuleSymbol> = state.keys().cloned().collect();
-    for k in keys {
-        match &state[&k] {
+    for (i, k) in keys.iter().enumerate() {
+        ctx.report("dead feature analysis", i, keys.len()).await;
+        if !ctx.proceed()? {
+            info!("dead feature analysis timed out after {i}/{}", keys.len());
+            //values of the remaining features are not verified
+            for k in keys[i..].iter() {
+                state.remove(k);
+            }
+            return Ok((state, false));
+        }
+        match &state[k] {
             SMTValueState::Any => {
                 continue;
             }
@@ -225,7 +628,7 @@ async fn find_fixed(
                 solve
                     .push(format!(
                         "(push 1)(assert (not {}))",
-                        module.pseudo_bool(k, base_module)
+                        module.pseudo_bool(*k, base_module)
                     ))
                     .await?;
             }
@@ -233,33 +636,40 @@ async fn find_fixed(
                 solve
                     .push(format!(
                         "(push 1)(assert {})",
-                        module.pseudo_bool(k, base_module)
+                        module.pseudo_bool(*k, base_module)
                     ))
                     .await?;
             }
         }
-        //let time = Instant::now();
-        if solve.check_sat().await? {
-            let unknown = state
-                .iter()
-                .filter(|(_, v)| !matches!(*v, SMTValueState::Any))
-                .fold(String::new(), |acc, (k, _)| {
-                    format!("{acc} v{}", module.var(*k))
-                });
-            let values = solve.values(unknown).await?;
-            for (s, v) in module.parse_values(&values, base_module) {
-                if let Some(old) = state.get(&s) {
-                    match (v, old) {
-                        (ConfigValue::Bool(true), SMTValueState::Off) => {
-                            state.insert(s, SMTValueState::Any);
-                        }
-                        (ConfigValue::Bool(false), SMTValueState::On) => {
-                            state.insert(s, SMTValueState::Any);
+        match solve.check_sat().await {
+            Ok(true) => {
+                let unknown = state
+                    .iter()
+                    .filter(|(_, v)| !matches!(*v, SMTValueState::Any))
+                    .fold(String::new(), |acc, (k, _)| {
+                        format!("{acc} v{}", module.var(*k))
+                    });
+                let values = solve.values(unknown).await?;
+                for (s, v) in module.parse_values(&values, base_module)? {
+                    if let Some(old) = state.get(&s) {
+                        match (v, old) {
+                            (ConfigValue::Bool(true), SMTValueState::Off) => {
+                                state.insert(s, SMTValueState::Any);
+                            }
+                            (ConfigValue::Bool(false), SMTValueState::On) => {
+                                state.insert(s, SMTValueState::Any);
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
                 }
             }
+            Ok(false) => {}
+            Err(e) if e.is::<TimeoutError>() => {
+                state.insert(*k, SMTValueState::Any);
+                complete = false;
+            }
+            Err(e) => return Err(e),
         }
         solve.push("(pop 1)".into()).await?;
     }
@@ -267,16 +677,26 @@ async fn find_fixed(
 
     // load in the module all variable and all constraints as Asserts
     let smt_module_constraint = uvl2smt_constraints(&base_module);
-    // create source for smtsolver, but only with the variable
-    let mut source_variable = smt_module_constraint.config_to_source();
-    let _ = writeln!(
-        source_variable,
-        "{}",
-        smt_module_constraint.variable_to_source(&base_module)
-    );
-    //create SMTSolver for the constraints
-    let mut solver_constraint = SmtSolver::new(source_variable, &cancel).await?;
+    // the solver only knows the variables, constraints are checked one by one
+    let solver_constraint = constraint_session
+        .load_variables(
+            &smt_module_constraint,
+            base_module,
+            &ctx.options.solver,
+            &ctx.cancel,
+        )
+        .await?;
+    solver_constraint
+        .set_timeout(ctx.options.query_timeout)
+        .await?;
+    let total = smt_module_constraint.asserts.len();
     for (i, Assert(info, expr)) in smt_module_constraint.asserts.iter().enumerate() {
+        ctx.report("tautology analysis", i, total).await;
+        if !ctx.proceed()? {
+            info!("tautology analysis timed out after {i}/{total}");
+            complete = false;
+            break;
+        }
         //get the negated constraint source
         let constraint_assert = smt_module_constraint.assert_to_source(i, info, expr, true);
         //push negated constraint
@@ -284,29 +704,54 @@ async fn find_fixed(
             .push(format!("(push 1) {}", constraint_assert))
             .await?;
         //check if negated constraint is unsat
-        let sat = solver_constraint.check_sat().await?;
-        if !sat {
-            let module_symbol = info.clone().unwrap().0;
-            state.insert(module_symbol, SMTValueState::On);
+        match solver_constraint.check_sat().await {
+            Ok(false) => {
+                let module_symbol = info.clone().unwrap().0;
+                state.insert(module_symbol, SMTValueState::On);
+            }
+            Ok(true) => {}
+            Err(e) if e.is::<TimeoutError>() => {
+                complete = false;
+            }
+            Err(e) => return Err(e),
         }
         //pop negated constraint
         solver_constraint.push("(pop 1)".into()).await?;
     }
 
-    Ok(state)
+    Ok((state, complete))
 }
 
-/// Creator for SMTModel
+/// Creator for SMTModel, the sessions are reset if anything fails
 async fn create_model(
+    session: &mut ModuleSession,
+    base_module: &Module,
+    module: SMTModule,
+    fixed: bool,
+    value: bool,
+    ctx: &AnalysisContext<'_>,
+) -> Result<SMTModel> {
+    let res = create_model_in(session, base_module, module, fixed, value, ctx).await;
+    if res.is_err() {
+        session.model.reset();
+        session.constraints.reset();
+    }
+    res
+}
+async fn create_model_in(
+    session: &mut ModuleSession,
     base_module: &Module,
-    cancel: CancellationToken,
     module: SMTModule,
-    source: String,
     fixed: bool,
     value: bool,
+    ctx: &AnalysisContext<'_>,
 ) -> Result<SMTModel> {
     let time = Instant::now();
-    let mut solver = SmtSolver::new(source, &cancel).await?;
+    let solver = session
+        .model
+        .load(&module, base_module, &ctx.options.solver, &ctx.cancel)
+        .await?;
+    solver.set_timeout(ctx.options.query_timeout).await?;
     info!("create model: {:?}", time.elapsed());
     if solver.check_sat().await? {
         let values = if value | fixed {
@@ -319,26 +764,32 @@ async fn create_model(
             let values = solver.values(query).await?;
 
             let time = Instant::now();
-            let values = module.parse_values(&values, base_module).collect();
+            let values = module
+                .parse_values(&values, base_module)?
+                .into_iter()
+                .collect();
             info!("parse values: {:?}", time.elapsed());
             values
         } else {
             HashMap::new()
         };
+        let (fixed, complete) = if fixed {
+            find_fixed(
+                solver,
+                &mut session.constraints,
+                base_module,
+                &module,
+                values.iter().map(|(k, v)| (*k, v.clone())),
+                ctx,
+            )
+            .await?
+        } else {
+            (HashMap::new(), false)
+        };
         Ok(SMTModel::SAT {
-            fixed: if fixed {
-                find_fixed(
-                    &mut solver,
-                    base_module,
-                    &module,
-                    values.iter().map(|(k, v)| (*k, v.clone())),
-                    cancel,
-                )
-                .await?
-            } else {
-                HashMap::new()
-            },
+            fixed,
             values,
+            complete,
         })
     } else {
         let core = solver.unsat_core().await?;
@@ -348,51 +799,188 @@ async fn create_model(
     }
 }
 
+/// Configurations are only counted up to this bound
+pub const COUNT_LIMIT: usize = 100;
+/// Count configurations satisfying the loaded module and expr, configurations are
+/// distinguished by their selected features only
+async fn count_configurations(
+    solver: &mut SmtSolver,
+    base_module: &Module,
+    module: &SMTModule,
+    expr: &smt_lib::Expr,
+) -> Result<usize> {
+    let features: Vec<(usize, ModuleSymbol)> = module
+        .variables
+        .iter()
+        .enumerate()
+        .filter(|(_, ms)| {
+            matches!(ms.sym, Symbol::Feature(..)) && base_module.type_of(**ms) == Type::Bool
+        })
+        .map(|(i, ms)| (i, *ms))
+        .collect();
+    let query = features
+        .iter()
+        .fold(String::new(), |acc, (i, _)| format!("{acc} v{i}"));
+    solver
+        .push(format!(
+            "(push 1)\n{}\n",
+            module.assert_to_source(module.asserts.len(), &None, expr, false)
+        ))
+        .await?;
+    let mut count = 0;
+    while count < COUNT_LIMIT && solver.check_sat().await? {
+        count += 1;
+        let values: HashMap<_, _> = module
+            .parse_values(&solver.values(query.clone()).await?, base_module)?
+            .into_iter()
+            .collect();
+        let mut block = String::new();
+        for (i, ms) in features.iter() {
+            match values.get(ms) {
+                Some(ConfigValue::Bool(true)) => {
+                    let _ = write!(block, " v{i}");
+                }
+                _ => {
+                    let _ = write!(block, " (not v{i})");
+                }
+            }
+        }
+        solver
+            .push(format!("(assert (not (and{block})))\n"))
+            .await?;
+    }
+    solver.push("(pop 1)\n".into()).await?;
+    Ok(count)
+}
+/// Number of configurations (added, removed) by changing the mode of a group in the module
+/// rooted at change.file
+pub async fn group_change_delta(
+    root: &RootGraph,
+    change: &GroupChange,
+    options: &SmtOptions,
+) -> Result<(usize, usize)> {
+    let module = root
+        .cache()
+        .modules
+        .get(&change.file)
+        .filter(|m| m.ok)
+        .ok_or("module not available")?;
+    let smt = uvl2smt_group_change(module, change);
+    let mut solver = SmtSolver::new(
+        smt.module.to_source(module),
+        &options.solver,
+        &root.cancellation_token(),
+    )
+    .await?;
+    solver.set_timeout(options.query_timeout).await?;
+    let added = smt_lib::Expr::And(vec![
+        smt.new.clone(),
+        smt_lib::Expr::Not(smt.old.clone().into()),
+    ]);
+    let removed = smt_lib::Expr::And(vec![smt.old, smt_lib::Expr::Not(smt.new.into())]);
+    Ok((
+        count_configurations(&mut solver, module, &smt.module, &added).await?,
+        count_configurations(&mut solver, module, &smt.module, &removed).await?,
+    ))
+}
+
 /// This function checks the SAT Level of a FileID
 async fn check_base_sat(
     root: &RootGraph,
     tx_err: &mpsc::Sender<DiagnosticUpdate>,
+    client: &tower_lsp::Client,
+    options: &SmtOptions,
+    sessions: &mut HashMap<FileID, ModuleSession>,
     latest_revisions: HashMap<FileID, Instant>,
 ) -> HashMap<FileID, Instant> {
-    let active = root.cache().modules.iter().filter(|(k, v)| {
-        latest_revisions
-            .get(*k)
-            .map(|old| old != &v.timestamp)
-            .unwrap_or(true)
-            && v.ok
-    });
-    let models = join_all(active.map(|(_, v)| {
-        let module = v.clone();
-        async move {
-            let smt_module = uvl2smt(&module, &HashMap::new());
-            let source = smt_module.to_source(&module);
-            let model = create_model(
-                &module,
-                root.cancellation_token(),
-                smt_module,
-                source,
-                true,
-                false,
-            )
-            .await;
-            model.map(|m| (m, module))
-        }
-    }))
+    sessions.retain(|k, _| root.cache().modules.contains_key(k));
+    let active: Vec<_> = root
+        .cache()
+        .modules
+        .iter()
+        .filter(|(k, v)| {
+            latest_revisions
+                .get(*k)
+                .map(|old| old != &v.timestamp)
+                .unwrap_or(true)
+                && v.ok
+                && options.check_void
+        })
+        .map(|(k, v)| (*k, v.clone(), sessions.remove(k).unwrap_or_default()))
+        .collect();
+    let models = join_all(
+        active
+            .into_iter()
+            .map(|(k, module, mut session)| async move {
+                let smt_module = uvl2smt(&module, &HashMap::new());
+                let progress = if options.progress {
+                    let file = module.file(InstanceID(0));
+                    let name = file
+                        .uri
+                        .path_segments()
+                        .and_then(|p| p.last())
+                        .unwrap_or("");
+                    Progress::begin(client, format!("UVLS: analysing {name}")).await
+                } else {
+                    None
+                };
+                let ctx =
+                    AnalysisContext::new(root.cancellation_token(), options, progress.as_ref());
+                let model = create_model(
+                    &mut session,
+                    &module,
+                    smt_module,
+                    options.check_dead_features,
+                    false,
+                    &ctx,
+                )
+                .await;
+                if let Some(progress) = progress {
+                    progress.end(None).await;
+                }
+                (k, session, model.map(|m| (m, module)))
+            }),
+    )
     .await;
 
     let mut e = ErrorsAcc::new(root);
-    for k in models.into_iter() {
+    //modules which have to be checked again with the next revision
+    let mut unchecked = HashSet::new();
+    //files whose smt rules were all analysed
+    let mut complete = HashSet::new();
+    for (file, session, k) in models.into_iter() {
+        sessions.insert(file, session);
         match k {
-            Ok((SMTModel::SAT { fixed, .. }, module)) => {
+            Ok((
+                SMTModel::SAT {
+                    fixed,
+                    complete: finished,
+                    ..
+                },
+                module,
+            )) => {
+                if finished {
+                    complete.extend(module.instances().map(|(_, file)| file.id));
+                }
                 let mut visited = HashSet::new();
-                for (m, file) in module.instances() {
+                //templates stand for all cardinality instances
+                for (m, file) in module
+                    .instances()
+                    .filter(|(m, _)| module.cardinality_origin(*m).is_none())
+                {
                     file.visit_children(Symbol::Root, true, |sym| match sym {
                         Symbol::Feature(..) => {
                             if let Some(val) = fixed.get(&m.sym(sym)) {
                                 match val {
                                     SMTValueState::Off => {
                                         if visited.insert((sym, file.id)) {
-                                            e.sym_info(sym, file.id, 10, "dead feature");
+                                            e.sym_rule(
+                                                sym,
+                                                file.id,
+                                                10,
+                                                Rule::DeadFeature,
+                                                "dead feature",
+                                            );
                                         }
                                         false
                                     }
@@ -412,7 +1000,13 @@ async fn check_base_sat(
                                 match val {
                                     SMTValueState::On => {
                                         if visited.insert((sym, file.id)) {
-                                            e.sym_info(sym, file.id, 10, "TAUT: constraint");
+                                            e.sym_rule(
+                                                sym,
+                                                file.id,
+                                                10,
+                                                Rule::Tautology,
+                                                "TAUT: constraint",
+                                            );
                                         }
                                         false
                                     }
@@ -434,16 +1028,37 @@ async fn check_base_sat(
                     if !void_is_marked {
                         // works only if keyword feature is the only keyword stored in the Keyword vector in the AST, but since I see no reason
                         // why another keyword is needed in the green tree, so the features keyword would always have id 0.
-                        e.sym(Symbol::Keyword(0), file, 12, "void feature model");
+                        e.sym_rule(
+                            Symbol::Keyword(0),
+                            file,
+                            12,
+                            Rule::VoidModel,
+                            "void feature model",
+                        );
                         void_is_marked = true;
                     }
                     if visited.insert((r.0.sym, file)) {
-                        e.sym(r.0.sym, file, 12, format!("UNSAT: {}", r.1))
+                        e.sym_rule(r.0.sym, file, 12, Rule::Unsat, format!("UNSAT: {}", r.1))
                     }
                 }
             }
-            Err(e) => {
-                info!("SMT check failed: {e}");
+            Err(err) => {
+                info!("SMT check failed: {err}");
+                unchecked.insert(file);
+                let has_keyword = root
+                    .files
+                    .get(&file)
+                    .and_then(|f| f.lsp_range(Symbol::Keyword(0)))
+                    .is_some();
+                if err.is::<TimeoutError>() && has_keyword {
+                    e.sym_rule(
+                        Symbol::Keyword(0),
+                        file,
+                        12,
+                        Rule::AnalysisTimeout,
+                        "semantic analysis timed out",
+                    );
+                }
             }
         }
     }
@@ -451,11 +1066,14 @@ async fn check_base_sat(
         .send(DiagnosticUpdate {
             timestamp: root.revision(),
             error_state: e.errors,
+            producer: Producer::Smt,
+            complete,
         })
         .await;
     root.cache()
         .modules
         .iter()
+        .filter(|(k, _)| !unchecked.contains(*k))
         .map(|(k, v)| (*k, v.timestamp))
         .collect()
 }
@@ -465,6 +1083,8 @@ async fn check_config(
     root: &RootGraph,
     tx_err: &mpsc::Sender<DiagnosticUpdate>,
     inlay_state: &InlayHandler,
+    options: &SmtOptions,
+    sessions: &mut HashMap<FileID, ModuleSession>,
     latest_revisions: HashMap<FileID, Instant>,
 ) -> HashMap<FileID, Instant> {
     //Reset inlays
@@ -480,51 +1100,60 @@ async fn check_config(
             inlay_state.maybe_reset(InlaySource::File(*k)).await;
         }
     }
-    let active = root.cache().config_modules.iter().filter(|(k, v)| {
-        latest_revisions
-            .get(*k)
-            .map(|old| old != &v.module.timestamp)
-            .unwrap_or(true)
-            && v.module.ok
-            && k.is_config()
-    });
-    let models = join_all(active.map(|(k, v)| {
-        let k = k.clone();
-        let module = v.clone();
-        async move {
-            info!("checking {k:?}");
-            let smt_module = uvl2smt(&module, &module.values);
-            let source = smt_module.to_source(&module);
-            let is_active = inlay_state.is_active(InlaySource::File(k));
-            let model = create_model(
-                &module.module,
-                root.cancellation_token(),
-                smt_module,
-                source,
-                !k.is_config(),
-                is_active,
-            )
-            .await;
-            if let Ok(model) = model.as_ref() {
-                inlay_state
-                    .maybe_publish(InlaySource::File(k), Instant::now(), || {
-                        Arc::new(OwnedSMTModel {
-                            model: model.clone(),
-                            module: module.module.clone(),
+    sessions.retain(|k, _| root.cache().config_modules.contains_key(k));
+    let active: Vec<_> = root
+        .cache()
+        .config_modules
+        .iter()
+        .filter(|(k, v)| {
+            latest_revisions
+                .get(*k)
+                .map(|old| old != &v.module.timestamp)
+                .unwrap_or(true)
+                && v.module.ok
+                && k.is_config()
+                && options.check_configs
+        })
+        .map(|(k, v)| (*k, v.clone(), sessions.remove(k).unwrap_or_default()))
+        .collect();
+    let models = join_all(
+        active
+            .into_iter()
+            .map(|(k, module, mut session)| async move {
+                info!("checking {k:?}");
+                let smt_module = uvl2smt(&module, &module.values);
+                let is_active = inlay_state.is_active(InlaySource::File(k));
+                let ctx = AnalysisContext::new(root.cancellation_token(), options, None);
+                let model = create_model(
+                    &mut session,
+                    &module.module,
+                    smt_module,
+                    !k.is_config(),
+                    is_active,
+                    &ctx,
+                )
+                .await;
+                if let Ok(model) = model.as_ref() {
+                    inlay_state
+                        .maybe_publish(InlaySource::File(k), Instant::now(), || {
+                            Arc::new(OwnedSMTModel {
+                                model: model.clone(),
+                                module: module.module.clone(),
+                            })
                         })
-                    })
-                    .await;
-            } else {
-                inlay_state.maybe_reset(InlaySource::File(k)).await;
-            }
+                        .await;
+                } else {
+                    inlay_state.maybe_reset(InlaySource::File(k)).await;
+                }
 
-            model.map(|m| (m, k, module))
-        }
-    }))
+                (k, session, model.map(|m| (m, k, module)))
+            }),
+    )
     .await;
 
     let mut e = ErrorsAcc::new(root);
-    for k in models.into_iter() {
+    for (file, session, k) in models.into_iter() {
+        sessions.insert(file, session);
         match k {
             Ok((SMTModel::SAT { .. }, ..)) => {
                 //Do something?
@@ -532,10 +1161,11 @@ async fn check_config(
             Ok((SMTModel::UNSAT { reasons }, root_file, module)) => {
                 for r in reasons {
                     if matches!(r.1, AssertName::Config) {
-                        e.span(
+                        e.span_rule(
                             module.source_map[&r.0].clone(),
                             root_file,
                             12,
+                            Rule::Config,
                             format!("UNSAT!"),
                         );
                     }
@@ -550,6 +1180,8 @@ async fn check_config(
         .send(DiagnosticUpdate {
             timestamp: root.revision(),
             error_state: e.errors,
+            producer: Producer::Smt,
+            complete: HashSet::new(),
         })
         .await;
     root.cache()
@@ -565,25 +1197,50 @@ pub async fn check_handler(
     tx_err: mpsc::Sender<DiagnosticUpdate>,
     client: tower_lsp::Client,
     inlay_state: InlayHandler,
+    rx_options: watch::Receiver<SmtOptions>,
 ) {
-    if !can_run_z3() {
-        client
-            .send_notification::<tower_lsp::lsp_types::notification::ShowMessage>(
-                ShowMessageParams {
-                    typ: MessageType::INFO,
-                    message: "UVLS: Z3 was not found on you're system. It is required for semantic analysis".into(),
-                },
-            )
-            .await;
-    }
+    let mut checked_solver = None;
     let mut latest_versions: HashMap<FileID, Instant> = HashMap::new();
     let mut latest_versions_config: HashMap<FileID, Instant> = HashMap::new();
+    let mut sessions: HashMap<FileID, ModuleSession> = HashMap::new();
+    let mut sessions_config: HashMap<FileID, ModuleSession> = HashMap::new();
     loop {
         info!("Check SMT");
         let root = rx_root.borrow_and_update().clone();
-        latest_versions = check_base_sat(&root, &tx_err, latest_versions).await;
-        latest_versions_config =
-            check_config(&root, &tx_err, &inlay_state, latest_versions_config).await;
+        let options = rx_options.borrow().clone();
+        if checked_solver.as_ref() != Some(&options.solver) {
+            if !can_run_solver(&options.solver) {
+                client
+                    .send_notification::<tower_lsp::lsp_types::notification::ShowMessage>(
+                        ShowMessageParams {
+                            typ: MessageType::INFO,
+                            message: format!("UVLS: {} was not found on you're system. It is required for semantic analysis",options.solver.path),
+                        },
+                    )
+                    .await;
+            }
+            checked_solver = Some(options.solver.clone());
+        }
+        let time = Instant::now();
+        latest_versions = check_base_sat(
+            &root,
+            &tx_err,
+            &client,
+            &options,
+            &mut sessions,
+            latest_versions,
+        )
+        .await;
+        latest_versions_config = check_config(
+            &root,
+            &tx_err,
+            &inlay_state,
+            &options,
+            &mut sessions_config,
+            latest_versions_config,
+        )
+        .await;
+        info!("SMT check done in {:?}", time.elapsed());
         if rx_root.changed().await.is_err() {
             break;
         }
@@ -595,7 +1252,9 @@ pub async fn web_view_handler(
     tx_ui: mpsc::Sender<webview::UIAction>,
     inlay_state: InlayHandler,
     inlay_source: InlaySource,
+    rx_options: watch::Receiver<SmtOptions>,
 ) -> Result<()> {
+    let mut session = ModuleSession::default();
     loop {
         let (module, cancel, tag, config_ok) = {
             let lock = state.borrow_and_update();
@@ -605,8 +1264,9 @@ pub async fn web_view_handler(
 
         if module.ok && config_ok {
             let smt_module = uvl2smt(&module, &module.values);
-            let source = smt_module.to_source(&module);
-            let res = create_model(&module, cancel, smt_module, source, false, true).await;
+            let options = rx_options.borrow().clone();
+            let ctx = AnalysisContext::new(cancel, &options, None);
+            let res = create_model(&mut session, &module, smt_module, false, true, &ctx).await;
             match res {
                 Ok(model) => {
                     inlay_state
@@ -641,3 +1301,85 @@ pub async fn web_view_handler(
         state.changed().await?;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smt::smt_lib::tests::with_module;
+    fn scopes(scopes: &[&[&str]]) -> Vec<Vec<String>> {
+        scopes
+            .iter()
+            .map(|s| s.iter().map(|a| a.to_string()).collect())
+            .collect()
+    }
+    fn asserts(asserts: &[&str]) -> Vec<String> {
+        asserts.iter().map(|a| a.to_string()).collect()
+    }
+    #[test]
+    fn test_scope_delta() {
+        let old = scopes(&[&["a", "b"], &["c", "d"], &["e"]]);
+        //unchanged
+        let (keep, new) = scope_delta(&old, asserts(&["a", "b", "c", "d", "e"]), 2);
+        assert_eq!((keep, new), (3, Vec::new()));
+        //an edit only pops the scopes from the one containing it
+        let (keep, new) = scope_delta(&old, asserts(&["a", "b", "x", "d", "e", "f"]), 2);
+        assert_eq!((keep, new), (1, scopes(&[&["x", "d"], &["e", "f"]])));
+        //removed asserts invalidate their scope, order within the module is irrelevant
+        let (keep, new) = scope_delta(&old, asserts(&["e", "d", "c"]), 2);
+        assert_eq!((keep, new), (0, scopes(&[&["e", "d"], &["c"]])));
+        //new asserts are pushed on top
+        let (keep, new) = scope_delta(&old, asserts(&["f", "a", "b", "c", "d", "e"]), 2);
+        assert_eq!((keep, new), (3, scopes(&[&["f"]])));
+    }
+    #[test]
+    fn test_session_plan() {
+        let session = SmtSession {
+            decls: "(declare-const v0 Bool)".into(),
+            scopes: scopes(&[&["a"]]),
+            ..Default::default()
+        };
+        let command = SolverOptions::default();
+        assert_eq!(
+            session.plan(&command, "(declare-const v0 Bool)"),
+            Some(SessionPlan::Reuse)
+        );
+        //changed variables reset the solver
+        assert_eq!(
+            session.plan(&command, "(declare-const v0 Int)"),
+            Some(SessionPlan::Reset)
+        );
+        //another solver needs a new process
+        let command = SolverOptions {
+            backend: SolverBackend::Cvc5,
+            ..Default::default()
+        };
+        assert_eq!(session.plan(&command, "(declare-const v0 Bool)"), None);
+    }
+    #[test]
+    fn test_session_fallback() {
+        with_module("features\n    Root\n", |module, _| {
+            let smt = uvl2smt(module, &HashMap::new());
+            let mut session = SmtSession {
+                decls: smt.variable_to_source(module),
+                scopes: vec![smt.assert_sources()],
+                ..Default::default()
+            };
+            let command = SolverOptions {
+                path: "/nonexistent/solver".into(),
+                ..Default::default()
+            };
+            let res = tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(session.load(&smt, module, &command, &CancellationToken::new()));
+            assert!(res.is_err());
+            //a failed load drops all scopes, the next load sends everything again
+            assert!(session.solver.is_none());
+            assert!(session.scopes.is_empty());
+            assert_eq!(scope_delta(&session.scopes, smt.assert_sources(), 2).0, 0);
+            //resetting after an error also forgets the scopes
+            session.scopes = vec![smt.assert_sources()];
+            session.reset();
+            assert!(session.scopes.is_empty());
+        });
+    }
+}
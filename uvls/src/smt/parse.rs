@@ -1,132 +1,370 @@
-//! Turn smt-lib strings to rust. (currently incomplete)
+//! Turn smt-lib strings to rust.
+//!
+//! Solver responses are first parsed into s-expressions and then evaluated, this way
+//! we accept all the ways different solvers print constants: nested arithmetic,
+//! rationals, z3 algebraic numbers (root-obj) and approximated decimals (1.333?).
 
 use crate::core::*;
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag},
-    character::complete::{char, digit1, multispace0, multispace1},
-    combinator::{cut, map, map_res, opt},
-    multi::fold_many1,
-    number::complete::double,
+    bytes::complete::{is_not, tag, take_while1},
+    character::complete::{char, multispace0},
+    combinator::{all_consuming, map},
+    multi::{fold_many0, many0},
     sequence::{delimited, preceded, terminated},
     IResult,
 };
+use std::fmt::Display;
 
-use super::SMTModule;
-fn boolean(input: &str) -> IResult<&str, bool> {
-    alt((map(tag("true"), |_| true), map(tag("false"), |_| false)))(input)
+/// Solver output that could not be understood
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(pub String);
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid solver output: {}", self.0)
+    }
 }
-fn decimal(input: &str) -> IResult<&str, usize> {
-    map_res(digit1, |r: &str| r.parse())(input)
+impl std::error::Error for ParseError {}
+type ParseResult<T> = std::result::Result<T, ParseError>;
+fn error<T>(msg: impl Into<String>) -> ParseResult<T> {
+    Err(ParseError(msg.into()))
 }
-fn float(input: &str) -> IResult<&str, f64> {
-    terminated(double, opt(tag("?")))(input)
+
+#[derive(Debug, Clone, PartialEq)]
+enum SExpr<'a> {
+    Atom(&'a str),
+    String(String),
+    List(Vec<SExpr<'a>>),
 }
-fn real_op(input: &str) -> IResult<&str, f64> {
-    let mut count = 0;
-    alt((
-        preceded(
-            terminated(char('*'), multispace1),
-            fold_many1(real_val, || 1.0, |acc, i| i * acc),
-        ),
-        preceded(
-            terminated(char('+'), multispace1),
-            fold_many1(real_val, || 0.0, |acc, i| i + acc),
-        ),
-        preceded(
-            terminated(char('/'), multispace1),
-            fold_many1(
-                real_val,
-                || f64::NAN,
-                |acc, i| {
-                    if acc.is_nan() {
-                        i
-                    } else {
-                        acc / i
+impl<'a> Display for SExpr<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Atom(a) => write!(f, "{a}"),
+            Self::String(s) => write!(f, "{s:?}"),
+            Self::List(l) => {
+                write!(f, "(")?;
+                for (i, e) in l.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
                     }
+                    write!(f, "{e}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Decode smt-lib unicode escapes (\u{XX}), unknown sequences are kept as is
+fn unescape_unicode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find("\\u{") {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = rest[3..].find('}').and_then(|end| {
+            u32::from_str_radix(&rest[3..3 + end], 16)
+                .ok()
+                .and_then(char::from_u32)
+                .map(|c| (c, end + 4))
+        });
+        if let Some((c, len)) = decoded {
+            out.push(c);
+            rest = &rest[len..];
+        } else {
+            out.push_str(&rest[..3]);
+            rest = &rest[3..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+fn string(input: &str) -> IResult<&str, String> {
+    map(
+        delimited(
+            char('"'),
+            fold_many0(
+                alt((is_not("\""), map(tag("\"\""), |_| "\""))),
+                String::new,
+                |mut acc, s| {
+                    acc.push_str(s);
+                    acc
                 },
             ),
+            char('"'),
         ),
-        preceded(
-            terminated(char('-'), multispace1),
-            fold_many1(
-                real_val,
-                || 0.0,
-                move |acc, i| {
-                    count += 1;
-                    let pref = if count == 2 { -1.0 } else { 1.0 };
-                    pref * acc - i
-                },
-            ),
-        ),
-    ))(input)
+        |s| unescape_unicode(&s),
+    )(input)
 }
-fn real_val(input: &str) -> IResult<&str, f64> {
-    preceded(multispace0, real_expr)(input)
-}
-fn real_expr(input: &str) -> IResult<&str, f64> {
+fn atom(input: &str) -> IResult<&str, &str> {
     alt((
-        float,
-        delimited(
-            char('('),
-            preceded(multispace0, real_op),
-            cut(preceded(multispace0, char(')'))),
-        ),
+        delimited(char('|'), is_not("|"), char('|')),
+        take_while1(|c: char| !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | '|')),
     ))(input)
 }
-fn string(input: &str) -> IResult<&str, &str> {
-    delimited(char('"'), is_not("\""), char('"'))(input)
-}
-fn variable(input: &str) -> IResult<&str, usize> {
-    map(preceded(char('v'), decimal), |r| r)(input)
-}
-struct ValueParser<F> {
-    var_ty: F,
-}
-impl<F> ValueParser<F>
-where
-    F: Fn(usize) -> Type,
-{
-    fn inner<'a>(&self, i: &'a str) -> IResult<&'a str, (usize, ConfigValue)> {
-        let (i, var) = preceded(multispace0, variable)(i)?;
-        match (self.var_ty)(var) {
-            Type::Bool => map(preceded(multispace1, boolean), |b| ConfigValue::Bool(b))(i),
-            Type::Real => map(preceded(multispace1, real_expr), |b| ConfigValue::Number(b))(i),
-            Type::String => map(preceded(multispace1, string), |b| {
-                ConfigValue::String(b.to_string())
-            })(i),
-            _ => unimplemented!(),
-        }
-        .map(|(s, v)| (s, (var, v)))
-    }
-    fn parse<'a>(&self, i: &'a str) -> IResult<&'a str, (usize, ConfigValue)> {
-        preceded(
-            multispace0,
-            delimited(
-                char('('),
-                |i| self.inner(i),
-                cut(preceded(multispace0, char(')'))),
+fn sexpr(input: &str) -> IResult<&str, SExpr<'_>> {
+    preceded(
+        multispace0,
+        alt((
+            map(string, SExpr::String),
+            map(atom, SExpr::Atom),
+            map(
+                delimited(char('('), many0(sexpr), preceded(multispace0, char(')'))),
+                SExpr::List,
             ),
-        )(i)
+        )),
+    )(input)
+}
+fn parse_sexpr(input: &str) -> ParseResult<SExpr<'_>> {
+    match all_consuming(terminated(sexpr, multispace0))(input) {
+        Ok((_, e)) => Ok(e),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+            let offset = input.len() - e.input.len();
+            let context: String = e.input.chars().take(20).collect();
+            error(format!("unexpected input at {offset}: {context:?}"))
+        }
+        Err(nom::Err::Incomplete(..)) => error("incomplete input"),
+    }
+}
+
+/// A constant from solver output
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+impl Value {
+    fn number(self) -> ParseResult<f64> {
+        match self {
+            Self::Number(n) => Ok(n),
+            v => error(format!("expected a number got {v:?}")),
+        }
+    }
+    /// convert to a config value of type ty
+    pub fn typed(self, ty: Type) -> ParseResult<ConfigValue> {
+        match (ty, self) {
+            (Type::Bool, Self::Bool(b)) => Ok(ConfigValue::Bool(b)),
+            (Type::Real, Self::Number(n)) => Ok(ConfigValue::Number(n)),
+            (Type::String, Self::String(s)) => Ok(ConfigValue::String(s)),
+            (ty, v) => error(format!("expected {ty:?} got {v:?}")),
+        }
+    }
+}
+fn numeral(a: &str) -> Option<f64> {
+    //z3 marks approximated decimals with ?
+    let a = a.strip_suffix('?').unwrap_or(a);
+    if a.starts_with(|c: char| c.is_ascii_digit()) {
+        a.parse().ok()
+    } else {
+        None
+    }
+}
+fn eval_numbers(args: &[SExpr]) -> ParseResult<Vec<f64>> {
+    args.iter().map(|a| eval(a)?.number()).collect()
+}
+/// evaluate a constant term
+fn eval(e: &SExpr) -> ParseResult<Value> {
+    match e {
+        SExpr::Atom("true") => Ok(Value::Bool(true)),
+        SExpr::Atom("false") => Ok(Value::Bool(false)),
+        SExpr::Atom(a) => numeral(a)
+            .map(Value::Number)
+            .ok_or_else(|| ParseError(format!("unknown constant {a}"))),
+        SExpr::String(s) => Ok(Value::String(s.clone())),
+        SExpr::List(l) => match l.as_slice() {
+            [SExpr::Atom("-"), x] => Ok(Value::Number(-eval(x)?.number()?)),
+            [SExpr::Atom("-"), first, rest @ ..] => Ok(Value::Number(
+                eval_numbers(rest)?
+                    .into_iter()
+                    .fold(eval(first)?.number()?, |acc, i| acc - i),
+            )),
+            [SExpr::Atom("+"), args @ ..] => Ok(Value::Number(eval_numbers(args)?.iter().sum())),
+            [SExpr::Atom("*"), args @ ..] => {
+                Ok(Value::Number(eval_numbers(args)?.iter().product()))
+            }
+            [SExpr::Atom("/"), first, rest @ ..] => Ok(Value::Number(
+                eval_numbers(rest)?
+                    .into_iter()
+                    .fold(eval(first)?.number()?, |acc, i| acc / i),
+            )),
+            [SExpr::Atom("to_real"), x] => Ok(Value::Number(eval(x)?.number()?)),
+            [SExpr::Atom("root-obj"), poly, SExpr::Atom(k)] => {
+                let k: usize = k
+                    .parse()
+                    .map_err(|_| ParseError(format!("bad root index {k}")))?;
+                let roots = real_roots(&polynomial(poly)?);
+                k.checked_sub(1)
+                    .and_then(|k| roots.get(k))
+                    .map(|r| Value::Number(*r))
+                    .ok_or_else(|| ParseError(format!("{e} has no root {k}")))
+            }
+            _ => error(format!("unsupported term {e}")),
+        },
     }
 }
 
-pub fn iter_values<'a>(
-    smt_module: &'a SMTModule,
-    module: &'a Module,
-    mut values: &'a str,
-) -> impl Iterator<Item = (ModuleSymbol, ConfigValue)> + 'a {
-    values = &values[values.find('(').map(|i| i + 1).unwrap_or(0)..];
-    let parser = ValueParser {
-        var_ty: |id| module.type_of(smt_module.variables[id]),
+/// coefficients of a univariate polynomial, lowest degree first
+fn polynomial(e: &SExpr) -> ParseResult<Vec<f64>> {
+    fn add(a: &[f64], b: &[f64]) -> Vec<f64> {
+        (0..a.len().max(b.len()))
+            .map(|i| a.get(i).unwrap_or(&0.0) + b.get(i).unwrap_or(&0.0))
+            .collect()
+    }
+    fn mul(a: &[f64], b: &[f64]) -> Vec<f64> {
+        let mut out = vec![0.0; a.len() + b.len() - 1];
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                out[i + j] += x * y;
+            }
+        }
+        out
+    }
+    let neg = |p: Vec<f64>| p.into_iter().map(|c| -c).collect::<Vec<_>>();
+    match e {
+        SExpr::List(l) => match l.as_slice() {
+            [SExpr::Atom("-"), x] => Ok(neg(polynomial(x)?)),
+            [SExpr::Atom("-"), first, rest @ ..] => {
+                rest.iter().try_fold(polynomial(first)?, |acc, i| {
+                    Ok(add(&acc, &neg(polynomial(i)?)))
+                })
+            }
+            [SExpr::Atom("+"), args @ ..] => args
+                .iter()
+                .try_fold(vec![0.0], |acc, i| Ok(add(&acc, &polynomial(i)?))),
+            [SExpr::Atom("*"), args @ ..] => args
+                .iter()
+                .try_fold(vec![1.0], |acc, i| Ok(mul(&acc, &polynomial(i)?))),
+            [SExpr::Atom("^"), base, SExpr::Atom(n)] => {
+                let n: usize = n
+                    .parse()
+                    .map_err(|_| ParseError(format!("bad exponent {n}")))?;
+                let base = polynomial(base)?;
+                Ok((0..n).fold(vec![1.0], |acc, _| mul(&acc, &base)))
+            }
+            _ => Ok(vec![eval(e)?.number()?]),
+        },
+        SExpr::Atom(a) if numeral(a).is_none() => Ok(vec![0.0, 1.0]),
+        _ => Ok(vec![eval(e)?.number()?]),
+    }
+}
+fn eval_polynomial(p: &[f64], x: f64) -> f64 {
+    p.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+/// all real roots in ascending order, the roots of the derivative split the
+/// real line in monotone intervals that contain at most one root each
+fn real_roots(p: &[f64]) -> Vec<f64> {
+    let deg = match p.iter().rposition(|c| *c != 0.0) {
+        Some(deg) if deg > 0 => deg,
+        _ => return Vec::new(),
+    };
+    let p = &p[..=deg];
+    if deg == 1 {
+        return vec![-p[0] / p[1]];
+    }
+    let derivative: Vec<f64> = p
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| i as f64 * c)
+        .collect();
+    let bound = 1.0
+        + p[..deg]
+            .iter()
+            .map(|c| (c / p[deg]).abs())
+            .fold(0.0, f64::max);
+    let mut points = vec![-bound];
+    points.extend(real_roots(&derivative));
+    points.push(bound);
+    let mut roots: Vec<f64> = Vec::new();
+    for w in points.windows(2) {
+        let (mut lo, mut hi) = (w[0], w[1]);
+        let (flo, fhi) = (eval_polynomial(p, lo), eval_polynomial(p, hi));
+        let root = if flo == 0.0 {
+            lo
+        } else if fhi == 0.0 {
+            hi
+        } else if flo.signum() != fhi.signum() {
+            for _ in 0..200 {
+                let mid = (lo + hi) / 2.0;
+                if mid <= lo || mid >= hi {
+                    break;
+                }
+                if eval_polynomial(p, mid).signum() == flo.signum() {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            (lo + hi) / 2.0
+        } else {
+            continue;
+        };
+        if roots.last().map(|last| *last != root).unwrap_or(true) {
+            roots.push(root);
+        }
+    }
+    roots
+}
+
+fn variable(name: &str) -> ParseResult<usize> {
+    name.strip_prefix('v')
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| ParseError(format!("unknown variable {name}")))
+}
+/// Parse the response of get-value: ((v0 true) (v1 (- 1.0)) ...)
+pub fn parse_values(input: &str) -> ParseResult<Vec<(usize, Value)>> {
+    if input.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let SExpr::List(pairs) = parse_sexpr(input)? else {
+        return error("expected a list of values");
+    };
+    pairs
+        .iter()
+        .map(|p| match p {
+            SExpr::List(l) => match l.as_slice() {
+                [SExpr::Atom(var), val] => Ok((variable(var)?, eval(val)?)),
+                _ => error(format!("expected a value got {p}")),
+            },
+            _ => error(format!("expected a value got {p}")),
+        })
+        .collect()
+}
+/// Parse the response of get-model, either (model (define-fun ..) ..) or ((define-fun ..) ..),
+/// definitions of anything but variables are ignored
+pub fn parse_model(input: &str) -> ParseResult<Vec<(usize, Value)>> {
+    let SExpr::List(defs) = parse_sexpr(input)? else {
+        return error("expected a model");
+    };
+    let defs = match defs.first() {
+        Some(SExpr::Atom("model")) => &defs[1..],
+        _ => &defs[..],
     };
-    std::iter::from_fn(move || match parser.parse(values) {
-        Ok((i, (v, c))) => {
-            values = i;
-            Some((smt_module.variables[v], c))
+    let mut out = Vec::new();
+    for d in defs {
+        let SExpr::List(l) = d else {
+            return error(format!("expected a definition got {d}"));
+        };
+        match l.as_slice() {
+            [SExpr::Atom("define-fun"), SExpr::Atom(name), SExpr::List(args), SExpr::Atom(sort), val] =>
+            {
+                if !args.is_empty() || variable(name).is_err() {
+                    continue;
+                }
+                let val = match (*sort, eval(val)?) {
+                    ("Bool", v @ Value::Bool(..))
+                    | ("Real" | "Int", v @ Value::Number(..))
+                    | ("String", v @ Value::String(..)) => v,
+                    (sort, v) => return error(format!("expected {sort} got {v:?}")),
+                };
+                out.push((variable(name)?, val));
+            }
+            [SExpr::Atom("define-fun"), ..] => {}
+            _ => return error(format!("expected a definition got {d}")),
         }
-        Err(_e) => None,
-    })
+    }
+    Ok(out)
 }
 
 #[cfg(test)]
@@ -163,7 +401,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_values() {
-        let i = "(v0 (- 1.0))
+        let i = "((v0 (- 1.0))
          (v1 (- (/ 1.0 103.0) ))
          (v2 (- 100 (/ 1.333? 102.000)  ))
             (v3 \"test\")
@@ -171,36 +409,65 @@ mod tests {
             (v5 1.0)
             ( v6 10)
          )";
-        let parser = ValueParser {
-            var_ty: |i: usize| match i {
-                3 => Type::String,
-                4 => Type::Bool,
-                _ => Type::Real,
-            },
-        };
-        let (i, (_, ConfigValue::Number(n))) = parser.parse(i).unwrap() else {
-            panic!()
-        };
-        assert_approx_eq!(n, -1.0);
-        let (i, (_, ConfigValue::Number(n))) = parser.parse(i).unwrap() else {
-            panic!()
-        };
-        assert_approx_eq!(n, -1.0 / 103.0);
-        let (i, (_, ConfigValue::Number(n))) = parser.parse(i).unwrap() else {
-            panic!()
+        let values = parse_values(i).unwrap();
+        let num = |i: usize| match values[i] {
+            (_, Value::Number(n)) => n,
+            _ => panic!(),
         };
-        assert_approx_eq!(n, 100.0 - 1.333 / 102.0);
-        let (i, (_, ConfigValue::String(n))) = parser.parse(i).unwrap() else {
-            panic!()
-        };
-        assert_eq!(n, "test");
-        let (i, (_, ConfigValue::Bool(n))) = parser.parse(i).unwrap() else {
-            panic!()
-        };
-        assert_eq!(n, true);
-        let (_i, (_, ConfigValue::Number(n))) = parser.parse(i).unwrap() else {
-            panic!()
+        assert_approx_eq!(num(0), -1.0);
+        assert_approx_eq!(num(1), -1.0 / 103.0);
+        assert_approx_eq!(num(2), 100.0 - 1.333 / 102.0);
+        assert_eq!(values[3], (3, Value::String("test".into())));
+        assert_eq!(values[4], (4, Value::Bool(true)));
+        assert_approx_eq!(num(5), 1.0);
+        assert_eq!(values[6], (6, Value::Number(10.0)));
+        assert_eq!(parse_values("  ").unwrap(), vec![]);
+    }
+    #[test]
+    fn test_strings() {
+        let i = "((v0 \"\") (v1 \"say \"\"hi\"\"\") (v2 \"a\\u{5c}b\\u{e4}\"))";
+        let values = parse_values(i).unwrap();
+        assert_eq!(values[0].1, Value::String("".into()));
+        assert_eq!(values[1].1, Value::String("say \"hi\"".into()));
+        assert_eq!(values[2].1, Value::String("a\\b\u{e4}".into()));
+    }
+    #[test]
+    fn test_algebraic() {
+        let values =
+            parse_values("((v0 (root-obj (+ (^ x 2) (- 2)) 2)) (v1 (/ (- 3) 4)) (v2 (- 2 1 1)))")
+                .unwrap();
+        let num = |i: usize| match values[i] {
+            (_, Value::Number(n)) => n,
+            _ => panic!(),
         };
-        assert_approx_eq!(n, 1.0);
+        assert_approx_eq!(num(0), 2.0f64.sqrt());
+        assert_approx_eq!(num(1), -0.75);
+        assert_approx_eq!(num(2), 0.0);
+        let roots = real_roots(&[-6.0, 11.0, -6.0, 1.0]);
+        assert_eq!(roots.len(), 3);
+        assert_approx_eq!(roots[0], 1.0);
+        assert_approx_eq!(roots[2], 3.0);
+    }
+    #[test]
+    fn test_model() {
+        let z3 = "(
+  (define-fun v1 () Real
+    (- 2.5))
+  (define-fun v0 () Bool
+    true)
+  (define-fun floor ((x Real)) Int (to_int x))
+)";
+        assert_eq!(
+            parse_model(z3).unwrap(),
+            vec![(1, Value::Number(-2.5)), (0, Value::Bool(true))]
+        );
+        let cvc5 = "(model (define-fun v0 () Int 3) (define-fun |v1| () String \"a\"))";
+        assert_eq!(
+            parse_model(cvc5).unwrap(),
+            vec![(0, Value::Number(3.0)), (1, Value::String("a".into()))]
+        );
+        assert!(parse_model("(model (define-fun v0 () Bool 1))").is_err());
+        assert!(parse_values("((v0 (foo 1)))").is_err());
+        assert!(parse_values("((v0 true)").is_err());
     }
 }
@@ -4,10 +4,11 @@ use indexmap::IndexSet;
 use lazy_static::lazy_static;
 use log::info;
 use regex::Regex;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::Display;
 use std::fmt::Write;
+use std::hash::{Hash, Hasher};
 use tokio::time::Instant;
-use ustr::Ustr;
 #[derive(Clone, Debug)]
 pub enum AssertName {
     Config,
@@ -70,10 +71,27 @@ pub enum Expr {
     Strlen(Box<Expr>),
     StrLess(Vec<Expr>),
     StrLessEq(Vec<Expr>),
+    /// lhs ++ rhs
     StrConcat(Box<Expr>, Box<Expr>),
     //IfThenElse
     Ite(Box<Expr>, Box<Expr>, Box<Expr>),
 }
+/// Escape a string literal for smt-lib, quotes are doubled and everything outside of
+/// printable ascii is encoded as unicode escape
+pub fn escape_string(val: &str) -> String {
+    let mut out = String::with_capacity(val.len());
+    for c in val.chars() {
+        match c {
+            '"' => out.push_str("\"\""),
+            '\\' => out.push_str("\\u{5c}"),
+            ' '..='~' => out.push(c),
+            _ => {
+                let _ = write!(out, "\\u{{{:x}}}", c as u32);
+            }
+        }
+    }
+    out
+}
 /// This is a helper module, to use SMT (and in that sense Z3) for every UVL Module
 /// A smt-lib module equivalent to some UVL source module.
 pub struct SMTModule {
@@ -81,86 +99,72 @@ pub struct SMTModule {
     pub asserts: Vec<Assert>,
 }
 impl SMTModule {
+    fn variable(&self, idx: usize) -> Result<ModuleSymbol> {
+        Ok(*self
+            .variables
+            .get_index(idx)
+            .ok_or_else(|| super::parse::ParseError(format!("unknown variable v{idx}")))?)
+    }
+    /// extract values from a get-model response
     #[allow(dead_code)]
-    pub fn parse_model<'a>(
-        &'a self,
-        model: &'a str,
-    ) -> impl Iterator<Item = (ModuleSymbol, ConfigValue)> + 'a {
-        lazy_static! {
-            static ref RE: Regex =
-                Regex::new(r#"\(\s*define-fun\s+v(\d+)\s+\(\)\s+(Bool|String|Real)\s+(true|false|"[^"]*"|-?[0-9]*\.[0-9]*)\s*\)"#)
-                    .unwrap();
-        };
-
-        RE.captures_iter(model).map(|i| {
-            let idx: usize = i[1].parse().unwrap();
-            let var = *self.variables.get_index(idx).unwrap();
-            (
-                var,
-                match &i[2] {
-                    "Bool" => ConfigValue::Bool(match &i[3] {
-                        "true" => true,
-                        _ => false,
-                    }),
-                    "Real" => ConfigValue::Number(i[3].parse().unwrap()),
-                    _ => {
-                        let s = &i[3];
-                        ConfigValue::String(s[1..s.len() - 1].into())
-                    }
-                },
-            )
-        })
+    pub fn parse_model(
+        &self,
+        model: &str,
+        module: &Module,
+    ) -> Result<Vec<(ModuleSymbol, ConfigValue)>> {
+        super::parse::parse_model(model)?
+            .into_iter()
+            .map(|(i, v)| {
+                let ms = self.variable(i)?;
+                Ok((ms, v.typed(module.type_of(ms))?))
+            })
+            .collect()
     }
-    /// extract values from a response string
-    pub fn parse_values<'a>(
-        &'a self,
-        values: &'a str,
-        module: &'a Module,
-    ) -> impl Iterator<Item = (ModuleSymbol, ConfigValue)> + 'a {
-        super::parse::iter_values(self, module, values)
-        /*
-        //This abomination parses a identifier with a bool, negative number, positive number or a string
-        //The problem is that z3 encodes numbers using nested expressions instead of simple floating point
-        //values. Hopefully all cases are covered here...
-        //TODO replace this with a true eval parser(best use NOM)
-
-        lazy_static! {
-            static ref RE: Regex = Regex::new(
-                r#"\(\s*v(\d+)\s+(?:(true|false)|\(- ((?:[0-9]*\.)?[0-9]+)\??|((?:[0-9]*\.)?[0-9]+)\??|"([^"]*)")\s*\)"#
-            )
-            .unwrap();
-        };
-        //info!("{values}");
-        RE.captures_iter(values).map(|i| {
-            let idx: usize = i[1].parse().unwrap();
-            let var = *self.variables.get_index(idx).unwrap();
-            (
-                var,
-                match (i.get(2), i.get(3), i.get(4), i.get(5)) {
-                    (Some(b), _, _, _) => ConfigValue::Bool(match b.as_str() {
-                        "true" => true,
-                        _ => false,
-                    }),
-                    (_, Some(num), _, _) => {
-                        ConfigValue::Number(-(num.as_str().parse::<f64>().unwrap()))
-                    }
-                    (_, _, Some(num), _) => ConfigValue::Number(num.as_str().parse().unwrap()),
-                    (_, _, _, Some(s)) => ConfigValue::String(s.as_str().into()),
-                    _ => unreachable!(),
-                },
-            )
-        })
-        */
+    /// extract values from a get-value response
+    pub fn parse_values(
+        &self,
+        values: &str,
+        module: &Module,
+    ) -> Result<Vec<(ModuleSymbol, ConfigValue)>> {
+        super::parse::parse_values(values)?
+            .into_iter()
+            .map(|(i, v)| {
+                let ms = self.variable(i)?;
+                Ok((ms, v.typed(module.type_of(ms))?))
+            })
+            .collect()
     }
     pub fn parse_unsat_core<'a>(&'a self, core: &'a str) -> impl Iterator<Item = AssertInfo> + 'a {
         lazy_static! {
             static ref RE: Regex = Regex::new(r"a(\d+)").unwrap();
         };
-        RE.captures_iter(core).filter_map(|i| {
-            let idx: usize = i[1].parse().unwrap();
-            self.asserts[idx].0.clone()
+        let index: HashMap<usize, usize> = self
+            .assert_ids()
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| (id, i))
+            .collect();
+        RE.captures_iter(core).filter_map(move |i| {
+            let idx = index.get(&i[1].parse::<usize>().ok()?)?;
+            self.asserts[*idx].0.clone()
         })
     }
+    /// Name of each assert, derived from its expression and the number of identical asserts
+    /// before it. Unlike the position, the name survives edits to unrelated asserts.
+    pub fn assert_ids(&self) -> Vec<usize> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        self.asserts
+            .iter()
+            .map(|Assert(_, expr)| {
+                let source = self.assert_to_source(0, &None, expr, false);
+                let n = seen.entry_ref(&source).or_insert(0);
+                let mut hasher = DefaultHasher::new();
+                (&source, *n).hash(&mut hasher);
+                *n += 1;
+                hasher.finish() as usize
+            })
+            .collect()
+    }
     /// create source to config z3 Solver
     pub fn config_to_source(&self) -> String {
         let out = "(set-option :produce-unsat-cores true)
@@ -219,7 +223,7 @@ impl SMTModule {
                             let _ = write!(out, " {r:?}");
                         }
                         Expr::String(val) => {
-                            let _ = write!(out, " \"{val}\"");
+                            let _ = write!(out, " \"{}\"", escape_string(val));
                         }
                         Expr::Var(off) => {
                             let _ = write!(out, " v{off}");
@@ -310,7 +314,7 @@ impl SMTModule {
                             stack.push(CExpr::End);
                             stack.push(CExpr::Expr(e));
                         }
-                        Expr::StrConcat(rhs, lhs) => {
+                        Expr::StrConcat(lhs, rhs) => {
                             stack.push(CExpr::End);
                             stack.push(CExpr::Expr(rhs));
                             stack.push(CExpr::Expr(lhs));
@@ -338,14 +342,22 @@ impl SMTModule {
         out
     }
 
+    /// Source of each assert, named by its id
+    pub fn assert_sources(&self) -> Vec<String> {
+        self.asserts
+            .iter()
+            .zip(self.assert_ids())
+            .map(|(Assert(info, expr), id)| self.assert_to_source(id, info, expr, false))
+            .collect()
+    }
     /// tree to source
     pub fn to_source(&self, module: &Module) -> String {
         let time = Instant::now();
         //
         let mut out = self.config_to_source();
         let _ = writeln!(out, "{}", self.variable_to_source(module));
-        for (i, Assert(info, expr)) in self.asserts.iter().enumerate() {
-            let _ = writeln!(out, "{}", self.assert_to_source(i, info, expr, false));
+        for source in self.assert_sources() {
+            let _ = writeln!(out, "{source}");
         }
         info!("model to string  in {:?}", time.elapsed());
         out
@@ -393,78 +405,18 @@ impl<'a> SMTBuilder<'a> {
         }
     }
     fn clause(&self, g: ModuleSymbol) -> Vec<Expr> {
-        let file = self
-            .module
-            .instances()
-            .filter(|(id, _)| id == &g.instance)
-            .map(|(_, f)| f)
-            .collect::<Vec<&AstDocument>>()[0];
-
-        let mut cardinalitys: HashMap<Ustr, (Cardinality, Vec<Symbol>)> = HashMap::from([]);
-        // Cardinality
-        let card_expressions = self
-            .module
-            .file(g.instance)
-            .direct_children(g.sym)
-            .filter(|i| {
-                if let Symbol::Feature(index) = i {
-                    let feature = file.get_feature(index.clone()).unwrap();
-                    match feature.cardinality {
-                        Some(Cardinality::Range(_, _)) => {
-                            if !cardinalitys.contains_key(&feature.name.name) {
-                                cardinalitys.insert(
-                                    feature.name.name,
-                                    (
-                                        feature.clone().cardinality.unwrap(),
-                                        vec![Symbol::Feature(index.clone())],
-                                    ),
-                                );
-                            } else {
-                                cardinalitys
-                                    .get_mut(&feature.name.name)
-                                    .unwrap()
-                                    .1
-                                    .push(Symbol::Feature(index.clone()));
-                            }
-                            return true;
-                        }
-                        _ => return false,
-                    }
-                }
-                return false;
-            })
-            .collect::<Vec<Symbol>>();
-
-        let mut result = self
-            .module
+        self.module
             .file(g.instance)
             .direct_children(g.sym)
-            .filter(|s| !card_expressions.contains(s))
             .map(|i| self.pseudo_bool(g.instance.sym(i)))
-            .collect::<Vec<Expr>>();
-
-        result.append(
-            cardinalitys
-                .values()
-                .into_iter()
-                .map(|(card, syms)| {
-                    let list = syms
-                        .into_iter()
-                        .map(|i| self.pseudo_bool(g.instance.sym(i.clone())))
-                        .collect::<Vec<Expr>>();
-                    match card {
-                        Cardinality::Range(min, max) => Expr::And(vec![
-                            Expr::AtLeast(min.clone(), list.clone()),
-                            Expr::AtMost(max.clone(), list),
-                        ]),
-                        _ => panic!(),
-                    }
-                })
-                .collect::<Vec<Expr>>()
-                .as_mut(),
-        );
-
-        return result;
+            .collect()
+    }
+    /// sym selected in each active instance of a cardinality feature
+    fn instance_clause(&self, feature: ModuleSymbol, sym: Symbol) -> Vec<Expr> {
+        self.module
+            .instances_of(feature)
+            .map(|i| self.pseudo_bool(i.sym(sym)))
+            .collect()
     }
     fn min_assert(&mut self, min: usize, p_bind: &Expr, g: ModuleSymbol) {
         let clause = self.clause(g);
@@ -480,6 +432,39 @@ impl<'a> SMTBuilder<'a> {
             Expr::Implies(vec![p_bind.clone(), Expr::AtMost(max, clause)]),
         ));
     }
+    /// Semantics of group g under parent p for mode
+    fn group_asserts(&mut self, mode: GroupMode, p_bind: &Expr, p: ModuleSymbol, g: ModuleSymbol) {
+        match mode {
+            GroupMode::Or => {
+                let clause = self.clause(g);
+                self.assert.push(Assert(
+                    Some(AssertInfo(g, AssertName::Group)),
+                    Expr::Implies(vec![p_bind.clone(), Expr::Or(clause)]),
+                ));
+            }
+            GroupMode::Alternative => {
+                self.min_assert(1, p_bind, g);
+                self.max_assert(1, p_bind, g);
+            }
+            GroupMode::Mandatory => {
+                for expr in self.clause(g) {
+                    info!("expr {:?}", expr);
+                    self.assert.push(Assert(
+                        Some(AssertInfo(p, AssertName::GroupMember)),
+                        Expr::Equal(vec![expr, p_bind.clone()]),
+                    ))
+                }
+            }
+            GroupMode::Optional | GroupMode::Cardinality(Cardinality::Fixed) => {}
+            GroupMode::Cardinality(Cardinality::Range(min, max)) => {
+                self.min_assert(min, p_bind, g);
+                self.max_assert(max, p_bind, g);
+            }
+            GroupMode::Cardinality(Cardinality::AtLeast(min)) => {
+                self.min_assert(min, p_bind, g);
+            }
+        }
+    }
     fn push_var(&mut self, ms: ModuleSymbol) -> Expr {
         self.sym2var.insert(ms);
         Expr::Var(self.sym2var.len() - 1)
@@ -495,78 +480,100 @@ impl Into<Expr> for ConfigValue {
         }
     }
 }
+/// Replaces the mode of a group in every instance of its file
+pub struct GroupChange {
+    pub file: FileID,
+    pub group: Symbol,
+    pub mode: GroupMode,
+}
+/// A SMT Module without the changed group and the old and new semantics of that group
+pub struct GroupChangeModule {
+    pub module: SMTModule,
+    pub old: Expr,
+    pub new: Expr,
+}
 /// Converts a UVL Module into a SMT Module
 pub fn uvl2smt(module: &Module, config: &HashMap<ModuleSymbol, ConfigValue>) -> SMTModule {
+    encode(module, config, None).0
+}
+/// Converts a UVL Module into a SMT Module where the group semantics of change are kept apart
+pub fn uvl2smt_group_change(module: &Module, change: &GroupChange) -> GroupChangeModule {
+    let (module, old, new) = encode(module, &HashMap::new(), Some(change));
+    let conjunction = |e: Vec<Expr>| {
+        if e.is_empty() {
+            Expr::Bool(true)
+        } else {
+            Expr::And(e)
+        }
+    };
+    GroupChangeModule {
+        module,
+        old: conjunction(old),
+        new: conjunction(new),
+    }
+}
+fn encode(
+    module: &Module,
+    config: &HashMap<ModuleSymbol, ConfigValue>,
+    change: Option<&GroupChange>,
+) -> (SMTModule, Vec<Expr>, Vec<Expr>) {
     assert!(module.ok);
+    let mut old = Vec::new();
+    let mut new = Vec::new();
     let mut builder = SMTBuilder {
         module,
         sym2var: IndexSet::new(),
         assert: Vec::new(),
     };
     //encode features
-    for (m, file) in module.instances() {
-        for f in file.all_features() {
+    for (m, _) in module.instances() {
+        for f in module.features(m) {
             builder.push_var(m.sym(f));
         }
-
-        for sym_feature in file.all_features() {
-            if let Symbol::Feature(id) = sym_feature {
-                let feature = file.get_feature(id).unwrap().clone();
-                if let Cardinality::Range(min, _) =
-                    feature.cardinality.unwrap_or_else(|| Cardinality::Fixed)
-                {
-                    // Make AtLeast assertion for cardinality feature
-                    if feature.first_cardinality_child {
-                        let mut list = vec![];
-                        let parent = file.parent(sym_feature, false).unwrap();
-
-                        for child in file.direct_children(parent) {
-                            if let Symbol::Feature(sibling_id) = child {
-                                let sibling = file.get_feature(sibling_id);
-                                if sibling.unwrap().name.name.as_str() == feature.name.name.as_str()
-                                {
-                                    list.push(builder.var(m.sym(Symbol::Feature(sibling_id))));
-                                }
-                            }
-                        }
-
-                        match file.parent(Symbol::Feature(id), false) {
-                            Some(Symbol::Group(_)) => {
-                                // if parent is group, a cardinality can be atleast 0 or min, since it can not be selected at all.
-                                builder.assert.push(Assert(
-                                    Some(AssertInfo(m.sym(sym_feature), AssertName::GroupMin)),
-                                    Expr::Or(vec![
-                                        Expr::AtLeast(min, list.clone()),
-                                        Expr::AtMost(0, list),
-                                    ]),
-                                ));
-                            }
-                            _ => {
-                                builder.assert.push(Assert(
-                                    Some(AssertInfo(m.sym(sym_feature), AssertName::GroupMin)),
-                                    Expr::AtLeast(min, list),
-                                ));
-                            }
-                        }
-                    }
-                }
+    }
+    //encode feature cardinalities, features inside a template are selected if they are
+    //selected in any instance
+    for (m, file) in module.instances() {
+        for f in module.features(m) {
+            let ms = m.sym(f);
+            let Some(&outer) = module.template_path(ms).first() else {
+                continue;
+            };
+            let instances = builder.instance_clause(m.sym(outer), f);
+            let any = if instances.is_empty() {
+                Expr::Bool(false)
+            } else {
+                Expr::Or(instances.clone())
+            };
+            builder.assert.push(Assert(
+                None,
+                Expr::Equal(vec![builder.pseudo_bool(ms), any]),
+            ));
+            if outer != f || instances.is_empty() {
+                continue;
+            }
+            let (min, max) = file.cardinality(f).unwrap();
+            builder.assert.push(Assert(
+                Some(AssertInfo(ms, AssertName::GroupMin)),
+                Expr::Implies(vec![
+                    builder.pseudo_bool(ms),
+                    Expr::AtLeast(min, instances.clone()),
+                ]),
+            ));
+            if let Some(max) = max {
+                builder.assert.push(Assert(
+                    Some(AssertInfo(ms, AssertName::GroupMax)),
+                    Expr::AtMost(max, instances),
+                ));
             }
         }
     }
-    //set config features
-    for (&ms, val) in config
-        .iter()
-        .filter(|i| matches!(i.0.sym, Symbol::Feature(..)))
-    {
-        let var = builder.var(ms);
-        builder.assert.push(Assert(
-            Some(AssertInfo(ms, AssertName::Config)),
-            Expr::Equal(vec![var, val.clone().into()]),
-        ));
-    }
+    //asserts depending on the configuration are placed last, so an incremental solver
+    //session only has to replace the tail when a configuration changes
+    let mut config_asserts = Vec::new();
     //encode attributes
     for (m, file) in module.instances() {
-        for f in file.all_features() {
+        for f in module.features(m).filter(|f| module.owns(m, *f)) {
             file.visit_named_children(f, true, |a, _| {
                 if !matches!(a, Symbol::Attribute(..)) {
                     return true;
@@ -596,20 +603,25 @@ pub fn uvl2smt(module: &Module, config: &HashMap<ModuleSymbol, ConfigValue>) ->
                 };
                 let attrib_var = builder.push_var(ms);
                 let feat_var = builder.pseudo_bool(m.sym(f));
-                builder.assert.push(Assert(
-                    Some(AssertInfo(ms, n)),
+                let assert = Assert(
+                    Some(AssertInfo(ms, n.clone())),
                     Expr::Equal(vec![
                         Expr::Ite(feat_var.into(), val.into(), zero.into()),
                         attrib_var,
                     ]),
-                ));
+                );
+                if matches!(n, AssertName::Config) {
+                    config_asserts.push(assert);
+                } else {
+                    builder.assert.push(assert);
+                }
                 true
             });
         }
     }
     //encode groups
     for (m, file) in module.instances() {
-        for p in file.all_features() {
+        for p in module.features(m).filter(|f| module.owns(m, *f)) {
             for g in file
                 .direct_children(p)
                 .filter(|sym| matches!(sym, Symbol::Group(..)))
@@ -625,32 +637,12 @@ pub fn uvl2smt(module: &Module, config: &HashMap<ModuleSymbol, ConfigValue>) ->
                         Expr::Implies(vec![c_bind.into(), p_bind.clone().into()]),
                     ));
                 }
-                match file.group_mode(g).unwrap() {
-                    GroupMode::Or => {
-                        let clause = builder.clause(m.sym(g));
-                        builder.assert.push(Assert(
-                            Some(AssertInfo(m.sym(g), AssertName::Group)),
-                            Expr::Implies(vec![p_bind.into(), Expr::Or(clause).into()]),
-                        ));
-                    }
-                    GroupMode::Alternative => {
-                        builder.min_assert(1, &p_bind, m.sym(g));
-                        builder.max_assert(1, &p_bind, m.sym(g));
-                    }
-                    GroupMode::Mandatory => {
-                        builder.clause(m.sym(g)).into_iter().for_each(|expr| {
-                            info!("expr {:?}", expr);
-                            builder.assert.push(Assert(
-                                Some(AssertInfo(m.sym(p), AssertName::GroupMember)),
-                                Expr::Equal(vec![expr.into(), p_bind.clone().into()]),
-                            ))
-                        });
-                    }
-                    GroupMode::Optional | GroupMode::Cardinality(Cardinality::Fixed) => {}
-                    GroupMode::Cardinality(Cardinality::Range(min, max)) => {
-                        builder.min_assert(min, &p_bind, m.sym(g));
-                        builder.max_assert(max, &p_bind, m.sym(g));
-                    }
+                let start = builder.assert.len();
+                builder.group_asserts(file.group_mode(g).unwrap(), &p_bind, m.sym(p), m.sym(g));
+                if let Some(change) = change.filter(|c| c.file == file.id && c.group == g) {
+                    old.extend(builder.assert.drain(start..).map(|Assert(_, e)| e));
+                    builder.group_asserts(change.mode.clone(), &p_bind, m.sym(p), m.sym(g));
+                    new.extend(builder.assert.drain(start..).map(|Assert(_, e)| e));
                 }
             }
         }
@@ -668,19 +660,40 @@ pub fn uvl2smt(module: &Module, config: &HashMap<ModuleSymbol, ConfigValue>) ->
     }
     //encode constraints
     for (m, file) in module.instances() {
-        for c in file.all_constraints() {
-            let expr = translate_constraint(file.constraint(c).unwrap(), m, &mut builder, file);
+        for c in file
+            .all_constraints()
+            .filter(|c| module.owns(m, file.scope(*c)))
+        {
+            let expr = translate_constraint_decl(c, m, &mut builder, file);
             builder.assert.push(Assert(
                 Some(AssertInfo(m.sym(c), AssertName::Constraint)),
                 expr,
             ));
         }
     }
-
-    SMTModule {
-        variables: builder.sym2var,
-        asserts: builder.assert,
+    //set config features, sorted to keep the source stable between runs
+    let mut config_features: Vec<_> = config
+        .iter()
+        .filter(|i| matches!(i.0.sym, Symbol::Feature(..)))
+        .collect();
+    config_features.sort_by_key(|(ms, _)| (ms.instance.0, ms.sym));
+    for (&ms, val) in config_features {
+        let var = builder.var(ms);
+        builder.assert.push(Assert(
+            Some(AssertInfo(ms, AssertName::Config)),
+            Expr::Equal(vec![var, val.clone().into()]),
+        ));
     }
+    builder.assert.extend(config_asserts);
+
+    (
+        SMTModule {
+            variables: builder.sym2var,
+            asserts: builder.assert,
+        },
+        old,
+        new,
+    )
 }
 
 /// create an SMTModule, but the asserts are only constraints
@@ -692,14 +705,14 @@ pub fn uvl2smt_constraints(module: &Module) -> SMTModule {
         assert: Vec::new(),
     };
     //encode features
-    for (m, file) in module.instances() {
-        for f in file.all_features() {
+    for (m, _) in module.instances() {
+        for f in module.features(m) {
             builder.push_var(m.sym(f));
         }
     }
     //encode attributes
     for (m, file) in module.instances() {
-        for f in file.all_features() {
+        for f in module.features(m).filter(|f| module.owns(m, *f)) {
             file.visit_named_children(f, true, |a, _| {
                 if !matches!(a, Symbol::Attribute(..)) {
                     return true;
@@ -712,8 +725,11 @@ pub fn uvl2smt_constraints(module: &Module) -> SMTModule {
     }
     //encode constraints
     for (m, file) in module.instances() {
-        for c in file.all_constraints() {
-            let expr = translate_constraint(file.constraint(c).unwrap(), m, &mut builder, file);
+        for c in file
+            .all_constraints()
+            .filter(|c| module.owns(m, file.scope(*c)))
+        {
+            let expr = translate_constraint_decl(c, m, &mut builder, file);
             builder.assert.push(Assert(
                 Some(AssertInfo(m.sym(c), AssertName::Constraint)),
                 expr,
@@ -726,6 +742,20 @@ pub fn uvl2smt_constraints(module: &Module) -> SMTModule {
     }
 }
 
+/// Translates a constraint symbol, feature local constraints only need to hold when the
+/// owning feature is selected
+fn translate_constraint_decl(
+    c: Symbol,
+    m: InstanceID,
+    builder: &mut SMTBuilder,
+    ast: &AstDocument,
+) -> Expr {
+    let expr = translate_constraint(ast.constraint(c).unwrap(), m, builder, ast);
+    match ast.scope(c) {
+        owner @ Symbol::Feature(..) => Expr::Implies(vec![builder.pseudo_bool(m.sym(owner)), expr]),
+        _ => expr,
+    }
+}
 /// Translates the constraints into Expressions which can be converted to Z3 Statements
 fn translate_constraint(
     decl: &ast::ConstraintDecl,
@@ -734,16 +764,7 @@ fn translate_constraint(
     ast: &AstDocument,
 ) -> Expr {
     match &decl.content {
-        ast::Constraint::Ref(sym) => {
-            let module_symbol: ModuleSymbol = builder.module.resolve_value(m.sym(*sym));
-            let resolved_ast = builder.module.file(module_symbol.instance);
-            let all_of = resolved_ast
-                .find_all_of(resolved_ast.name(module_symbol.sym).unwrap())
-                .into_iter()
-                .map(|feature| builder.var(module_symbol.instance.sym(feature)))
-                .collect::<Vec<Expr>>();
-            return Expr::Or(all_of);
-        }
+        ast::Constraint::Ref(sym) => builder.var(m.sym(*sym)),
         ast::Constraint::Not(lhs) => stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
             Expr::Not(translate_constraint(lhs, m, builder, ast).into())
         }),
@@ -766,10 +787,11 @@ fn translate_constraint(
             let (rhs, rty) = translate_expr(rhs, m, builder);
             debug_assert!(rty == lty);
             if lty == Type::String {
+                //lexicographic order, a > b is encoded as b < a
                 match op {
                     ast::EquationOP::Equal => Expr::Equal(vec![lhs, rhs]),
-                    ast::EquationOP::Greater => Expr::StrLess(vec![lhs, rhs]),
-                    ast::EquationOP::Smaller => Expr::Not(Expr::StrLessEq(vec![lhs, rhs]).into()),
+                    ast::EquationOP::Greater => Expr::StrLess(vec![rhs, lhs]),
+                    ast::EquationOP::Smaller => Expr::StrLess(vec![lhs, rhs]),
                 }
             } else {
                 match op {
@@ -801,7 +823,7 @@ fn translate_expr(decl: &ast::ExprDecl, m: InstanceID, builder: &mut SMTBuilder)
             debug_assert!(rty == lty);
             if rty == Type::String {
                 debug_assert!(*op == NumericOP::Add);
-                (Expr::StrConcat(rhs.into(), lhs.into()), Type::String)
+                (Expr::StrConcat(lhs.into(), rhs.into()), Type::String)
             } else {
                 let expr = match op {
                     ast::NumericOP::Add => Expr::Add(vec![lhs, rhs]),
@@ -817,18 +839,21 @@ fn translate_expr(decl: &ast::ExprDecl, m: InstanceID, builder: &mut SMTBuilder)
             let mut count_features = Vec::new();
             let tgt = context
                 .map(|sym| builder.module.resolve_value(m.sym(sym)))
-                .unwrap_or(m.sym(Symbol::Root));
+                .unwrap_or(builder.module.file_instance(m).sym(Symbol::Root));
             let tgt_file = builder.module.file(tgt.instance);
             tgt_file.visit_attributes(tgt.sym, |feature, attrib, prefix| {
                 if prefix == query.names.as_slice()
                     && tgt_file.type_of(attrib).unwrap() == Type::Real
                 {
-                    count_features.push(Expr::Ite(
-                        builder.pseudo_bool(tgt.instance.sym(feature)).into(),
-                        Expr::Real(1.0).into(),
-                        Expr::Real(0.0).into(),
-                    ));
-                    all_attributes.push(builder.var(tgt.instance.sym(attrib)));
+                    //attributes inside a template are aggregated over all instances
+                    for attrib in builder.module.instantiations(tgt.instance.sym(attrib)) {
+                        count_features.push(Expr::Ite(
+                            builder.pseudo_bool(attrib.instance.sym(feature)).into(),
+                            Expr::Real(1.0).into(),
+                            Expr::Real(0.0).into(),
+                        ));
+                        all_attributes.push(builder.var(attrib));
+                    }
                 }
             });
             if all_attributes.is_empty() {
@@ -854,3 +879,172 @@ fn translate_expr(decl: &ast::ExprDecl, m: InstanceID, builder: &mut SMTBuilder)
         ),
     }
 }
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ropey::Rope;
+    use std::sync::Arc;
+    use tower_lsp::lsp_types::Url;
+    pub(crate) fn with_module<T>(source: &str, f: impl FnOnce(&Module, FileID) -> T) -> T {
+        let rope = Rope::from_str(source);
+        let tree = parse::parse(&rope, None);
+        let uri = Url::parse("file:///test.uvl").unwrap();
+        let ast = AstDocument::new(rope, tree, uri, Instant::now());
+        let id = ast.id;
+        let files: AstFiles = [(id, Arc::new(ast))].into_iter().collect();
+        let configs = ConfigFiles::new();
+        let mut err = ErrorsAcc {
+            errors: HashMap::new(),
+            files: &files,
+            configs: &configs,
+        };
+        let root = RootGraph::new(
+            &files,
+            &configs,
+            0,
+            &ImportRoots::default(),
+            &Cache::default(),
+            &mut err,
+            &mut HashMap::new(),
+        );
+        f(&root.cache().modules[&id], id)
+    }
+    fn smt_source(source: &str) -> String {
+        with_module(source, |module, _| {
+            uvl2smt(module, &HashMap::new()).to_source(module)
+        })
+    }
+    fn group_change(source: &str, mode: GroupMode) -> (String, String, String) {
+        with_module(source, |module, id| {
+            let group = module
+                .file(InstanceID(0))
+                .direct_children(Symbol::Root)
+                .flat_map(|f| module.file(InstanceID(0)).direct_children(f))
+                .next()
+                .unwrap();
+            let change = GroupChange {
+                file: id,
+                group,
+                mode,
+            };
+            let smt = uvl2smt_group_change(module, &change);
+            (
+                smt.module.to_source(module),
+                smt.module.assert_to_source(0, &None, &smt.old, false),
+                smt.module.assert_to_source(0, &None, &smt.new, false),
+            )
+        })
+    }
+    #[test]
+    fn test_group_change() {
+        let source = "features\n    Root\n        or\n            a\n            b\n";
+        let (module, old, new) = group_change(source, GroupMode::Alternative);
+        assert!(!module.contains("(or"));
+        assert!(old.contains("(or v1 v2)"));
+        assert!(new.contains("((_ at-most 1) v1 v2)"));
+        let (_, _, new) = group_change(source, GroupMode::Cardinality(Cardinality::Range(2, 2)));
+        assert!(new.contains("((_ at-least 2) v1 v2)"));
+        assert!(new.contains("((_ at-most 2) v1 v2)"));
+        let source = "features\n    Root\n        [1..*]\n            a\n            b\n";
+        let (module, old, new) = group_change(source, GroupMode::Mandatory);
+        assert!(!module.contains("at-least"));
+        assert!(old.contains("((_ at-least 1) v1 v2)"));
+        assert!(!new.contains("at-least"));
+    }
+    #[test]
+    fn test_assert_ids() {
+        let ids = |source: &str| {
+            with_module(source, |module, _| {
+                let smt = uvl2smt(module, &HashMap::new());
+                let ids = smt.assert_ids();
+                //every name maps back to its own assert
+                for (Assert(info, _), id) in smt.asserts.iter().zip(ids.iter()) {
+                    let core: Vec<_> = smt.parse_unsat_core(&format!("(a{id})")).collect();
+                    assert_eq!(format!("{core:?}"), format!("{:?}", Vec::from_iter(info)));
+                }
+                ids
+            })
+        };
+        let old = ids("features\n    Root\n        optional\n            a\n            b\nconstraints\n    a => b\n");
+        let new = ids("features\n    Root\n        optional\n            a\n            b\nconstraints\n    b\n    a => b\n    a => b\n");
+        //inserting asserts keeps the names of the others, duplicates get their own name
+        assert!(old.iter().all(|id| new.contains(id)));
+        assert_eq!(new.len(), old.len() + 2);
+        assert_eq!(
+            new.iter().collect::<hashbrown::HashSet<_>>().len(),
+            new.len()
+        );
+    }
+    #[test]
+    fn test_attribute_constraint_owner() {
+        let source = smt_source(
+            r#"features
+    Root
+        optional
+            A {constraint B, meta {constraints [!C]}}
+            B
+            C
+constraints
+    B => C
+"#,
+        );
+        //feature local constraints only hold if their feature is selected
+        assert!(source.contains("(=> v1 v2)"), "{source}");
+        assert!(source.contains("(=> v1(not v3))"), "{source}");
+        //global constraints are not conditioned
+        assert!(source.contains("(assert(! (=> v2 v3)"), "{source}");
+    }
+    #[test]
+    fn test_string_constraints() {
+        let source = smt_source(
+            r#"features
+    Root
+        optional
+            String a
+            String b
+constraints
+    a > b
+    a < b
+    a + 'x' == b
+    !(a == '')
+"#,
+        );
+        assert!(source.contains("(str.< v2 v1)"), "{source}");
+        assert!(source.contains("(str.< v1 v2)"), "{source}");
+        assert!(source.contains("(str.++ v1 \"x\")"), "{source}");
+        assert!(source.contains("(not(= v1 \"\"))"), "{source}");
+    }
+    #[test]
+    fn test_feature_cardinality() {
+        let source = smt_source(
+            r#"features
+    Root
+        optional
+            Item cardinality [2..3]
+                mandatory
+                    Part
+"#,
+        );
+        //two default instances, the template is active if any instance is
+        assert!(source.contains("(assert(= v1(or v3 v5)))"), "{source}");
+        assert!(source.contains("(=> v1((_ at-least 2) v3 v5))"), "{source}");
+        assert!(source.contains("((_ at-most 3) v3 v5)"), "{source}");
+        assert!(source.contains("(assert(= v2(or v4 v6)))"), "{source}");
+    }
+    #[test]
+    fn test_escape_string() {
+        assert_eq!(escape_string("a\"b"), "a\"\"b");
+        assert_eq!(escape_string("a\\b\u{e4}"), "a\\u{5c}b\\u{e4}");
+    }
+    #[test]
+    fn test_string_round_trip() {
+        for val in ["a\"b", "a\\b\u{e4}", "\u{1f600} {x}", ""] {
+            let values = format!("((v0 \"{}\"))", escape_string(val));
+            let parsed = crate::smt::parse::parse_values(&values).unwrap();
+            assert_eq!(
+                parsed,
+                vec![(0, crate::smt::parse::Value::String(val.into()))]
+            );
+        }
+    }
+}
@@ -72,6 +72,7 @@ pub enum SatState {
 /// - Attributes
 /// - File
 /// - Feature
+/// - Instances of a cardinality feature
 /// - Attribute
 /// - Link
 #[derive(Debug, Clone, PartialEq)]
@@ -87,6 +88,13 @@ pub enum UIEntryValue {
         smt_value: Option<ConfigValue>,
         ty: Type,
         unsat: bool,
+        /// index if this is an instance of a cardinality feature
+        instance: Option<usize>,
+    },
+    Instances {
+        name: Ustr,
+        count: usize,
+        max: Option<usize>,
     },
     Attribute {
         name: Ustr,
@@ -198,6 +206,8 @@ pub enum UIAction {
     UpdateSMTInvalid(String, u8),
     Set(ModuleSymbol, u8, ConfigValue),
     Unset(ModuleSymbol, u8),
+    AddInstance(ModuleSymbol, u8),
+    RemoveInstance(InstanceID, u8),
     ShowSym(ModuleSymbol, u8),
     SolverActive,
     Save,
@@ -231,6 +241,8 @@ impl PartialEq for AppProps {
 }
 
 /// goes through the UVL file and stores each entry accordingly in the provided *entries* variable
+///
+/// Cardinality features are stored as list of their instances, each instance is stored recursively
 fn create_file_tree(
     file: &AstDocument,
     module: &Module,
@@ -239,12 +251,14 @@ fn create_file_tree(
     config: &HashMap<ModuleSymbol, ConfigValue>,
     entries: &mut IndexMap<ModuleSymbol, UIEntry>,
 ) {
-    let mut last = Symbol::Root;
+    let root = module.instance_root(instance);
+    let mut last = root;
     let mut vdir = 0;
-    file.visit_children_depth(Symbol::Root, true, |sym, depth| match sym {
+    file.visit_children_depth(root, false, |sym, depth| match sym {
         Symbol::Reference(_) => {
             let depth = depth + base_depth - 1;
             let name = file.path(sym).iter().join(".");
+            let tgt = module.resolve_value(instance.sym(sym));
             //Values will be resolved in the frontend
             entries.insert(
                 instance.sym(sym),
@@ -253,9 +267,9 @@ fn create_file_tree(
                     open: false,
                     value: UIEntryValue::Link {
                         name,
-                        tgt: module.resolve_value(instance.sym(sym)),
+                        tgt,
                         config: None,
-                        ty: Type::Real,
+                        ty: module.type_of(tgt),
                         smt_value: None,
                         unsat: false,
                     },
@@ -263,6 +277,45 @@ fn create_file_tree(
             );
             false
         }
+        Symbol::Feature(_) if file.cardinality(sym).is_some() => {
+            let depth = depth + base_depth - 1;
+            let ms = instance.sym(sym);
+            let name = file.name(sym).unwrap();
+            let (_, max) = file.cardinality(sym).unwrap();
+            entries.insert(
+                ms,
+                UIEntry {
+                    depth,
+                    open: false,
+                    value: UIEntryValue::Instances {
+                        name,
+                        count: module.instance_count(ms),
+                        max,
+                    },
+                },
+            );
+            for (k, i) in module.instances_of(ms).enumerate() {
+                let ms = i.sym(sym);
+                entries.insert(
+                    ms,
+                    UIEntry {
+                        depth: depth + 1,
+                        open: false,
+                        value: UIEntryValue::Feature {
+                            name,
+                            unsat: false,
+                            config: config.get(&ms).cloned(),
+                            smt_value: None,
+                            ty: file.type_of(sym).unwrap(),
+                            instance: Some(k),
+                        },
+                    },
+                );
+                create_file_tree(file, module, depth + 2, i, config, entries);
+            }
+            last = sym;
+            false
+        }
         Symbol::Feature(_) | Symbol::Attribute(_) => {
             let depth = depth + base_depth - 1;
             match file.type_of(sym).unwrap() {
@@ -304,6 +357,7 @@ fn create_file_tree(
                             config,
                             smt_value: None,
                             ty: file.type_of(sym).unwrap(),
+                            instance: None,
                         },
                         Symbol::Attribute(..) => match file.value(sym).unwrap() {
                             Value::Bool(num) => UIEntryValue::Attribute {
@@ -461,8 +515,9 @@ fn transfer_config(source: &mut ConfigSource, new: Arc<Module>) {
         return;
     }
     let ser = source.module.serialize();
+    let mut new = (*new).clone();
     let (new_values, _) = new.resolve_config(&ser, |_, _| {});
-    source.module.module = new;
+    source.module.module = Arc::new(new);
     source.module.values = new_values;
     source.ok = true;
 }
@@ -546,8 +601,10 @@ async fn ui_event_loop(
 
                 let config_module = tx_config.borrow().module.clone();
                 let smt_module = uvl2smt(&config_module.module, &config_module.values);
+                let solver = pipeline.smt_options().borrow().solver.clone();
                 let solver = SmtSolver::new(
                     smt_module.to_source(&config_module),
+                    &solver,
                     &CancellationToken::new(),
                 )
                 .await;
@@ -566,9 +623,14 @@ async fn ui_event_loop(
                                 .await
                                 .unwrap_or(String::from(""));
 
-                            let values_parsed: HashMap<ModuleSymbol, ConfigValue> = smt_module
-                                .parse_values(&values, &config_module.module)
-                                .collect();
+                            let values_parsed: HashMap<ModuleSymbol, ConfigValue> =
+                                match smt_module.parse_values(&values, &config_module.module) {
+                                    Ok(values) => values.into_iter().collect(),
+                                    Err(e) => {
+                                        info!("failed to save configurations: {e}");
+                                        continue;
+                                    }
+                                };
 
                             // Store solution in file
                             tokio::task::spawn_blocking(move || {
@@ -670,6 +732,42 @@ async fn ui_event_loop(
                     config.cancel = CancellationToken::new();
                 });
             }
+            UIAction::AddInstance(feature, tag) => {
+                if tag != ctag {
+                    continue;
+                }
+                let full = {
+                    let config = tx_config.borrow();
+                    let module = &config.module.module;
+                    let max = module
+                        .file(feature.instance)
+                        .cardinality(feature.sym)
+                        .and_then(|(_, max)| max);
+                    max.map_or(false, |max| module.instance_count(feature) >= max)
+                };
+                if full {
+                    continue;
+                }
+                tx_config.send_modify(|config| {
+                    let mut module = (*config.module.module).clone();
+                    module.set_instance_count(feature, module.instance_count(feature) + 1);
+                    config.module.module = Arc::new(module);
+                    config.cancel.cancel();
+                    config.cancel = CancellationToken::new();
+                });
+                rebuild_config(ui_state, ui_config, &tx_config.borrow());
+            }
+            UIAction::RemoveInstance(instance, tag) => {
+                if tag != ctag {
+                    continue;
+                }
+                tx_config.send_modify(|config| {
+                    config.module = config.module.remove_instance(instance);
+                    config.cancel.cancel();
+                    config.cancel = CancellationToken::new();
+                });
+                rebuild_config(ui_state, ui_config, &tx_config.borrow());
+            }
             UIAction::Unset(sym, tag) => {
                 if tag != ctag {
                     continue;
@@ -726,10 +824,11 @@ async fn ui_event_loop(
                             state.solver_active = false
                         });
                         for (k, v) in values {
-                            let entry = &mut entries[&k];
-
-                            entry.unsat(false);
-                            entry.update_smt(Some(v));
+                            //template values are not displayed
+                            if let Some(entry) = entries.get_mut(&k) {
+                                entry.unsat(false);
+                                entry.update_smt(Some(v));
+                            }
                         }
                     }
                     smt::SMTModel::UNSAT { reasons } => {
@@ -747,7 +846,9 @@ async fn ui_event_loop(
                                     k,
                                     smt::AssertName::Config | smt::AssertName::Attribute,
                                 ) => {
-                                    entries[&k].unsat(true);
+                                    if let Some(entry) = entries.get_mut(&k) {
+                                        entry.unsat(true);
+                                    }
                                 }
                                 _ => {}
                             }
@@ -837,6 +938,7 @@ pub async fn ui_main(
         tx_sync.clone(),
         pipeline.inlay_state().clone(),
         ide::inlays::InlaySource::Web(id),
+        pipeline.smt_options(),
     ));
     //Sync module with the lsp state
     spawn(ui_sync(pipeline.clone(), tx_sync, root));
@@ -190,7 +190,7 @@ fn ConfigInput<'a>(cx: Scope<'a, ConfigInputProps<'a>>) -> Element {
                     value:"{x}",
                     oninput:move |e|{
 
-                        tx.send(UIAction::Set(*sym,*tag,ConfigValue::String(e.value.replace('"',""))));
+                        tx.send(UIAction::Set(*sym,*tag,ConfigValue::String(e.value.clone())));
                         cx.needs_update();
 
                     }
@@ -234,6 +234,7 @@ fn ConfigInput<'a>(cx: Scope<'a, ConfigInputProps<'a>>) -> Element {
 
 #[inline_props]
 fn Value(cx: Scope, value: UIEntryValue, sym: ModuleSymbol, tag: u8) -> Element {
+    let tx = use_coroutine_handle::<UIAction>(cx).unwrap();
     match value {
         UIEntryValue::Attributes(..) => None,
         UIEntryValue::File { .. } => None,
@@ -242,17 +243,50 @@ fn Value(cx: Scope, value: UIEntryValue, sym: ModuleSymbol, tag: u8) -> Element
             smt_value,
             ty,
             unsat,
+            instance,
             ..
         } => cx.render(rsx! {
-            ConfigInput{
-                config:config.as_ref(),
-                base:smt_value.as_ref(),
-                unsat:*unsat,
-                sym:*sym,
-                ty:*ty,
-                tag:*tag,
+            div{
+                class:"config",
+                ConfigInput{
+                    config:config.as_ref(),
+                    base:smt_value.as_ref(),
+                    unsat:*unsat,
+                    sym:*sym,
+                    ty:*ty,
+                    tag:*tag,
+                }
+                if instance.is_some(){
+                    rsx!{button{
+                        class:"delete-btn",
+                        title:"Remove instance",
+                        onclick:move |_|{
+                            tx.send(UIAction::RemoveInstance(sym.instance,*tag));
+                        },
+                        Icon{icon:Icon::CircleCross,class:"btn-icon"}
+                    }}
+                }
             }
         }),
+        UIEntryValue::Instances { count, max, .. } => {
+            let full = max.map(|max| *count >= max).unwrap_or(false);
+            cx.render(rsx! {
+                div{
+                    class:"config",
+                    "{count} instances"
+                    if !full{
+                        rsx!{button{
+                            class:"delete-btn",
+                            title:"Add instance",
+                            onclick:move |_|{
+                                tx.send(UIAction::AddInstance(*sym,*tag));
+                            },
+                            Icon{icon:Icon::CirclePlus,class:"btn-icon"}
+                        }}
+                    }
+                }
+            })
+        }
 
         UIEntryValue::Link {
             config,
@@ -291,7 +325,7 @@ fn icon(value: &UIEntryValue) -> Icon {
     match value {
         UIEntryValue::Attributes(..) => Icon::Attributes,
         UIEntryValue::File { .. } => Icon::File,
-        UIEntryValue::Feature { .. } => Icon::Feature,
+        UIEntryValue::Feature { .. } | UIEntryValue::Instances { .. } => Icon::Feature,
         UIEntryValue::Attribute { .. } => Icon::Attribute,
         UIEntryValue::Link { .. } => Icon::Link,
     }
@@ -310,8 +344,14 @@ fn FileEntry(cx: Scope, node: UIEntry, leaf: bool, sym: ModuleSymbol, tag: u8) -
             }
         }
         UIEntryValue::Link { name, .. } => name.clone(),
+        UIEntryValue::Feature {
+            name,
+            instance: Some(k),
+            ..
+        } => format!("{}[{}]", name, k),
         UIEntryValue::Attribute { name, .. }
         | UIEntryValue::Feature { name, .. }
+        | UIEntryValue::Instances { name, .. }
         | UIEntryValue::Attributes(name) => format!("{}", name),
     };
 
@@ -424,19 +464,25 @@ where
                 .get_index(i + 1)
                 .map(|(_, vn)| vn.depth <= v.depth)
                 .unwrap_or(true);
-            //Resolve link
-            if let UIEntryValue::Link { name, tgt, .. } = &v.value {
-                let UIEntryValue::Feature {
+            //Resolve link, links into templates are displayed without value
+            let link = match &v.value {
+                UIEntryValue::Link { name, tgt, .. } => {
+                    state.entries.get(tgt).map(|e| (name, tgt, &e.value))
+                }
+                _ => None,
+            };
+            if let Some((
+                name,
+                tgt,
+                UIEntryValue::Feature {
                     config,
                     smt_value,
                     ty,
                     unsat,
                     ..
-                } = &state.entries[tgt].value
-                else {
-                    panic!()
-                };
-
+                },
+            )) = link
+            {
                 rsx! {
                     FileEntry{node:UIEntry{
                         open:v.open,